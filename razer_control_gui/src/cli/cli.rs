@@ -1,14 +1,21 @@
 #[path = "../comms.rs"]
 mod comms;
 use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::sync::OnceLock;
 
 #[derive(Parser)]
 #[command(version="0.5.0", about="razer laptop configuration for linux", name="razer-cli")]
 struct Cli {
+    /// Device to apply the command to, as listed by `read devices`
+    #[arg(long, global = true, default_value_t = 0)]
+    device: usize,
     #[command(subcommand)]
     args: Args,
 }
 
+/// Device index given with --device, 0 sends the commands unchanged
+static TARGET_DEVICE: OnceLock<usize> = OnceLock::new();
+
 #[derive(Subcommand)]
 enum Args {
     /// Read the current configuration of the device for some attribute
@@ -31,6 +38,47 @@ enum Args {
         #[command(subcommand)]
         effect: Effect,
     },
+    /// Manage effect profiles
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Simulate the idle or active watch firing, for testing the light off/restore
+    Trigger {
+        event: TriggerEvent,
+    },
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum TriggerEvent {
+    Idle,
+    Active,
+}
+
+#[derive(Subcommand)]
+enum ProfileAction {
+    /// Load whichever of the two profiles is not currently active
+    Toggle(ToggleProfilesParams),
+    /// Save the current effects as a profile
+    Save {
+        /// profile name (letters, digits, '-' and '_')
+        name: String,
+    },
+    /// Replace the current effects with a saved profile
+    Load {
+        /// profile name
+        name: String,
+    },
+    /// List the saved profiles
+    List,
+}
+
+#[derive(Parser)]
+struct ToggleProfilesParams {
+    /// first profile, applied when neither is active
+    a: String,
+    /// second profile
+    b: String,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -45,20 +93,83 @@ impl OnOff {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum UpDown {
+    Up,
+    Down,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum FanMode {
+    /// the daemon sets the fan from the temperature
+    Daemon,
+    /// only manual fan speeds
+    Manual,
+    /// the laptop firmware
+    Auto,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum WaveDirection {
+    Right,
+    Left,
+}
+
 #[derive(Subcommand)]
 enum ReadAttr {
+    /// List the detected devices
+    Devices,
     /// Read the current fan speed
     Fan(AcStateParam),
+    /// Read the manual fan speed range of the laptop
+    FanRange(AcStateParam),
     /// Read the current power mode
     Power(AcStateParam),
+    /// Read the valid CPU and GPU boost levels of the laptop
+    BoostRange(AcStateParam),
     /// Read the current brightness
     Brightness(AcStateParam),
+    /// Read the idle timeout
+    Idle(AcStateParam),
     /// Read the current logo mode
     Logo(AcStateParam),
     /// Read the current sync mode
     Sync,
     /// Read the current bho mode
     Bho,
+    /// Read the battery percentage charging stops at
+    ChargeLimit,
+    /// Read what the daemon restored at startup
+    Startup,
+    /// Read the niceness of the daemon worker threads
+    Priority,
+    /// Read the thermal headroom (100 = cool, 0 = critical)
+    Headroom,
+    /// Read when and by which process the current effect was set
+    EffectSource,
+    /// Read the state of the temperature based fan control
+    FanControl,
+    /// Read who controls the fan speed
+    FanMode,
+    /// Read the frames per second of keyboard animations
+    AnimationRate,
+    /// Read the valid parameter ranges of the standard effects
+    EffectRanges,
+    /// Read whether the keyboard is accepting writes
+    Hardware,
+    /// Read the CPU temperature
+    Temp,
+    /// Read the battery percentage and whether the laptop is plugged in
+    Battery,
+    /// List the effect names the daemon supports
+    Effects,
+    /// List the layers of the effect stack
+    Layers,
+    /// Describe the parameters of a custom effect
+    EffectParams {
+        /// Effect name, as listed by `read effects`
+        name: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -69,12 +180,68 @@ enum WriteAttr {
     Power(PowerParams),
     /// Set the brightness of the keyboard
     Brightness(BrightnessParams),
+    /// Change the brightness of the keyboard relative to the current value
+    AdjustBrightness(AdjustBrightnessParams),
+    /// Step the brightness of the keyboard up or down by the configured amount
+    StepBrightness(StepBrightnessParams),
     /// Set the logo mode
     Logo(LogoParams),
     /// Set sync
     Sync(SyncParams),
     /// Set battery health optimization
     Bho(BhoParams),
+    /// Set the niceness of the daemon worker threads
+    Priority(PriorityParams),
+    /// Show the active power mode as a keyboard colour
+    PowerColor(PowerColorParams),
+    /// Limit the fan speed set by the temperature monitor
+    FanCeiling(FanCeilingParams),
+    /// Set how long the keyboard stays lit without user activity
+    IdleTimeout(IdleTimeoutParams),
+    /// Set the power mode and fan speed from a preset
+    Preset(PresetParams),
+    /// Set the battery percentage charging stops at
+    ChargeLimit {
+        /// percent (50-100), 100 charges fully
+        percent: u8,
+    },
+    /// Turn the keyboard backlight off, or back on with its previous effect and brightness
+    KeyboardPower {
+        state: OnOff,
+    },
+    /// Set who controls the fan speed
+    FanMode {
+        mode: FanMode,
+    },
+    /// Set the frames per second of keyboard animations
+    AnimationRate {
+        /// frames per second (1-60), lower saves power
+        fps: u32,
+    },
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Preset {
+    Silent,
+    Balanced,
+    Performance,
+    Max,
+}
+
+#[derive(Parser)]
+struct PresetParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// max is gaming mode with the fan at full speed
+    preset: Preset,
+}
+
+#[derive(Parser)]
+struct IdleTimeoutParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// timeout in milliseconds (at least 5000), 0 to use the timeout in minutes
+    timeout_ms: u64,
 }
 
 #[derive(Parser)]
@@ -105,12 +272,57 @@ struct BrightnessParams {
     brightness: i32,
 }
 
+#[derive(Parser)]
+struct StepBrightnessParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// up or down
+    direction: UpDown,
+}
+
+#[derive(Parser)]
+struct AdjustBrightnessParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// change in percent, e.g. 10 or -10
+    #[arg(allow_negative_numbers = true)]
+    delta: i32,
+}
+
 #[derive(Parser)]
 struct LogoParams {
     /// battery/plugged in
     ac_state: AcState,
-    /// logo mode (0, 1 or 2)
+    /// logo mode (0 = off, 1 = on, 2 = breathing)
     logo_state: i32,
+    /// logo brightness in percent (0-100)
+    #[arg(long)]
+    brightness: Option<u8>,
+}
+
+#[derive(Parser)]
+struct PriorityParams {
+    /// niceness (0-19), higher values yield more CPU to other processes
+    level: i32,
+}
+
+#[derive(Parser)]
+struct FanCeilingParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// maximum fan speed in RPM, 0 to remove the limit
+    max_rpm: i32,
+    /// keep the limit even at critical temperatures
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(Parser)]
+struct PowerColorParams {
+    state: OnOff,
+    /// only light the top row instead of the whole keyboard
+    #[arg(long)]
+    overlay: bool,
 }
 
 #[derive(Parser)]
@@ -212,6 +424,97 @@ enum Effect {
     StaticGradient(StaticGradientParams),
     WaveGradient(WaveGradientParams),
     BreathingSingle(BreathingSingleParams),
+    /// Static colour taken from the desktop wallpaper
+    Wallpaper(WallpaperParams),
+    /// Different colours for the left and right halves of the keyboard
+    Split(SplitParams),
+    /// Text scrolling across the keyboard (letters, digits and !?-.:)
+    ScrollText(ScrollTextParams),
+    /// Static colour given as hue, saturation and value
+    StaticHsv(StaticHsvParams),
+    /// Apply a stored scripted effect
+    Scripted(ScriptedParams),
+    /// Store a scripted effect definition from a JSON file
+    LoadScripted(LoadScriptedParams),
+    /// Rainbow moving across the keyboard
+    SpectrumCycle(SpectrumCycleParams),
+    /// Rings of colour spreading from the pressed keys
+    Ripple(RippleParams),
+    /// Colour moving from the first to the second one as the CPU load rises
+    LoadMeter(StaticGradientParams),
+    /// A row of keys filling up with the CPU temperature
+    TempGauge(TempGaugeParams),
+    /// The number row filling up with the battery percentage
+    BatteryGauge(BatteryGaugeParams),
+    /// Remove a layer of the effect stack, as listed by `read layers`
+    RemoveLayer {
+        /// layer index, 0 being the bottom layer
+        index: usize,
+    },
+}
+
+#[derive(Parser)]
+struct RippleParams {
+    /// red (0-255)
+    red: u8,
+    /// green (0-255)
+    green: u8,
+    /// blue (0-255)
+    blue: u8,
+    /// time a ripple takes to fade, in tenths of a second (1-255)
+    decay: u8,
+    /// background colour as red, green and blue (0-255)
+    #[arg(long, num_args = 3)]
+    background: Option<Vec<u8>>,
+}
+
+#[derive(Parser)]
+struct TempGaugeParams {
+    /// temperature of an empty gauge, in Celsius
+    min: u8,
+    /// temperature of a full gauge, in Celsius
+    max: u8,
+    /// keyboard row to use, 0 being the function keys (0-5)
+    #[arg(long, default_value_t = 0)]
+    row: u8,
+}
+
+#[derive(Parser)]
+struct BatteryGaugeParams {
+    /// red of a full battery (0-255)
+    full_red: u8,
+    /// green of a full battery (0-255)
+    full_green: u8,
+    /// blue of a full battery (0-255)
+    full_blue: u8,
+    /// red of an empty battery (0-255)
+    empty_red: u8,
+    /// green of an empty battery (0-255)
+    empty_green: u8,
+    /// blue of an empty battery (0-255)
+    empty_blue: u8,
+    /// percentage below which the gauge turns amber
+    #[arg(long, default_value_t = 20)]
+    low: u8,
+}
+
+#[derive(Parser)]
+struct SpectrumCycleParams {
+    /// duration of a full cycle in seconds (1-255)
+    period: u8,
+    /// move from right to left instead of left to right
+    #[arg(long)]
+    reverse: bool,
+}
+
+#[derive(Parser)]
+struct StaticHsvParams {
+    /// hue in degrees (0-360)
+    hue: f32,
+    /// saturation (0.0-1.0)
+    saturation: f32,
+    /// value (0.0-1.0)
+    value: f32,
 }
 
 #[derive(Parser)]
@@ -254,6 +557,12 @@ struct WaveGradientParams {
     green2: u8,
     /// blue2 (0-255)
     blue2: u8,
+    /// way the wave moves
+    #[arg(long, value_enum, default_value_t = WaveDirection::Right)]
+    direction: WaveDirection,
+    /// speed of the wave (1-10)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=10))]
+    speed: u8,
 }
 
 #[derive(Parser)]
@@ -268,6 +577,56 @@ struct BreathingSingleParams {
     duration: u8,
 }
 
+#[derive(Parser)]
+struct SplitParams {
+    /// red1 (0-255)
+    red1: u8,
+    /// green1 (0-255)
+    green1: u8,
+    /// blue1 (0-255)
+    blue1: u8,
+    /// red2 (0-255)
+    red2: u8,
+    /// green2 (0-255)
+    green2: u8,
+    /// blue2 (0-255)
+    blue2: u8,
+    /// width of the blend band between the halves, in columns (0-15)
+    blend: Option<u8>,
+}
+
+#[derive(Parser)]
+struct ScriptedParams {
+    /// name of the scripted effect
+    name: String,
+}
+
+#[derive(Parser)]
+struct LoadScriptedParams {
+    /// path to the JSON definition
+    path: String,
+}
+
+#[derive(Parser)]
+struct ScrollTextParams {
+    /// red (0-255)
+    red: u8,
+    /// green (0-255)
+    green: u8,
+    /// blue (0-255)
+    blue: u8,
+    /// speed in columns per second (1-255)
+    speed: u8,
+    /// text to scroll
+    text: String,
+}
+
+#[derive(Parser)]
+struct WallpaperParams {
+    /// image to use instead of the current wallpaper
+    path: Option<String>,
+}
+
 fn main() {
     if std::fs::metadata(comms::SOCKET_PATH).is_err() {
         eprintln!("Error. Socket doesn't exit. Is daemon running?");
@@ -275,15 +634,39 @@ fn main() {
     }
 
     let cli = Cli::parse();
+    if let Err(e) = comms::check_protocol_version() {
+        eprintln!("Error. {}", e);
+        std::process::exit(1);
+    }
+    TARGET_DEVICE.get_or_init(|| cli.device);
 
     match cli.args {
         Args::Read { attr } => match attr {
+            ReadAttr::Devices => list_devices(),
             ReadAttr::Fan(AcStateParam { ac_state }) => read_fan_rpm(ac_state as usize),
+            ReadAttr::FanRange(AcStateParam { ac_state }) => read_fan_rpm_range(ac_state as usize),
             ReadAttr::Power(AcStateParam { ac_state }) => read_power_mode(ac_state as usize),
+            ReadAttr::BoostRange(AcStateParam { ac_state }) => read_boost_range(ac_state as usize),
             ReadAttr::Brightness(AcStateParam { ac_state }) => read_brightness(ac_state as usize),
+            ReadAttr::Idle(AcStateParam { ac_state }) => read_idle(ac_state as usize),
             ReadAttr::Logo(AcStateParam { ac_state }) => read_logo_mode(ac_state as usize),
             ReadAttr::Sync => read_sync(),
             ReadAttr::Bho => read_bho(),
+            ReadAttr::ChargeLimit => read_charge_limit(),
+            ReadAttr::Startup => read_startup_report(),
+            ReadAttr::Priority => read_priority(),
+            ReadAttr::Headroom => read_thermal_headroom(),
+            ReadAttr::EffectSource => read_effect_provenance(),
+            ReadAttr::FanControl => read_fan_control_state(),
+            ReadAttr::FanMode => read_fan_control_mode(),
+            ReadAttr::AnimationRate => read_animation_rate(),
+            ReadAttr::EffectRanges => read_standard_effect_ranges(),
+            ReadAttr::Hardware => read_hardware_status(),
+            ReadAttr::Temp => read_cpu_temperature(),
+            ReadAttr::Battery => read_battery_status(),
+            ReadAttr::Effects => list_effects(),
+            ReadAttr::Layers => read_effect_stack(),
+            ReadAttr::EffectParams { name } => read_effect_param_spec(name),
         },
         Args::Write { attr } => match attr {
             WriteAttr::Fan(FanParams { ac_state, speed }) => {
@@ -299,14 +682,42 @@ fn main() {
                 ac_state,
                 brightness,
             }) => write_brightness(ac_state as usize, brightness as u8),
+            WriteAttr::AdjustBrightness(AdjustBrightnessParams { ac_state, delta }) => {
+                adjust_brightness(ac_state as usize, delta)
+            }
+            WriteAttr::StepBrightness(StepBrightnessParams { ac_state, direction }) => {
+                step_brightness(ac_state as usize, direction == UpDown::Up)
+            }
             WriteAttr::Sync(SyncParams { sync_state }) => write_sync(sync_state.is_on()),
             WriteAttr::Logo(LogoParams {
                 ac_state,
                 logo_state,
-            }) => write_logo_mode(ac_state as usize, logo_state as u8),
+                brightness,
+            }) => write_logo_mode(ac_state as usize, logo_state, brightness),
             WriteAttr::Bho(BhoParams { state, threshold }) => {
                 validate_and_write_bho(threshold, state)
             }
+            WriteAttr::Priority(PriorityParams { level }) => write_priority(level),
+            WriteAttr::IdleTimeout(IdleTimeoutParams { ac_state, timeout_ms }) => {
+                write_idle_timeout(ac_state as usize, timeout_ms)
+            }
+            WriteAttr::FanCeiling(FanCeilingParams { ac_state, max_rpm, strict }) => {
+                write_fan_ceiling(ac_state as usize, max_rpm, strict)
+            }
+            WriteAttr::PowerColor(PowerColorParams { state, overlay }) => {
+                write_power_mode_color_sync(state.is_on(), overlay)
+            }
+            WriteAttr::Preset(PresetParams { ac_state, preset }) => {
+                write_performance_preset(ac_state as usize, preset)
+            }
+            WriteAttr::ChargeLimit { percent } => write_charge_limit(percent),
+            WriteAttr::KeyboardPower { state } => write_keyboard_power(state.is_on()),
+            WriteAttr::FanMode { mode } => write_fan_control_mode(match mode {
+                FanMode::Daemon => comms::FanControlMode::DaemonManaged,
+                FanMode::Manual => comms::FanControlMode::Manual,
+                FanMode::Auto => comms::FanControlMode::FirmwareAuto,
+            }),
+            WriteAttr::AnimationRate { fps } => write_animation_rate(fps),
         },
         Args::Effect { effect } => match effect {
             Effect::Static(params) => send_effect(
@@ -333,13 +744,64 @@ fn main() {
                     params.red2,
                     params.green2,
                     params.blue2,
+                    params.direction as u8,
+                    params.speed,
                 ],
             ),
             Effect::BreathingSingle(params) => send_effect(
                 "breathing_single".to_string(),
                 vec![params.red, params.green, params.blue, params.duration],
             ),
+            Effect::ScrollText(params) => {
+                let mut args = vec![params.red, params.green, params.blue, params.speed];
+                args.extend(params.text.into_bytes());
+                send_effect("scroll_text".to_string(), args)
+            }
+            Effect::Scripted(params) => set_scripted_effect(params.name),
+            Effect::RemoveLayer { index } => remove_effect_layer(index),
+            Effect::Ripple(params) => {
+                let mut args = vec![params.red, params.green, params.blue, params.decay];
+                args.extend(params.background.unwrap_or_default());
+                send_effect("ripple".to_string(), args)
+            }
+            Effect::LoadMeter(params) => send_effect(
+                "load_meter".to_string(),
+                vec![params.red1, params.green1, params.blue1, params.red2, params.green2, params.blue2],
+            ),
+            Effect::TempGauge(params) => {
+                send_effect("temp_gauge".to_string(), vec![params.min, params.max, params.row])
+            }
+            Effect::BatteryGauge(params) => send_effect(
+                "battery_gauge".to_string(),
+                vec![
+                    params.full_red, params.full_green, params.full_blue,
+                    params.empty_red, params.empty_green, params.empty_blue,
+                    params.low,
+                ],
+            ),
+            Effect::SpectrumCycle(params) => {
+                send_effect("spectrum_cycle".to_string(), vec![params.period, params.reverse as u8])
+            }
+            Effect::LoadScripted(params) => load_effect_definition(&params.path),
+            Effect::Wallpaper(params) => set_colour_from_wallpaper(params.path),
+            Effect::Split(params) => set_split_colours(
+                [params.red1, params.green1, params.blue1],
+                [params.red2, params.green2, params.blue2],
+                params.blend.unwrap_or(0),
+            ),
+            Effect::StaticHsv(params) => send_effect_colours(
+                "static".to_string(),
+                vec![comms::Colour::Hsv { h: params.hue, s: params.saturation, v: params.value }],
+                vec![],
+            ),
+        },
+        Args::Profile { action } => match action {
+            ProfileAction::Toggle(ToggleProfilesParams { a, b }) => toggle_profiles(a, b),
+            ProfileAction::Save { name } => save_profile(name),
+            ProfileAction::Load { name } => load_profile(name),
+            ProfileAction::List => list_profiles(),
         },
+        Args::Trigger { event } => trigger_idle_event(event),
         Args::StandardEffect { effect } => match effect {
             StandardEffect::Off => send_standard_effect("off".to_string(), vec![]),
             StandardEffect::Spectrum => send_standard_effect("spectrum".to_string(), vec![]),
@@ -520,7 +982,67 @@ fn send_standard_effect(name: String, params: Vec<u8>) {
 
 fn send_effect(name: String, params: Vec<u8>) {
     match send_data(comms::DaemonCommand::SetEffect { name, params }) {
-        Some(comms::DaemonResponse::SetEffect { result }) => {
+        Some(comms::DaemonResponse::SetEffect { result, error }) => {
+            if result {
+                println!("Effect set OK!");
+            } else if let Some(error) = error {
+                eprintln!("Effect set FAIL: {}", error);
+            } else {
+                eprintln!("Effect set FAIL!");
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn send_effect_colours(name: String, colours: Vec<comms::Colour>, params: Vec<u8>) {
+    match send_data(comms::DaemonCommand::SetEffectColours { name, colours, params }) {
+        Some(comms::DaemonResponse::SetEffectColours { result }) => {
+            if result {
+                println!("Effect set OK!");
+            } else {
+                eprintln!("Effect set FAIL!");
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn set_scripted_effect(name: String) {
+    match send_data(comms::DaemonCommand::SetScriptedEffect { name }) {
+        Some(comms::DaemonResponse::SetScriptedEffect { result }) => {
+            if result {
+                println!("Effect set OK!");
+            } else {
+                eprintln!("Effect set FAIL!");
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn load_effect_definition(path: &str) {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", path, e);
+            return;
+        }
+    };
+    match send_data(comms::DaemonCommand::LoadEffectDefinition { json }) {
+        Some(comms::DaemonResponse::LoadEffectDefinition { error: None }) => println!("Effect stored OK!"),
+        Some(comms::DaemonResponse::LoadEffectDefinition { error: Some(e) }) => eprintln!("Invalid effect: {}", e),
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn set_colour_from_wallpaper(path: Option<String>) {
+    match send_data(comms::DaemonCommand::SetColorFromWallpaper { path }) {
+        Some(comms::DaemonResponse::SetColorFromWallpaper { result }) => {
             if result {
                 println!("Effect set OK!");
             } else {
@@ -532,13 +1054,143 @@ fn send_effect(name: String, params: Vec<u8>) {
     }
 }
 
+fn set_split_colours(left: [u8; 3], right: [u8; 3], blend: u8) {
+    let left = comms::Colour::Rgb(left);
+    let right = comms::Colour::Rgb(right);
+    match send_data(comms::DaemonCommand::SetSplitColors { left, right, blend }) {
+        Some(comms::DaemonResponse::SetSplitColors { result, error }) => {
+            if result {
+                println!("Effect set OK!");
+            } else if let Some(error) = error {
+                eprintln!("Effect set FAIL: {}", error);
+            } else {
+                eprintln!("Effect set FAIL!");
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn trigger_idle_event(event: TriggerEvent) {
+    let cmd = match event {
+        TriggerEvent::Idle => comms::DaemonCommand::TriggerIdle,
+        TriggerEvent::Active => comms::DaemonCommand::TriggerActive,
+    };
+    match send_data(cmd) {
+        Some(comms::DaemonResponse::TriggerIdle { result })
+        | Some(comms::DaemonResponse::TriggerActive { result }) => {
+            if !result {
+                eprintln!("Not allowed to trigger idle events!");
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn toggle_profiles(a: String, b: String) {
+    match send_data(comms::DaemonCommand::ToggleProfiles { a, b }) {
+        Some(comms::DaemonResponse::ToggleProfiles { profile: Some(profile) }) => {
+            println!("Profile {} applied", profile);
+        },
+        Some(comms::DaemonResponse::ToggleProfiles { profile: None }) => {
+            eprintln!("Failed to apply profile!");
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn save_profile(name: String) {
+    match send_data(comms::DaemonCommand::SaveEffectProfile { name }) {
+        Some(comms::DaemonResponse::SaveEffectProfile { result }) => {
+            if result {
+                println!("Profile saved");
+            } else {
+                eprintln!("Failed to save profile!");
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn load_profile(name: String) {
+    match send_data(comms::DaemonCommand::LoadEffectProfile { name: name.clone() }) {
+        Some(comms::DaemonResponse::LoadEffectProfile { result }) => {
+            if result {
+                println!("Profile {} applied", name);
+            } else {
+                eprintln!("Failed to apply profile!");
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn list_profiles() {
+    match send_data(comms::DaemonCommand::ListEffectProfiles) {
+        Some(comms::DaemonResponse::ListEffectProfiles { names }) => {
+            for name in names {
+                println!("{}", name);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
 fn send_data(opt: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
-    match comms::bind() {
+    let index = TARGET_DEVICE.get().copied().unwrap_or(0);
+    let opt = match index {
+        0 => opt,
+        _ => comms::DaemonCommand::OnDevice { index, command: Box::new(opt) },
+    };
+    let response = match comms::bind() {
         Some(socket) => comms::send_to_daemon(opt, socket),
         None => {
             eprintln!("Error. Cannot bind to socket");
             None
         },
+    };
+    let response = match response {
+        Some(comms::DaemonResponse::OnDevice { response }) => Some(*response),
+        response => response,
+    };
+    match response {
+        Some(comms::DaemonResponse::Error { code, message }) => {
+            eprintln!("Daemon error ({:?}): {}", code, message);
+            None
+        },
+        response => response,
+    }
+}
+
+fn list_devices() {
+    match send_data(comms::DaemonCommand::ListDevices) {
+        Some(comms::DaemonResponse::ListDevices { devices }) => {
+            if devices.is_empty() {
+                println!("No devices found");
+            }
+            for (i, device) in devices.iter().enumerate() {
+                let mut features = vec![];
+                if device.has_fan_control {
+                    features.push("fan control");
+                }
+                if device.has_bho {
+                    features.push("bho");
+                }
+                if device.has_logo_led {
+                    features.push("logo");
+                }
+                println!("{}: {} ({}x{} keys) {}", i, device.name,
+                    device.key_matrix.0, device.key_matrix.1, features.join(", "));
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
     }
 }
 
@@ -557,6 +1209,35 @@ fn read_fan_rpm(ac: usize) {
     }
 }
 
+fn read_boost_range(ac: usize) {
+    match send_data(comms::DaemonCommand::GetBoostRange { ac }) {
+        Some(comms::DaemonResponse::GetBoostRange { cpu, gpu }) => {
+            for (name, range) in [("CPU", cpu), ("GPU", gpu)] {
+                if range.supported {
+                    println!("{} boost: {} - {} ({})", name, range.min, range.max, range.steps.join(", "));
+                } else {
+                    println!("{} boost: not supported", name);
+                }
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_fan_rpm_range(ac: usize) {
+    match send_data(comms::DaemonCommand::GetFanRpmRange { ac }) {
+        Some(comms::DaemonResponse::GetFanRpmRange { min, max, supports_auto }) => {
+            println!("Fan speed range: {} - {} RPM", min, max);
+            if supports_auto {
+                println!("0 selects automatic fan control");
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
 fn read_logo_mode(ac: usize) {
     match send_data(comms::DaemonCommand::GetLogoLedState { ac }) {
         Some(comms::DaemonResponse::GetLogoLedState { logo_state }) => {
@@ -653,6 +1334,7 @@ fn write_pwr_mode(ac: usize, pwr_mode: u8, cpu_mode: Option<u8>, gpu_mode: Optio
         cpu: cm,
         gpu: gm,
     }) {
+        Some(comms::DaemonResponse::SetPowerMode { error: Some(e), .. }) => eprintln!("Failed to set power mode: {}", e),
         Some(_) => read_power_mode(ac),
         None => {
             Cli::command()
@@ -675,6 +1357,18 @@ fn read_brightness(ac: usize) {
     }
 }
 
+fn read_idle(ac: usize) {
+    match send_data(comms::DaemonCommand::GetIdle { ac }) {
+        Some(comms::DaemonResponse::GetIdle { timeout_ms, .. }) if timeout_ms != 0 => {
+            println!("Idle timeout: {}ms", timeout_ms);
+        },
+        Some(comms::DaemonResponse::GetIdle { val: 0, .. }) => println!("Idle timeout: disabled"),
+        Some(comms::DaemonResponse::GetIdle { val, .. }) => println!("Idle timeout: {} minutes", val),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
 fn read_sync() {
     match send_data(comms::DaemonCommand::GetSync()) {
         Some(comms::DaemonResponse::GetSync { sync }) => {
@@ -685,23 +1379,407 @@ fn read_sync() {
     }
 }
 
+fn read_startup_report() {
+    match send_data(comms::DaemonCommand::GetStartupReport) {
+        Some(comms::DaemonResponse::GetStartupReport {
+            device,
+            effects_restored,
+            settings_restored,
+            ac_online,
+            errors,
+        }) => {
+            println!("Device: {}", device.unwrap_or_else(|| String::from("None")));
+            println!("Effects restored: {}", effects_restored);
+            println!("Settings restored: {}", settings_restored);
+            let ac_desc = match ac_online {
+                Some(true) => "AC",
+                Some(false) => "Battery",
+                None => "Unknown",
+            };
+            println!("Power source at startup: {}", ac_desc);
+            for error in errors {
+                eprintln!("Error: {}", error);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_thermal_headroom() {
+    match send_data(comms::DaemonCommand::GetThermalHeadroom) {
+        Some(comms::DaemonResponse::GetThermalHeadroom { headroom: Some(headroom), fan_tier, fan_rpm }) => {
+            println!("Thermal headroom: {}%", headroom);
+            println!("Fan tier: {} ({} RPM)", fan_tier, fan_rpm);
+        },
+        Some(comms::DaemonResponse::GetThermalHeadroom { headroom: None, .. }) => {
+            eprintln!("No temperature sensor available");
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn list_effects() {
+    match send_data(comms::DaemonCommand::ListEffects) {
+        Some(comms::DaemonResponse::ListEffects { custom, standard }) => {
+            println!("Custom effects: {}", custom.join(", "));
+            println!("Standard effects: {}", standard.join(", "));
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn remove_effect_layer(index: usize) {
+    match send_data(comms::DaemonCommand::RemoveEffectLayer { index }) {
+        Some(comms::DaemonResponse::RemoveEffectLayer { result }) => {
+            if result {
+                println!("Layer removed");
+            } else {
+                eprintln!("No layer {}!", index);
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_effect_stack() {
+    match send_data(comms::DaemonCommand::GetEffectStack) {
+        Some(comms::DaemonResponse::GetEffectStack { layers }) => {
+            if layers.is_empty() {
+                println!("No effect layers");
+            }
+            for (index, layer) in layers.iter().enumerate() {
+                println!("{}: {} ({} keys)", index, layer.name, layer.key_mask_summary);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_effect_param_spec(name: String) {
+    match send_data(comms::DaemonCommand::GetEffectParamSpec { name: name.clone() }) {
+        Some(comms::DaemonResponse::GetEffectParamSpec { min_len, max_len, fields }) => {
+            match max_len {
+                Some(max) if max == min_len => println!("{}: {} params", name, min_len),
+                Some(max) => println!("{}: {} to {} params", name, min_len, max),
+                None => println!("{}: at least {} params", name, min_len),
+            }
+            println!("Fields: {}", fields.join(", "));
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => {}, // send_data reported the error
+    }
+}
+
+fn read_cpu_temperature() {
+    match send_data(comms::DaemonCommand::GetCpuTemperature) {
+        Some(comms::DaemonResponse::GetCpuTemperature { temp: Some(temp), fahrenheit }) => {
+            println!("CPU temperature: {:.1}°{}", temp, if fahrenheit { 'F' } else { 'C' });
+        },
+        Some(comms::DaemonResponse::GetCpuTemperature { temp: None, .. }) => {
+            eprintln!("No temperature sensor available");
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_hardware_status() {
+    match send_data(comms::DaemonCommand::GetHardwareStatus) {
+        Some(comms::DaemonResponse::GetHardwareStatus { device_present, write_failures, total_write_failures, rediscoveries }) => {
+            println!("Device present: {}", device_present);
+            println!("Failed frames in a row: {}", write_failures);
+            println!("Failed frames total: {}", total_write_failures);
+            println!("Device reopened: {} times", rediscoveries);
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_battery_status() {
+    match send_data(comms::DaemonCommand::GetBatteryStatus) {
+        Some(comms::DaemonResponse::GetBatteryStatus { percentage, on_ac, charging }) => {
+            println!("Battery: {:.0}%", percentage);
+            println!("AC: {}", if on_ac { "plugged in" } else { "unplugged" });
+            println!("Charging: {}", charging);
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_standard_effect_ranges() {
+    match send_data(comms::DaemonCommand::GetStandardEffectRanges) {
+        Some(comms::DaemonResponse::GetStandardEffectRanges { ranges }) => {
+            for r in ranges {
+                println!("{} {}: {}-{}", r.effect, r.param, r.min, r.max);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_fan_control_state() {
+    match send_data(comms::DaemonCommand::GetFanControlState) {
+        Some(comms::DaemonResponse::GetFanControlState { temp, required_rpm, applied_rpm, ceiling, strict, ceiling_limiting }) => {
+            match temp {
+                Some(temp) => println!("CPU temperature: {:.1}°C", temp),
+                None => println!("CPU temperature: unknown"),
+            }
+            println!("Required fan speed: {} RPM", required_rpm);
+            println!("Applied fan speed: {} RPM", applied_rpm);
+            if ceiling > 0 {
+                println!("Fan ceiling: {} RPM{}", ceiling, if strict { " (strict)" } else { "" });
+            } else {
+                println!("Fan ceiling: none");
+            }
+            if ceiling_limiting {
+                println!("Ceiling is limiting the fan speed");
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_fan_control_mode() {
+    match send_data(comms::DaemonCommand::GetFanControlMode) {
+        Some(comms::DaemonResponse::GetFanControlMode { mode }) => {
+            let desc = match mode {
+                comms::FanControlMode::DaemonManaged => "daemon (temperature based)",
+                comms::FanControlMode::Manual => "manual",
+                comms::FanControlMode::FirmwareAuto => "auto (firmware)",
+            };
+            println!("Fan control: {}", desc);
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_fan_control_mode(mode: comms::FanControlMode) {
+    match send_data(comms::DaemonCommand::SetFanControlMode { mode }) {
+        Some(comms::DaemonResponse::SetFanControlMode { error: Some(e), .. }) => eprintln!("Failed to set the fan control mode: {}", e),
+        Some(comms::DaemonResponse::SetFanControlMode { .. }) => read_fan_control_mode(),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_animation_rate() {
+    match send_data(comms::DaemonCommand::GetAnimationRate) {
+        Some(comms::DaemonResponse::GetAnimationRate { fps }) => println!("Animation rate: {} fps", fps),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_animation_rate(fps: u32) {
+    match send_data(comms::DaemonCommand::SetAnimationRate { fps }) {
+        Some(comms::DaemonResponse::SetAnimationRate { fps: set, .. }) => {
+            if set != fps {
+                eprintln!("Animation rate clamped to {} fps", set);
+            }
+            read_animation_rate()
+        }
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_effect_provenance() {
+    fn or_unknown<T: ToString>(v: Option<T>) -> String {
+        v.map_or(String::from("unknown"), |v| v.to_string())
+    }
+
+    match send_data(comms::DaemonCommand::GetEffectProvenance) {
+        Some(comms::DaemonResponse::GetEffectProvenance { timestamp, pid, uid }) => {
+            println!("Set at (unix time): {}", or_unknown(timestamp));
+            println!("Client pid: {}", or_unknown(pid));
+            println!("Client uid: {}", or_unknown(uid));
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_priority() {
+    match send_data(comms::DaemonCommand::GetThreadPriority) {
+        Some(comms::DaemonResponse::GetThreadPriority { level }) => {
+            println!("Current worker niceness: {}", level);
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_priority(level: i32) {
+    match send_data(comms::DaemonCommand::SetThreadPriority { level }) {
+        Some(comms::DaemonResponse::SetThreadPriority { level: Some(level) }) => {
+            println!("Current worker niceness: {}", level);
+        },
+        Some(_) => eprintln!("Failed to set worker niceness!"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn write_fan_ceiling(ac: usize, max_rpm: i32, strict: bool) {
+    match send_data(comms::DaemonCommand::SetFanCeiling { ac, max_rpm, strict }) {
+        Some(comms::DaemonResponse::SetFanCeiling { result: true }) => {},
+        Some(_) => eprintln!("Failed to set fan ceiling!"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn write_power_mode_color_sync(enabled: bool, overlay: bool) {
+    match send_data(comms::DaemonCommand::SetPowerModeColorSync { enabled, overlay, colors: None }) {
+        Some(comms::DaemonResponse::SetPowerModeColorSync { result: true }) => {},
+        Some(_) => eprintln!("Failed to set power mode colour sync!"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn adjust_brightness(ac: usize, delta: i32) {
+    match send_data(comms::DaemonCommand::AdjustBrightness { ac, delta }) {
+        Some(comms::DaemonResponse::AdjustBrightness { result: true, brightness }) => {
+            println!("Current brightness: {}", brightness);
+        },
+        Some(_) => eprintln!("Failed to adjust brightness!"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn step_brightness(ac: usize, up: bool) {
+    match send_data(comms::DaemonCommand::StepBrightness { ac, up }) {
+        Some(comms::DaemonResponse::StepBrightness { result: true, brightness }) => {
+            println!("Current brightness: {}", brightness);
+        },
+        Some(_) => eprintln!("Failed to adjust brightness!"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn write_keyboard_power(on: bool) {
+    match send_data(comms::DaemonCommand::SetKeyboardPower { on }) {
+        Some(comms::DaemonResponse::SetKeyboardPower { error: Some(e), .. }) => eprintln!("Failed to set keyboard power: {}", e),
+        Some(comms::DaemonResponse::SetKeyboardPower { .. }) => println!("Keyboard: {}", if on { "on" } else { "off" }),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
 fn write_brightness(ac: usize, val: u8) {
     match send_data(comms::DaemonCommand::SetBrightness { ac, val }) {
+        Some(comms::DaemonResponse::SetBrightness { error: Some(e), .. }) => eprintln!("Failed to set brightness: {}", e),
         Some(_) => read_brightness(ac),
         None => eprintln!("Unknown error!"),
     }
 }
 
+fn read_charge_limit() {
+    match send_data(comms::DaemonCommand::GetChargeLimit) {
+        Some(comms::DaemonResponse::GetChargeLimit { percent: Some(percent) }) => {
+            println!("Charge limit: {}%", percent);
+        },
+        Some(comms::DaemonResponse::GetChargeLimit { percent: None }) => {
+            println!("Charge limit: not supported");
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_charge_limit(percent: u8) {
+    if !(50..=100).contains(&percent) {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Charge limit must be between 50 and 100")
+            .exit()
+    }
+    match send_data(comms::DaemonCommand::SetChargeLimit { percent }) {
+        Some(comms::DaemonResponse::SetChargeLimit { result: true, .. }) => read_charge_limit(),
+        Some(comms::DaemonResponse::SetChargeLimit { error, .. }) => {
+            eprintln!("Failed to set the charge limit: {}", error.unwrap_or_default());
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_performance_preset(ac: usize, preset: Preset) {
+    let preset = match preset {
+        Preset::Silent => comms::PerformancePreset::Silent,
+        Preset::Balanced => comms::PerformancePreset::Balanced,
+        Preset::Performance => comms::PerformancePreset::Performance,
+        Preset::Max => comms::PerformancePreset::Max,
+    };
+    match send_data(comms::DaemonCommand::SetPerformancePreset { ac, preset }) {
+        Some(comms::DaemonResponse::SetPerformancePreset { result: true, pwr, cpu, gpu, fan_rpm, .. }) => {
+            let fan = match fan_rpm {
+                0 => String::from("auto"),
+                rpm => format!("{} RPM", rpm),
+            };
+            println!("Applied power mode {} (CPU boost {}, GPU boost {}), fan {}", pwr, cpu, gpu, fan);
+        },
+        Some(comms::DaemonResponse::SetPerformancePreset { error, .. }) => {
+            eprintln!("Failed to apply the preset: {}", error.unwrap_or_default());
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_idle_timeout(ac: usize, timeout_ms: u64) {
+    match send_data(comms::DaemonCommand::SetIdleTimeout { ac, timeout_ms }) {
+        Some(comms::DaemonResponse::SetIdleTimeout { result }) => {
+            if result {
+                println!("Idle timeout set");
+            } else {
+                eprintln!("Failed to set the idle timeout!");
+            }
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
 fn write_fan_speed(ac: usize, x: i32) {
     match send_data(comms::DaemonCommand::SetFanSpeed { ac, rpm: x }) {
+        Some(comms::DaemonResponse::SetFanSpeed { error: Some(e), .. }) => eprintln!("Failed to set fan speed: {}", e),
+        Some(comms::DaemonResponse::SetFanSpeed { clamped: true, .. }) => {
+            println!("Fan speed is outside the limits of this laptop and was clamped");
+            read_fan_rpm(ac)
+        },
         Some(_) => read_fan_rpm(ac),
         None => eprintln!("Unknown error!"),
     }
 }
 
-fn write_logo_mode(ac: usize, x: u8) {
-    match send_data(comms::DaemonCommand::SetLogoLedState { ac, logo_state: x }) {
-        Some(_) => read_logo_mode(ac),
+fn write_logo_mode(ac: usize, x: i32, brightness: Option<u8>) {
+    let mode = match x {
+        0 => comms::LogoMode::Off,
+        1 => comms::LogoMode::On,
+        2 => comms::LogoMode::Breathing,
+        _ => Cli::command()
+            .error(ErrorKind::InvalidValue, "Logo mode must be 0, 1 or 2")
+            .exit(),
+    };
+    if brightness.is_some_and(|b| b > 100) {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Brightness must be between 0 and 100")
+            .exit()
+    }
+    match send_data(comms::DaemonCommand::SetLogoLedMode { ac, mode, brightness }) {
+        Some(comms::DaemonResponse::SetLogoLedMode { result: true, .. }) => read_logo_mode(ac),
+        Some(comms::DaemonResponse::SetLogoLedMode { error, .. }) => {
+            eprintln!("Failed to set the logo mode: {}", error.unwrap_or_default());
+        },
+        Some(_) => eprintln!("Unexpected response from daemon!"),
         None => eprintln!("Unknown error!"),
     }
 }
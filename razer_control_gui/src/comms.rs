@@ -5,6 +5,106 @@ use std::os::unix::net::{UnixListener, UnixStream};
 /// Razer laptop control socket path
 pub const SOCKET_PATH: &str = "/tmp/razercontrol-socket";
 
+/// Version of the command and response enums. Bump it whenever they change,
+/// clients refuse to talk to a daemon that speaks another version.
+/// bincode encodes a variant by its position, so new variants go at the end
+/// and existing ones are never reordered or removed, or older clients would
+/// send one command and the daemon run another. The snapshot tests at the
+/// end of this file fail when an encoding changes without a version bump
+pub const PROTOCOL_VERSION: u32 = 16;
+
+/// Largest message accepted on the socket, anything longer is treated as garbage
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+/// A colour, either as RGB or as hue (degrees), saturation and value (0.0 - 1.0)
+pub enum Colour {
+    Rgb([u8; 3]),
+    Hsv { h: f32, s: f32, v: f32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Valid values of a standard effect parameter
+pub struct EffectParamRange {
+    pub effect: String,
+    pub param: String,
+    pub min: u8,
+    pub max: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Valid CPU or GPU boost levels in custom power mode
+pub struct BoostRange {
+    pub supported: bool,
+    pub min: u8,
+    pub max: u8,
+    /// Name of each level from min to max
+    pub steps: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Power mode and fan combinations applied by `SetPerformancePreset`
+pub enum PerformancePreset {
+    Silent,
+    Balanced,
+    Performance,
+    Max,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Logo LED modes, the same as the logo_state values 0, 1 and 2
+pub enum LogoMode {
+    Off,
+    On,
+    Breathing,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Who sets the fan speed
+pub enum FanControlMode {
+    DaemonManaged, // the temperature monitor, from the temperature profile
+    Manual,        // SetFanSpeed only, the temperature monitor leaves the fan alone
+    FirmwareAuto,  // the laptop firmware, the fan speed is set to 0 (auto)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Kind of failure reported by `DaemonResponse::Error`
+pub enum ErrorCode {
+    InvalidCommand, // The command could not be parsed or is not allowed
+    LockPoisoned,   // No longer sent, poisoned locks are recovered. Kept for the wire format
+    NoDevice,       // No supported device is attached
+    NotFound,       // Unknown effect, profile or similar
+    Unsupported,    // The device lacks the feature
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A detected device and what it supports, according to the laptops file
+pub struct DeviceInfo {
+    pub name: String,
+    pub has_fan_control: bool,
+    pub has_bho: bool,
+    pub has_logo_led: bool,
+    /// Rows and columns of the per-key lighting
+    pub key_matrix: (usize, usize),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A layer of the effect stack
+pub struct EffectInfo {
+    pub name: String,
+    /// Number of keys (out of 90) the layer is applied to
+    pub key_mask_summary: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The effect of a layer and the params it was set with
+pub struct EffectDescriptor {
+    /// Name as listed in the effect stack, e.g. "Wave Gradient"
+    pub name: String,
+    pub params: Vec<u8>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 /// Represents data sent TO the daemon
 pub enum DaemonCommand {
@@ -26,32 +126,169 @@ pub enum DaemonCommand {
     GetSync (),
     SetBatteryHealthOptimizer { is_on: bool, threshold: u8 },
     GetBatteryHealthOptimizer (),
-    GetDeviceName 
+    GetDeviceName,
+    SetColorFromWallpaper { path: Option<String> }, // Static colour from the wallpaper
+    SetSplitColors { left: Colour, right: Colour, blend: u8 }, // Blend band width in columns
+    GetStartupReport,
+    ToggleProfiles { a: String, b: String }, // Load whichever of the two profiles isn't active
+    SetThreadPriority { level: i32 }, // Niceness of the animator and monitor threads
+    GetThreadPriority,
+    GetThermalHeadroom,
+    // colors are indexed by power mode, None keeps the configured ones
+    SetPowerModeColorSync { enabled: bool, overlay: bool, colors: Option<[[u8; 3]; 5]> },
+    GetEffectProvenance,
+    // Same as SetEffect, the colours are converted to RGB and put in front of params
+    SetEffectColours { name: String, colours: Vec<Colour>, params: Vec<u8> },
+    // max_rpm = 0 removes the ceiling, strict keeps it even at critical temperatures
+    SetFanCeiling { ac: usize, max_rpm: i32, strict: bool },
+    GetFanControlState,
+    TriggerIdle,   // Same as the idle watch firing
+    TriggerActive, // Same as the active watch firing
+    GetKeyboardRGBDiff { since_seq: u64 }, // Keys changed since the frame the client last saw
+    SetLayerIgnoreBrightness { index: usize, ignore: bool }, // Keep a layer at full brightness
+    // Runs the commands in order without other clients interleaving. Batches can't be nested
+    Batch { commands: Vec<DaemonCommand> },
+    IsEffectSupported { name: String },
+    AdjustBrightness { ac: usize, delta: i32 }, // Brightness change in percent
+    LoadEffectDefinition { json: String }, // Validates and stores a scripted effect
+    SetScriptedEffect { name: String },    // Applies a stored scripted effect
+    GetStandardEffectRanges,
+    GetHardwareStatus,
+    GetCpuTemperature,
+    ListEffects,
+    GetEffectParamSpec { name: String },
+    // Exactly 90 colours, one per key. A layer one past the top one is added
+    SetKeyColors { layer: usize, colors: Vec<(u8, u8, u8)> },
+    SaveEffectProfile { name: String }, // Stores the current effect stack under a name
+    LoadEffectProfile { name: String },
+    ListEffectProfiles,
+    GetEffectStack,
+    RemoveEffectLayer { index: usize }, // 0 is the bottom layer
+    // Adds a layer on top of the stack, only covering the keys set in the 90 entry mask
+    PushEffect { name: String, params: Vec<u8>, mask: Vec<bool> },
+    SetLayerOpacity { index: usize, opacity: f32 }, // 0.0 (invisible) to 1.0 (covers the layers below)
+    // Raised to at least 5000, 0 falls back to the SetIdle timeout in minutes
+    SetIdleTimeout { ac: usize, timeout_ms: u64 },
+    GetFanRpmRange { ac: usize },
+    // Runs a command on another device, 0 is the one every other command uses
+    OnDevice { index: usize, command: Box<DaemonCommand> },
+    ListDevices,
+    // Sent by clients before anything else, client_version is their PROTOCOL_VERSION
+    Handshake { client_version: u32 },
+    SetPerformancePreset { ac: usize, preset: PerformancePreset },
+    GetBoostRange { ac: usize },
+    SetChargeLimit { percent: u8 }, // 50-100, 100 charges fully
+    GetChargeLimit,
+    GetBatteryStatus,
+    GetIdle { ac: usize },
+    SetLogoLedMode { ac: usize, mode: LogoMode, brightness: Option<u8> }, // brightness in percent
+    StepBrightness { ac: usize, up: bool }, // AdjustBrightness by the configured step
+    // Turns the backlight off until turned on again, idle and screensaver events don't turn it back on
+    SetKeyboardPower { on: bool },
+    GetFanControlMode,
+    SetFanControlMode { mode: FanControlMode },
+    GetAnimationRate,
+    SetAnimationRate { fps: u32 }, // clamped to 1 - MAX_ANIMATION_FPS
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 /// Represents data sent back from Daemon after it receives
 /// a command.
 pub enum DaemonResponse {
-    SetFanSpeed { result: bool },                    // Response
+    SetFanSpeed { result: bool, error: Option<String>, clamped: bool }, // Response, clamped if the speed was outside the model limits
     GetFanSpeed { rpm: i32 },                        // Get (Fan speed)
-    SetPowerMode { result: bool },                   // Response
+    SetPowerMode { result: bool, error: Option<String> }, // Response, error describes a failure
     GetPwrLevel { pwr: u8 },                         // Get (Power mode)
     GetCPUBoost { cpu: u8 },                         // Get (CPU boost)
     GetGPUBoost { gpu: u8 },                         // Get (GPU boost)
     SetLogoLedState {result: bool },
     GetLogoLedState { logo_state: u8 },
-    GetKeyboardRGB { layer: i32, rgbdata: Vec<u8> }, // Response (RGB) of 90 keys
-    SetEffect { result: bool },                       // Set keyboard colour
+    // Response (RGB) of 90 keys, with the effect of the layer (the top one for layer -1)
+    GetKeyboardRGB { layer: i32, rgbdata: Vec<u8>, active_effect: Option<EffectDescriptor> },
+    SetEffect { result: bool, error: Option<String> }, // Set keyboard colour, error describes a failure
     SetStandardEffect { result: bool },                       // Set keyboard colour
-    SetBrightness { result: bool },
+    SetBrightness { result: bool, error: Option<String> },
     SetIdle { result: bool },
     GetBrightness { result: u8 },
     SetSync { result: bool },
     GetSync { sync: bool },
     SetBatteryHealthOptimizer { result: bool },
     GetBatteryHealthOptimizer { is_on: bool, threshold: u8 },
-    GetDeviceName { name: String }
+    GetDeviceName { name: String },
+    SetColorFromWallpaper { result: bool },
+    SetSplitColors { result: bool, error: Option<String> }, // error describes a failure
+    GetStartupReport {
+        device: Option<String>,
+        effects_restored: bool,  // false if the default effect was used
+        settings_restored: bool, // fan/power/light settings came from the config file
+        ac_online: Option<bool>,
+        errors: Vec<String>,
+    },
+    ToggleProfiles { profile: Option<String> }, // Newly applied profile, None on failure
+    SetThreadPriority { level: Option<i32> }, // Applied (clamped) niceness, None on failure
+    GetThreadPriority { level: i32 },
+    // 100 = plenty of cooling margin, 0 = critical temperature. None if no sensor is readable
+    GetThermalHeadroom { headroom: Option<u8>, fan_tier: String, fan_rpm: i32 },
+    SetPowerModeColorSync { result: bool },
+    // timestamp is in seconds since the unix epoch, None means unknown
+    GetEffectProvenance { timestamp: Option<u64>, pid: Option<i32>, uid: Option<u32> },
+    SetEffectColours { result: bool },
+    SetFanCeiling { result: bool },
+    // State of the temperature based fan control for the current AC state
+    GetFanControlState { temp: Option<f32>, required_rpm: i32, applied_rpm: i32, ceiling: i32, strict: bool, ceiling_limiting: bool },
+    TriggerIdle { result: bool },
+    TriggerActive { result: bool },
+    // keys are (index, RGB). full is set when all 90 keys are sent because since_seq was too old
+    GetKeyboardRGBDiff { seq: u64, full: bool, keys: Vec<(u8, [u8; 3])> },
+    SetLayerIgnoreBrightness { result: bool },
+    // One response per command, None if the batch was rejected
+    Batch { responses: Option<Vec<DaemonResponse>> },
+    // layered = accepted by SetEffect, standard = accepted by SetStandardEffect
+    IsEffectSupported { layered: bool, standard: bool },
+    AdjustBrightness { result: bool, brightness: u8 }, // New brightness in percent
+    LoadEffectDefinition { error: Option<String> }, // None if the definition was stored
+    SetScriptedEffect { result: bool },
+    // Colour parameters are always 0-255 and not listed
+    GetStandardEffectRanges { ranges: Vec<EffectParamRange> },
+    // write_failures counts the frames that failed in a row, 0 when the keyboard is healthy
+    GetHardwareStatus { device_present: bool, write_failures: u32, total_write_failures: u64, rediscoveries: u32 },
+    // In the configured unit, Fahrenheit if set, otherwise Celsius. None if no sensor is readable
+    GetCpuTemperature { temp: Option<f32>, fahrenheit: bool },
+    // custom = names for SetEffect, standard = names for SetStandardEffect
+    ListEffects { custom: Vec<String>, standard: Vec<String> },
+    // Expected length of the SetEffect params, max_len is None if unbounded
+    GetEffectParamSpec { min_len: usize, max_len: Option<usize>, fields: Vec<String> },
+    SetKeyColors { result: bool },
+    SaveEffectProfile { result: bool },
+    LoadEffectProfile { result: bool }, // false if the profile doesn't exist
+    ListEffectProfiles { names: Vec<String> },
+    GetEffectStack { layers: Vec<EffectInfo> }, // Bottom layer first
+    RemoveEffectLayer { result: bool },
+    PushEffect { result: bool, error: Option<String> },
+    SetLayerOpacity { result: bool },
+    SetIdleTimeout { result: bool },
+    // Manual fan speed limits of the model, supports_auto if 0 selects automatic control
+    GetFanRpmRange { min: i32, max: i32, supports_auto: bool },
+    OnDevice { response: Box<DaemonResponse> },
+    ListDevices { devices: Vec<DeviceInfo> }, // Indexed like OnDevice
+    Handshake { daemon_version: String, protocol_version: u32 },
+    // Sent instead of the normal response when a command can't be answered
+    Error { code: ErrorCode, message: String },
+    // The power mode, boosts and fan speed (0 = auto) the preset applied
+    SetPerformancePreset { result: bool, error: Option<String>, pwr: u8, cpu: u8, gpu: u8, fan_rpm: i32 },
+    GetBoostRange { cpu: BoostRange, gpu: BoostRange },
+    SetChargeLimit { result: bool, error: Option<String> },
+    GetChargeLimit { percent: Option<u8> }, // None if the charge can't be limited
+    GetBatteryStatus { percentage: f64, on_ac: bool, charging: bool },
+    // val is the SetIdle timeout in minutes, timeout_ms the SetIdleTimeout one, which is used when not 0
+    GetIdle { val: u32, timeout_ms: u64 },
+    SetLogoLedMode { result: bool, error: Option<String> },
+    StepBrightness { result: bool, brightness: u8 }, // New brightness in percent
+    SetKeyboardPower { result: bool, error: Option<String> },
+    GetFanControlMode { mode: FanControlMode },
+    SetFanControlMode { result: bool, error: Option<String> },
+    GetAnimationRate { fps: u32 },
+    SetAnimationRate { result: bool, fps: u32 }, // fps is the rate after clamping
 }
 
 #[allow(dead_code)]
@@ -87,14 +324,29 @@ pub fn create() -> Option<UnixListener> {
     return None;
 }
 
+#[allow(dead_code)]
+/// Checks that the daemon speaks the same protocol as this client. Err
+/// describes the mismatch, commands must not be sent in that case
+pub fn check_protocol_version() -> Result<(), String> {
+    let socket = bind().ok_or_else(|| String::from("Cannot bind to socket"))?;
+    match send_to_daemon(DaemonCommand::Handshake { client_version: PROTOCOL_VERSION }, socket) {
+        Some(DaemonResponse::Handshake { protocol_version, .. }) if protocol_version == PROTOCOL_VERSION => Ok(()),
+        Some(DaemonResponse::Handshake { daemon_version, protocol_version }) => Err(format!(
+            "Daemon {} speaks protocol version {}, this client speaks version {}. Please update both",
+            daemon_version, protocol_version, PROTOCOL_VERSION
+        )),
+        // Daemons from before the handshake can't parse it and don't answer
+        _ => Err(String::from("The daemon did not answer the handshake, it is probably older than this client")),
+    }
+}
+
 #[allow(dead_code)]
 pub fn send_to_daemon(command: DaemonCommand, mut sock: UnixStream) -> Option<DaemonResponse> {
     if let Ok(encoded) = bincode::serialize(&command) {
-        if sock.write_all(&encoded).is_ok() {
-            let mut buf = [0u8; 4096];
-            return match sock.read(&mut buf) {
-                Ok(readed) if readed > 0 => read_from_socked_resp(&buf[0..readed]),
-                Ok(_) => {
+        if write_frame(&mut sock, &encoded).is_ok() {
+            return match read_frame(&mut sock) {
+                Ok(frame) => read_from_socked_resp(&frame),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
                     eprintln!("No response from daemon");
                     None
                 }
@@ -110,6 +362,29 @@ pub fn send_to_daemon(command: DaemonCommand, mut sock: UnixStream) -> Option<Da
     return None;
 }
 
+/// Writes a message as its length (u32, little endian) followed by the payload
+pub fn write_frame<W: Write>(sock: &mut W, payload: &[u8]) -> std::io::Result<()> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Message too long"));
+    }
+    sock.write_all(&(payload.len() as u32).to_le_bytes())?;
+    sock.write_all(payload)
+}
+
+/// Reads a message written by `write_frame`, waiting for all of it even if it
+/// arrives in several pieces
+pub fn read_frame<R: Read>(sock: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    sock.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Message of {} bytes is too long", len)));
+    }
+    let mut payload = vec![0u8; len];
+    sock.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
 /// Deserializes incomming bytes in order to return
 /// a `DaemonResponse`. None is returned if deserializing failed
 fn read_from_socked_resp(bytes: &[u8]) -> Option<DaemonResponse> {
@@ -140,3 +415,276 @@ pub fn read_from_socket_req(bytes: &[u8]) -> Option<DaemonCommand> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, ErrorKind};
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    /// One value of every command in declaration order, with its bincode
+    /// encoding in hex. The first four bytes are the variant index
+    fn command_snapshots() -> Vec<(DaemonCommand, &'static str)> {
+        vec![
+            (DaemonCommand::SetFanSpeed { ac: 1, rpm: 3500 }, "00000000 0100000000000000ac0d0000"),
+            (DaemonCommand::GetFanSpeed { ac: 1 }, "01000000 0100000000000000"),
+            (DaemonCommand::SetPowerMode { ac: 1, pwr: 4, cpu: 2, gpu: 1 }, "02000000 0100000000000000040201"),
+            (DaemonCommand::GetPwrLevel { ac: 0 }, "03000000 0000000000000000"),
+            (DaemonCommand::GetCPUBoost { ac: 0 }, "04000000 0000000000000000"),
+            (DaemonCommand::GetGPUBoost { ac: 1 }, "05000000 0100000000000000"),
+            (DaemonCommand::SetLogoLedState { ac: 1, logo_state: 2 }, "06000000 010000000000000002"),
+            (DaemonCommand::GetLogoLedState { ac: 0 }, "07000000 0000000000000000"),
+            (DaemonCommand::GetKeyboardRGB { layer: -1 }, "08000000 ffffffff"),
+            (DaemonCommand::SetEffect { name: s("static"), params: vec![255, 0, 0] }, "09000000 06000000000000007374617469630300000000000000ff0000"),
+            (DaemonCommand::SetStandardEffect { name: s("wave"), params: vec![1] }, "0a000000 040000000000000077617665010000000000000001"),
+            (DaemonCommand::SetBrightness { ac: 1, val: 80 }, "0b000000 010000000000000050"),
+            (DaemonCommand::SetIdle { ac: 0, val: 5 }, "0c000000 000000000000000005000000"),
+            (DaemonCommand::GetBrightness { ac: 1 }, "0d000000 0100000000000000"),
+            (DaemonCommand::SetSync { sync: true }, "0e000000 01"),
+            (DaemonCommand::GetSync(), "0f000000"),
+            (DaemonCommand::SetBatteryHealthOptimizer { is_on: true, threshold: 80 }, "10000000 0150"),
+            (DaemonCommand::GetBatteryHealthOptimizer(), "11000000"),
+            (DaemonCommand::GetDeviceName, "12000000"),
+            (DaemonCommand::SetColorFromWallpaper { path: Some(s("/a.png")) }, "13000000 0106000000000000002f612e706e67"),
+            (DaemonCommand::SetSplitColors { left: Colour::Rgb([1, 2, 3]), right: Colour::Hsv { h: 120.0, s: 1.0, v: 0.5 }, blend: 3 }, "14000000 00000000010203010000000000f0420000803f0000003f03"),
+            (DaemonCommand::GetStartupReport, "15000000"),
+            (DaemonCommand::ToggleProfiles { a: s("a"), b: s("b") }, "16000000 010000000000000061010000000000000062"),
+            (DaemonCommand::SetThreadPriority { level: 5 }, "17000000 05000000"),
+            (DaemonCommand::GetThreadPriority, "18000000"),
+            (DaemonCommand::GetThermalHeadroom, "19000000"),
+            (DaemonCommand::SetPowerModeColorSync { enabled: true, overlay: false, colors: None }, "1a000000 010000"),
+            (DaemonCommand::GetEffectProvenance, "1b000000"),
+            (DaemonCommand::SetEffectColours { name: s("split"), colours: vec![Colour::Rgb([4, 5, 6])], params: vec![2] }, "1c000000 050000000000000073706c6974010000000000000000000000040506010000000000000002"),
+            (DaemonCommand::SetFanCeiling { ac: 0, max_rpm: 4000, strict: true }, "1d000000 0000000000000000a00f000001"),
+            (DaemonCommand::GetFanControlState, "1e000000"),
+            (DaemonCommand::TriggerIdle, "1f000000"),
+            (DaemonCommand::TriggerActive, "20000000"),
+            (DaemonCommand::GetKeyboardRGBDiff { since_seq: 7 }, "21000000 0700000000000000"),
+            (DaemonCommand::SetLayerIgnoreBrightness { index: 1, ignore: true }, "22000000 010000000000000001"),
+            (DaemonCommand::Batch { commands: vec![DaemonCommand::GetDeviceName] }, "23000000 010000000000000012000000"),
+            (DaemonCommand::IsEffectSupported { name: s("ripple") }, "24000000 0600000000000000726970706c65"),
+            (DaemonCommand::AdjustBrightness { ac: 1, delta: -10 }, "25000000 0100000000000000f6ffffff"),
+            (DaemonCommand::LoadEffectDefinition { json: s("{}") }, "26000000 02000000000000007b7d"),
+            (DaemonCommand::SetScriptedEffect { name: s("x") }, "27000000 010000000000000078"),
+            (DaemonCommand::GetStandardEffectRanges, "28000000"),
+            (DaemonCommand::GetHardwareStatus, "29000000"),
+            (DaemonCommand::GetCpuTemperature, "2a000000"),
+            (DaemonCommand::ListEffects, "2b000000"),
+            (DaemonCommand::GetEffectParamSpec { name: s("static") }, "2c000000 0600000000000000737461746963"),
+            (DaemonCommand::SetKeyColors { layer: 0, colors: vec![(1, 2, 3)] }, "2d000000 00000000000000000100000000000000010203"),
+            (DaemonCommand::SaveEffectProfile { name: s("p") }, "2e000000 010000000000000070"),
+            (DaemonCommand::LoadEffectProfile { name: s("p") }, "2f000000 010000000000000070"),
+            (DaemonCommand::ListEffectProfiles, "30000000"),
+            (DaemonCommand::GetEffectStack, "31000000"),
+            (DaemonCommand::RemoveEffectLayer { index: 2 }, "32000000 0200000000000000"),
+            (DaemonCommand::PushEffect { name: s("static"), params: vec![9], mask: vec![true, false] }, "33000000 060000000000000073746174696301000000000000000902000000000000000100"),
+            (DaemonCommand::SetLayerOpacity { index: 1, opacity: 0.5 }, "34000000 01000000000000000000003f"),
+            (DaemonCommand::SetIdleTimeout { ac: 1, timeout_ms: 30000 }, "35000000 01000000000000003075000000000000"),
+            (DaemonCommand::GetFanRpmRange { ac: 0 }, "36000000 0000000000000000"),
+            (DaemonCommand::OnDevice { index: 1, command: Box::new(DaemonCommand::GetDeviceName) }, "37000000 010000000000000012000000"),
+            (DaemonCommand::ListDevices, "38000000"),
+            (DaemonCommand::Handshake { client_version: 15 }, "39000000 0f000000"),
+            (DaemonCommand::SetPerformancePreset { ac: 1, preset: PerformancePreset::Max }, "3a000000 010000000000000003000000"),
+            (DaemonCommand::GetBoostRange { ac: 0 }, "3b000000 0000000000000000"),
+            (DaemonCommand::SetChargeLimit { percent: 80 }, "3c000000 50"),
+            (DaemonCommand::GetChargeLimit, "3d000000"),
+            (DaemonCommand::GetBatteryStatus, "3e000000"),
+            (DaemonCommand::GetIdle { ac: 1 }, "3f000000 0100000000000000"),
+            (DaemonCommand::SetLogoLedMode { ac: 0, mode: LogoMode::Breathing, brightness: Some(50) }, "40000000 0000000000000000020000000132"),
+            (DaemonCommand::StepBrightness { ac: 1, up: false }, "41000000 010000000000000000"),
+            (DaemonCommand::SetKeyboardPower { on: true }, "42000000 01"),
+            (DaemonCommand::GetFanControlMode, "43000000"),
+            (DaemonCommand::SetFanControlMode { mode: FanControlMode::Manual }, "44000000 01000000"),
+            (DaemonCommand::GetAnimationRate, "45000000"),
+            (DaemonCommand::SetAnimationRate { fps: 30 }, "46000000 1e000000"),
+        ]
+    }
+
+    /// One value of every response in declaration order, like `command_snapshots`
+    fn response_snapshots() -> Vec<(DaemonResponse, &'static str)> {
+        vec![
+            (DaemonResponse::SetFanSpeed { result: true, error: None, clamped: false }, "00000000 010000"),
+            (DaemonResponse::GetFanSpeed { rpm: 3500 }, "01000000 ac0d0000"),
+            (DaemonResponse::SetPowerMode { result: false, error: Some(s("e")) }, "02000000 0001010000000000000065"),
+            (DaemonResponse::GetPwrLevel { pwr: 4 }, "03000000 04"),
+            (DaemonResponse::GetCPUBoost { cpu: 2 }, "04000000 02"),
+            (DaemonResponse::GetGPUBoost { gpu: 1 }, "05000000 01"),
+            (DaemonResponse::SetLogoLedState { result: true }, "06000000 01"),
+            (DaemonResponse::GetLogoLedState { logo_state: 1 }, "07000000 01"),
+            (DaemonResponse::GetKeyboardRGB { layer: 0, rgbdata: vec![1, 2, 3], active_effect: Some(EffectDescriptor { name: s("Static"), params: vec![1, 2, 3] }) }, "08000000 0000000003000000000000000102030106000000000000005374617469630300000000000000010203"),
+            (DaemonResponse::SetEffect { result: true, error: None }, "09000000 0100"),
+            (DaemonResponse::SetStandardEffect { result: true }, "0a000000 01"),
+            (DaemonResponse::SetBrightness { result: true, error: None }, "0b000000 0100"),
+            (DaemonResponse::SetIdle { result: true }, "0c000000 01"),
+            (DaemonResponse::GetBrightness { result: 80 }, "0d000000 50"),
+            (DaemonResponse::SetSync { result: true }, "0e000000 01"),
+            (DaemonResponse::GetSync { sync: false }, "0f000000 00"),
+            (DaemonResponse::SetBatteryHealthOptimizer { result: true }, "10000000 01"),
+            (DaemonResponse::GetBatteryHealthOptimizer { is_on: true, threshold: 80 }, "11000000 0150"),
+            (DaemonResponse::GetDeviceName { name: s("Blade") }, "12000000 0500000000000000426c616465"),
+            (DaemonResponse::SetColorFromWallpaper { result: true }, "13000000 01"),
+            (DaemonResponse::SetSplitColors { result: true, error: None }, "14000000 0100"),
+            (DaemonResponse::GetStartupReport { device: Some(s("Blade")), effects_restored: true, settings_restored: false, ac_online: Some(true), errors: vec![s("e")] }, "15000000 010500000000000000426c616465010001010100000000000000010000000000000065"),
+            (DaemonResponse::ToggleProfiles { profile: Some(s("a")) }, "16000000 01010000000000000061"),
+            (DaemonResponse::SetThreadPriority { level: Some(5) }, "17000000 0105000000"),
+            (DaemonResponse::GetThreadPriority { level: 5 }, "18000000 05000000"),
+            (DaemonResponse::GetThermalHeadroom { headroom: Some(40), fan_tier: s("LOW"), fan_rpm: 3000 }, "19000000 012803000000000000004c4f57b80b0000"),
+            (DaemonResponse::SetPowerModeColorSync { result: true }, "1a000000 01"),
+            (DaemonResponse::GetEffectProvenance { timestamp: Some(1000), pid: Some(42), uid: None }, "1b000000 01e803000000000000012a00000000"),
+            (DaemonResponse::SetEffectColours { result: true }, "1c000000 01"),
+            (DaemonResponse::SetFanCeiling { result: true }, "1d000000 01"),
+            (DaemonResponse::GetFanControlState { temp: Some(55.5), required_rpm: 3000, applied_rpm: 2500, ceiling: 2500, strict: false, ceiling_limiting: true }, "1e000000 0100005e42b80b0000c4090000c40900000001"),
+            (DaemonResponse::TriggerIdle { result: true }, "1f000000 01"),
+            (DaemonResponse::TriggerActive { result: false }, "20000000 00"),
+            (DaemonResponse::GetKeyboardRGBDiff { seq: 9, full: false, keys: vec![(3, [1, 2, 3])] }, "21000000 090000000000000000010000000000000003010203"),
+            (DaemonResponse::SetLayerIgnoreBrightness { result: true }, "22000000 01"),
+            (DaemonResponse::Batch { responses: Some(vec![DaemonResponse::SetIdle { result: true }]) }, "23000000 0101000000000000000c00000001"),
+            (DaemonResponse::IsEffectSupported { layered: true, standard: false }, "24000000 0100"),
+            (DaemonResponse::AdjustBrightness { result: true, brightness: 60 }, "25000000 013c"),
+            (DaemonResponse::LoadEffectDefinition { error: None }, "26000000 00"),
+            (DaemonResponse::SetScriptedEffect { result: true }, "27000000 01"),
+            (DaemonResponse::GetStandardEffectRanges { ranges: vec![EffectParamRange { effect: s("wave"), param: s("direction"), min: 1, max: 2 }] }, "28000000 01000000000000000400000000000000776176650900000000000000646972656374696f6e0102"),
+            (DaemonResponse::GetHardwareStatus { device_present: true, write_failures: 0, total_write_failures: 3, rediscoveries: 1 }, "29000000 0100000000030000000000000001000000"),
+            (DaemonResponse::GetCpuTemperature { temp: Some(60.0), fahrenheit: false }, "2a000000 010000704200"),
+            (DaemonResponse::ListEffects { custom: vec![s("static")], standard: vec![s("wave")] }, "2b000000 010000000000000006000000000000007374617469630100000000000000040000000000000077617665"),
+            (DaemonResponse::GetEffectParamSpec { min_len: 3, max_len: None, fields: vec![s("red")] }, "2c000000 03000000000000000001000000000000000300000000000000726564"),
+            (DaemonResponse::SetKeyColors { result: true }, "2d000000 01"),
+            (DaemonResponse::SaveEffectProfile { result: true }, "2e000000 01"),
+            (DaemonResponse::LoadEffectProfile { result: false }, "2f000000 00"),
+            (DaemonResponse::ListEffectProfiles { names: vec![s("p")] }, "30000000 0100000000000000010000000000000070"),
+            (DaemonResponse::GetEffectStack { layers: vec![EffectInfo { name: s("Static"), key_mask_summary: 90 }] }, "31000000 010000000000000006000000000000005374617469635a"),
+            (DaemonResponse::RemoveEffectLayer { result: true }, "32000000 01"),
+            (DaemonResponse::PushEffect { result: true, error: None }, "33000000 0100"),
+            (DaemonResponse::SetLayerOpacity { result: true }, "34000000 01"),
+            (DaemonResponse::SetIdleTimeout { result: true }, "35000000 01"),
+            (DaemonResponse::GetFanRpmRange { min: 3500, max: 5000, supports_auto: true }, "36000000 ac0d00008813000001"),
+            (DaemonResponse::OnDevice { response: Box::new(DaemonResponse::GetDeviceName { name: s("Blade") }) }, "37000000 120000000500000000000000426c616465"),
+            (DaemonResponse::ListDevices { devices: vec![DeviceInfo { name: s("Blade"), has_fan_control: true, has_bho: false, has_logo_led: true, key_matrix: (6, 15) }] }, "38000000 01000000000000000500000000000000426c61646501000106000000000000000f00000000000000"),
+            (DaemonResponse::Handshake { daemon_version: s("0.2.0"), protocol_version: 15 }, "39000000 0500000000000000302e322e300f000000"),
+            (DaemonResponse::Error { code: ErrorCode::NotFound, message: s("e") }, "3a000000 03000000010000000000000065"),
+            (DaemonResponse::SetPerformancePreset { result: true, error: None, pwr: 1, cpu: 0, gpu: 0, fan_rpm: 0 }, "3b000000 010001000000000000"),
+            (DaemonResponse::GetBoostRange { cpu: BoostRange { supported: true, min: 0, max: 3, steps: vec![s("low")] }, gpu: BoostRange { supported: false, min: 0, max: 0, steps: vec![] } }, "3c000000 010003010000000000000003000000000000006c6f770000000000000000000000"),
+            (DaemonResponse::SetChargeLimit { result: true, error: None }, "3d000000 0100"),
+            (DaemonResponse::GetChargeLimit { percent: Some(80) }, "3e000000 0150"),
+            (DaemonResponse::GetBatteryStatus { percentage: 50.5, on_ac: true, charging: false }, "3f000000 00000000004049400100"),
+            (DaemonResponse::GetIdle { val: 5, timeout_ms: 0 }, "40000000 050000000000000000000000"),
+            (DaemonResponse::SetLogoLedMode { result: true, error: None }, "41000000 0100"),
+            (DaemonResponse::StepBrightness { result: true, brightness: 70 }, "42000000 0146"),
+            (DaemonResponse::SetKeyboardPower { result: true, error: None }, "43000000 0100"),
+            (DaemonResponse::GetFanControlMode { mode: FanControlMode::FirmwareAuto }, "44000000 02000000"),
+            (DaemonResponse::SetFanControlMode { result: true, error: None }, "45000000 0100"),
+            (DaemonResponse::GetAnimationRate { fps: 10 }, "46000000 0a000000"),
+            (DaemonResponse::SetAnimationRate { result: true, fps: 60 }, "47000000 013c000000"),
+        ]
+    }
+
+    /// Protocol versions and a fingerprint of the snapshots at that version,
+    /// oldest first. Changed snapshots need a new entry with a new PROTOCOL_VERSION
+    const SNAPSHOT_HISTORY: &[(u32, u64)] = &[
+        (15, 0x4579bd00a296fa1b),
+        (16, 0xd1be2f84753419c3),
+    ];
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn check_snapshots<T: Serialize + std::fmt::Debug>(snapshots: Vec<(T, &'static str)>) {
+        for (index, (value, hex)) in snapshots.iter().enumerate() {
+            let encoded = bincode::serialize(value).unwrap();
+            assert_eq!(to_hex(&encoded), hex.replace(' ', ""), "encoding of {:?} changed", value);
+            assert_eq!(u32::from_le_bytes([encoded[0], encoded[1], encoded[2], encoded[3]]) as usize, index,
+                "{:?} moved, variants must stay in place", value);
+        }
+    }
+
+    /// FNV-1a over every snapshot
+    fn snapshot_fingerprint() -> u64 {
+        command_snapshots().iter().map(|(_, hex)| *hex)
+            .chain(response_snapshots().iter().map(|(_, hex)| *hex))
+            .flat_map(|hex| hex.bytes().chain(std::iter::once(b'\n')))
+            .fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+    }
+
+    #[test]
+    fn commands_match_snapshots() {
+        check_snapshots(command_snapshots());
+    }
+
+    #[test]
+    fn responses_match_snapshots() {
+        check_snapshots(response_snapshots());
+    }
+
+    #[test]
+    fn every_variant_has_a_snapshot() {
+        // The variant index one past the last snapshot must not exist
+        let next = (command_snapshots().len() as u32).to_le_bytes();
+        let err = bincode::deserialize::<DaemonCommand>(&next).unwrap_err().to_string();
+        assert!(err.contains("variant index"), "DaemonCommand has variants without a snapshot: {}", err);
+        let next = (response_snapshots().len() as u32).to_le_bytes();
+        let err = bincode::deserialize::<DaemonResponse>(&next).unwrap_err().to_string();
+        assert!(err.contains("variant index"), "DaemonResponse has variants without a snapshot: {}", err);
+    }
+
+    #[test]
+    fn snapshot_changes_bump_protocol_version() {
+        assert!(SNAPSHOT_HISTORY.windows(2).all(|w| w[0].0 < w[1].0), "history versions must increase");
+        let (version, fingerprint) = *SNAPSHOT_HISTORY.last().unwrap();
+        assert_eq!(
+            fingerprint, snapshot_fingerprint(),
+            "the snapshots changed: bump PROTOCOL_VERSION and add ({}, {:#x}) to SNAPSHOT_HISTORY",
+            PROTOCOL_VERSION + 1, snapshot_fingerprint()
+        );
+        assert_eq!(version, PROTOCOL_VERSION, "the snapshots at PROTOCOL_VERSION must be the last history entry");
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let mut sock = Cursor::new(Vec::new());
+        write_frame(&mut sock, b"first").unwrap();
+        write_frame(&mut sock, b"").unwrap();
+        assert_eq!(&sock.get_ref()[..4], &5u32.to_le_bytes());
+
+        sock.set_position(0);
+        assert_eq!(read_frame(&mut sock).unwrap(), b"first");
+        assert_eq!(read_frame(&mut sock).unwrap(), b"");
+        assert_eq!(read_frame(&mut sock).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn frame_in_pieces() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, b"in pieces").unwrap();
+        let (head, tail) = frame.split_at(6);
+        let mut sock = Cursor::new(head.to_vec()).chain(Cursor::new(tail.to_vec()));
+        assert_eq!(read_frame(&mut sock).unwrap(), b"in pieces");
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, b"truncated").unwrap();
+        frame.truncate(frame.len() - 1);
+        assert_eq!(read_frame(&mut Cursor::new(frame)).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        // Not even the whole length
+        assert_eq!(read_frame(&mut Cursor::new(vec![9, 0])).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut sock = Cursor::new(Vec::new());
+        let err = write_frame(&mut sock, &vec![0; MAX_FRAME_SIZE + 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(sock.get_ref().is_empty(), "nothing is written for a rejected frame");
+        write_frame(&mut sock, &vec![0; MAX_FRAME_SIZE]).unwrap();
+
+        // The length is checked before reading or allocating the payload
+        let len = (MAX_FRAME_SIZE as u32 + 1).to_le_bytes();
+        let err = read_frame(&mut Cursor::new(len.to_vec())).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
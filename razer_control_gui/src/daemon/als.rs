@@ -0,0 +1,33 @@
+use std::fs;
+
+const IIO_DEVICES_DIR: &str = "/sys/bus/iio/devices";
+
+/// Finds the first IIO device that measures illuminance, usually
+/// /sys/bus/iio/devices/iio:device0
+pub fn find_sensor() -> Option<String> {
+    let mut devices: Vec<String> = fs::read_dir(IIO_DEVICES_DIR).ok()?
+        .flatten()
+        .map(|e| format!("{}/{}", IIO_DEVICES_DIR, e.file_name().to_string_lossy()))
+        .collect();
+    devices.sort();
+    devices.into_iter().find(|dir| {
+        fs::metadata(format!("{}/in_illuminance_input", dir)).is_ok()
+            || fs::metadata(format!("{}/in_illuminance_raw", dir)).is_ok()
+    })
+}
+
+fn read_value(path: String) -> Option<f32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Reads the ambient light in lux. Sensors without a processed value report
+/// a raw one, which is converted with their offset and scale
+pub fn read_lux(sensor: &str) -> Option<f32> {
+    if let Some(lux) = read_value(format!("{}/in_illuminance_input", sensor)) {
+        return Some(lux);
+    }
+    let raw = read_value(format!("{}/in_illuminance_raw", sensor))?;
+    let offset = read_value(format!("{}/in_illuminance_offset", sensor)).unwrap_or(0.0);
+    let scale = read_value(format!("{}/in_illuminance_scale", sensor)).unwrap_or(1.0);
+    Some((raw + offset) * scale)
+}
@@ -0,0 +1,35 @@
+use std::{fs, io};
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+const THRESHOLD_FILE: &str = "charge_control_end_threshold";
+
+/// Finds the charge threshold file of the first battery that has one,
+/// usually /sys/class/power_supply/BAT0/charge_control_end_threshold
+fn find_threshold_file() -> Option<String> {
+    let mut batteries: Vec<String> = fs::read_dir(POWER_SUPPLY_DIR).ok()?
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("BAT"))
+        .collect();
+    batteries.sort();
+    batteries.into_iter()
+        .map(|name| format!("{}/{}/{}", POWER_SUPPLY_DIR, name, THRESHOLD_FILE))
+        .find(|path| fs::metadata(path).is_ok())
+}
+
+/// Whether the kernel exposes a charge threshold
+pub fn is_supported() -> bool {
+    find_threshold_file().is_some()
+}
+
+/// Reads the percentage the battery stops charging at
+pub fn read_limit() -> Option<u8> {
+    let path = find_threshold_file()?;
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+pub fn write_limit(percent: u8) -> io::Result<()> {
+    let path = find_threshold_file()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No charge threshold file"))?;
+    fs::write(path, percent.to_string())
+}
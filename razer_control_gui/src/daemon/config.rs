@@ -2,8 +2,16 @@ use serde::{Deserialize, Serialize};
 use std::{fs, fs::File, io, env};
 use std::io::prelude::*;
 
+const DATA_DIR: &str = "/.local/share/razercontrol/";
 const SETTINGS_FILE: &str = "/.local/share/razercontrol/daemon.json";
 const EFFECTS_FILE: &str = "/.local/share/razercontrol/effects.json";
+const USER_SETTINGS_FILE: &str = "/.local/share/razercontrol/config.toml";
+const PROFILES_DIR: &str = "/.local/share/razercontrol/profiles/";
+const SCRIPTED_EFFECTS_DIR: &str = "/.local/share/razercontrol/scripted/";
+/// Shortest idle timeout, anything below is raised to it so the idle watch
+/// doesn't fire (and get re-armed) every few moments
+pub const MIN_IDLE_TIMEOUT_MS: u64 = 5000;
+pub const DEFAULT_AC_EVENT_SCRIPT: &str = "power_state_handler.sh";
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct PowerConfig {
@@ -15,6 +23,14 @@ pub struct PowerConfig {
     pub logo_state: u8,
     pub screensaver: bool, // turno of keyboard light if screen is blank
     pub idle: u32,
+    #[serde(default)]
+    pub idle_ms: u64, // idle timeout in milliseconds, overrides idle (minutes) when not 0
+    #[serde(default)]
+    pub fan_ceiling: i32, // max RPM set by the temperature monitor, 0 = no limit
+    #[serde(default)]
+    pub fan_ceiling_strict: bool, // keep the ceiling even at critical temperatures
+    #[serde(default)]
+    pub logo_brightness: Option<u8>, // 0-255, None = left as the firmware has it
 }
 
 impl PowerConfig {
@@ -28,17 +44,217 @@ impl PowerConfig {
             logo_state: 0,
             screensaver: false,
             idle: 0,
+            idle_ms: 0,
+            fan_ceiling: 0,
+            fan_ceiling_strict: false,
+            logo_brightness: None,
         }
     }
 }
 
+/// Which temperature drives the fan control
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FanControlSource {
+    Cpu,
+    Gpu,
+    Max, // the hotter of the two
+}
+
+/// How the temperatures of several CPU packages (or cores) are combined
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuTempMode {
+    Max,
+    Average,
+}
+
+/// Who sets the fan speed, see comms::FanControlMode
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FanControlMode {
+    Daemon,
+    Manual,
+    Auto,
+}
+
+/// Unit temperatures are shown in. Thresholds are always in Celsius
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+pub enum TemperatureUnit {
+    #[serde(rename = "C")]
+    Celsius,
+    #[serde(rename = "F")]
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    /// Converts a temperature in Celsius to this unit
+    pub fn to_display(self, temp_c: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => temp_c,
+            TemperatureUnit::Fahrenheit => temp_c * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    /// Formats a temperature in Celsius in this unit, e.g. "122.0°F"
+    pub fn format(self, temp_c: f32) -> String {
+        match self {
+            TemperatureUnit::Celsius => format!("{:.1}°C", temp_c),
+            TemperatureUnit::Fahrenheit => format!("{:.1}°F", self.to_display(temp_c)),
+        }
+    }
+}
+
+/// Temperature thresholds (Celsius) and fan speeds (RPM, 0 = auto) used by
+/// the temperature based fan control
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct TemperatureProfile {
+    pub temp_low: f32,      // Below this: fan_auto
+    pub temp_medium: f32,   // Below this: fan_low
+    pub temp_high: f32,     // Below this: fan_medium
+    pub temp_critical: f32, // Below this: fan_high, above: fan_max
+    pub fan_auto: i32,
+    pub fan_low: i32,
+    pub fan_medium: i32,
+    pub fan_high: i32,
+    pub fan_max: i32,
+    pub fan_curve: Vec<(f32, i32)>, // (temperature, rpm) points, replaces the tiers if not empty
+    pub hysteresis: f32, // degrees below a threshold before stepping down a tier
+    pub fan_control_source: FanControlSource,
+    pub cpu_temp_mode: CpuTempMode,
+    pub temperature_unit: TemperatureUnit, // logs and GetCpuTemperature
+    pub smoothing_samples: usize, // the fan follows the average of this many readings, 1 = no smoothing
+    pub safety_temp: f32, // at or above this the fan runs at least at safety_min_rpm, whatever the tiers or curve say
+    pub safety_min_rpm: i32, // 0 disables the safety floor
+    pub emergency_temp: f32, // at or above this the power mode drops to emergency_mode, 0 = disabled
+    pub emergency_recovery_temp: f32, // the previous power mode comes back below this
+    pub emergency_mode: (u8, u8, u8), // power mode, cpu boost, gpu boost
+}
+
+impl Default for TemperatureProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemperatureProfile {
+    pub fn new() -> TemperatureProfile {
+        TemperatureProfile {
+            temp_low: 50.0,
+            temp_medium: 65.0,
+            temp_high: 75.0,
+            temp_critical: 85.0,
+            fan_auto: 0,
+            fan_low: 2000,
+            fan_medium: 3500,
+            fan_high: 4500,
+            fan_max: 5500,
+            fan_curve: vec![],
+            hysteresis: 3.0,
+            fan_control_source: FanControlSource::Cpu,
+            cpu_temp_mode: CpuTempMode::Max,
+            temperature_unit: TemperatureUnit::Celsius,
+            smoothing_samples: 3,
+            safety_temp: 90.0,
+            safety_min_rpm: 3500,
+            emergency_temp: 95.0,
+            emergency_recovery_temp: 85.0,
+            emergency_mode: (0, 0, 0), // balanced
+        }
+    }
+}
+
+/// How the keyboard brightness follows the ambient light sensor, see
+/// `Configuration::auto_brightness`
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(default)]
+pub struct AmbientLightProfile {
+    pub min_brightness: u8, // percent in the dark
+    pub max_brightness: u8, // percent at max_lux and above
+    pub max_lux: f32,
+    pub smoothing: f32, // weight of a new reading, 0.0 - 1.0, lower is steadier
+    pub manual_grace_s: u64, // seconds auto brightness pauses after the brightness is set by hand
+}
+
+impl Default for AmbientLightProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AmbientLightProfile {
+    pub fn new() -> AmbientLightProfile {
+        AmbientLightProfile {
+            min_brightness: 10,
+            max_brightness: 100,
+            max_lux: 400.0,
+            smoothing: 0.2,
+            manual_grace_s: 300,
+        }
+    }
+
+    /// Brightness (percent) for a light level. Brighter surroundings need a
+    /// brighter keyboard to stay readable
+    pub fn get_brightness(&self, lux: f32) -> u8 {
+        let ratio = (lux / self.max_lux.max(1.0)).clamp(0.0, 1.0);
+        let min = self.min_brightness.min(100) as f32;
+        let max = (self.max_brightness.min(100) as f32).max(min);
+        (min + (max - min) * ratio).round() as u8
+    }
+}
+
+/// What to do with the keyboard light when the display power save mode changes
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+pub enum DisplayPowerAction {
+    Ignore,
+    LightOff,
+    Restore,
+}
+
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Configuration {
     pub power: [PowerConfig; 2],
     pub sync: bool, // sync light settings between ac and battery
     pub no_light: f64, // no light bellow this percentage of battery
     pub standard_effect: u8,
     pub standard_effect_params: Vec<u8>,
+    pub ac_debounce_ms: u64, // only react to AC changes that stay stable this long
+    pub wallpaper_path: Option<String>, // used when the desktop wallpaper can't be queried
+    pub wallpaper_follow: bool, // keep the keyboard colour in sync with the wallpaper
+    pub power_mode_colour_sync: bool, // show the active power mode as a keyboard colour
+    pub power_mode_colour_overlay: bool, // only light the top row instead of the whole keyboard
+    pub power_mode_colours: [[u8; 3]; 5], // indexed by power mode
+    pub display_power_actions: [DisplayPowerAction; 4], // indexed by PowerSaveMode (on, standby, suspend, off)
+    pub temperature: TemperatureProfile,
+    pub power_presets_enabled: bool, // apply the presets below when the AC state changes
+    pub on_ac: (u8, u8, u8), // power mode, cpu boost, gpu boost
+    pub on_battery: (u8, u8, u8),
+    pub ac_event_script: Option<String>, // run on AC changes, absolute or relative to $HOME, None = disabled
+    pub battery_low: f64,  // percentage, battery_event_script runs with "low" when dropping to it
+    pub battery_full: f64, // percentage, battery_event_script runs with "full" when charging to it
+    pub battery_event_script: Option<String>, // absolute or relative to $HOME, None = disabled
+    pub idle_brightness: u8, // percent the keyboard dims to on idle, 0 turns it off
+    pub charge_limit: Option<u8>, // percent the battery stops charging at, None = untouched
+    pub bho: Option<(bool, u8)>, // battery health optimizer (is_on, threshold), None = untouched
+    pub watch_config: bool, // reload the settings when config.toml changes
+    #[serde(alias = "off_on_exit")]
+    pub exit_light_off: bool, // turn the keyboard light off on exit instead of restoring the standard effect
+    pub brightness_step: u8, // percent StepBrightness changes the brightness by
+    pub ac_brightness: bool, // apply the brightness stored for the AC state when plugging in or out
+    pub effect_fade_ms: u32, // crossfade when SetEffect replaces the effect, 0 = instant
+    pub animation_fps: u32, // frames per second of keyboard animations
+    pub fan_control_mode: FanControlMode, // the temperature monitor only sets the fan in daemon mode
+    pub manual_fan_timeout_s: u64, // manual mode goes back to daemon mode after this long, 0 = never
+    pub auto_brightness: bool, // follow the ambient light sensor, if there is one
+    pub ambient_light: AmbientLightProfile,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Configuration {
@@ -48,12 +264,52 @@ impl Configuration {
             sync: false,
             no_light: 0.0,
             standard_effect: 0, // off
-            standard_effect_params: vec![]
+            standard_effect_params: vec![],
+            ac_debounce_ms: 500,
+            wallpaper_path: None,
+            wallpaper_follow: false,
+            power_mode_colour_sync: false,
+            power_mode_colour_overlay: false,
+            power_mode_colours: [
+                [0, 255, 0],   // balanced
+                [255, 0, 0],   // gaming
+                [0, 0, 255],   // creator
+                [255, 255, 255],
+                [255, 128, 0], // custom
+            ],
+            display_power_actions: [
+                DisplayPowerAction::Restore,
+                DisplayPowerAction::LightOff,
+                DisplayPowerAction::LightOff,
+                DisplayPowerAction::LightOff,
+            ],
+            temperature: TemperatureProfile::new(),
+            power_presets_enabled: false,
+            on_ac: (1, 2, 2),      // gaming
+            on_battery: (0, 1, 0), // balanced
+            ac_event_script: Some(String::from(DEFAULT_AC_EVENT_SCRIPT)),
+            battery_low: 15.0,
+            battery_full: 95.0,
+            battery_event_script: None,
+            idle_brightness: 0,
+            charge_limit: None,
+            bho: None,
+            watch_config: false,
+            exit_light_off: false,
+            brightness_step: 10,
+            ac_brightness: false,
+            effect_fade_ms: 0,
+            animation_fps: crate::kbd::ANIMATION_FPS,
+            fan_control_mode: FanControlMode::Daemon,
+            manual_fan_timeout_s: 0,
+            auto_brightness: false,
+            ambient_light: AmbientLightProfile::new(),
         };
     }
 
     pub fn write_to_file(&mut self) -> io::Result<()> {
         let j: String = serde_json::to_string_pretty(&self)?;
+        fs::create_dir_all(get_home_directory() + DATA_DIR)?;
         File::create(get_home_directory() + SETTINGS_FILE)?.write_all(j.as_bytes())?;
         Ok(())
     }
@@ -64,6 +320,52 @@ impl Configuration {
         Ok(res)
     }
 
+    /// Overrides the loaded settings with the ones set in config.toml
+    pub fn apply_user_settings(&mut self, settings: &UserSettings) {
+        if let Some(temperature) = &settings.temperature {
+            self.temperature = temperature.clone();
+        }
+        if let Some(idle_ms) = settings.idle_ms_ac {
+            self.power[1].idle_ms = idle_ms;
+        }
+        if let Some(idle_ms) = settings.idle_ms_battery {
+            self.power[0].idle_ms = idle_ms;
+        }
+        if let Some(enabled) = settings.power_presets_enabled {
+            self.power_presets_enabled = enabled;
+        }
+        if let Some(on_ac) = settings.on_ac {
+            self.on_ac = on_ac;
+        }
+        if let Some(on_battery) = settings.on_battery {
+            self.on_battery = on_battery;
+        }
+        if let Some(script) = &settings.ac_event_script {
+            self.ac_event_script = non_empty(script);
+        }
+        if let Some(script) = &settings.battery_event_script {
+            self.battery_event_script = non_empty(script);
+        }
+        if let Some(watch) = settings.watch_config {
+            self.watch_config = watch;
+        }
+        if let Some(auto) = settings.auto_brightness {
+            self.auto_brightness = auto;
+        }
+        if let Some(ambient_light) = settings.ambient_light {
+            self.ambient_light = ambient_light;
+        }
+        if let Some(off) = settings.off_on_exit {
+            self.exit_light_off = off;
+        }
+        if let Some(timeout) = settings.manual_fan_timeout_s {
+            self.manual_fan_timeout_s = timeout;
+        }
+        if let Some(apply) = settings.ac_brightness {
+            self.ac_brightness = apply;
+        }
+    }
+
     pub fn write_effects_save(json: serde_json::Value) -> io::Result<()> {
         let j: String = serde_json::to_string_pretty(&json)?;
         File::create(get_home_directory() + EFFECTS_FILE)?.write_all(j.as_bytes())?;
@@ -75,8 +377,256 @@ impl Configuration {
         let res: serde_json::Value = serde_json::from_str(str.as_str())?;
         Ok(res)
     }
+
+    pub fn read_effects_profile(name: &str) -> io::Result<serde_json::Value> {
+        let str = fs::read_to_string(get_profile_path(name)?)?;
+        let res: serde_json::Value = serde_json::from_str(str.as_str())?;
+        Ok(res)
+    }
+
+    pub fn write_effects_profile(name: &str, json: serde_json::Value) -> io::Result<()> {
+        let j: String = serde_json::to_string_pretty(&json)?;
+        let path = get_profile_path(name)?;
+        fs::create_dir_all(get_home_directory() + PROFILES_DIR)?;
+        File::create(path)?.write_all(j.as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the names of the saved effect profiles, sorted
+    pub fn list_effects_profiles() -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(get_home_directory() + PROFILES_DIR) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut names: Vec<String> = vec![];
+        for entry in entries {
+            let file_name = entry?.file_name();
+            if let Some(name) = file_name.to_str().and_then(|n| n.strip_suffix(".json")) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn write_effect_definition(name: &str, json: &str) -> io::Result<()> {
+        fs::create_dir_all(get_home_directory() + SCRIPTED_EFFECTS_DIR)?;
+        File::create(get_named_path(SCRIPTED_EFFECTS_DIR, name)?)?.write_all(json.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn read_effect_definition(name: &str) -> io::Result<String> {
+        fs::read_to_string(get_named_path(SCRIPTED_EFFECTS_DIR, name)?)
+    }
+}
+
+/// Hand edited settings from ~/.local/share/razercontrol/config.toml. Every
+/// field is optional, the ones left out keep the value from daemon.json (or
+/// the built-in default). Precedence, highest first:
+///
+/// 1. environment variables (RAZERCONTROL_AC_EVENT_SCRIPT,
+///    RAZERCONTROL_BATTERY_EVENT_SCRIPT, RAZERCONTROL_IDLE_MS,
+///    RAZERCONTROL_POWER_PRESETS)
+/// 2. config.toml
+/// 3. daemon.json, then the built-in defaults
+///
+/// A partial [temperature] table fills the missing fields with the defaults.
+/// An empty script path disables the script
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct UserSettings {
+    pub temperature: Option<TemperatureProfile>,
+    pub idle_ms_ac: Option<u64>,
+    pub idle_ms_battery: Option<u64>,
+    pub power_presets_enabled: Option<bool>,
+    pub on_ac: Option<(u8, u8, u8)>, // power mode, cpu boost, gpu boost
+    pub on_battery: Option<(u8, u8, u8)>,
+    pub ac_event_script: Option<String>,
+    pub battery_event_script: Option<String>,
+    pub watch_config: Option<bool>,
+    pub auto_brightness: Option<bool>,
+    pub ambient_light: Option<AmbientLightProfile>,
+    pub off_on_exit: Option<bool>,
+    pub manual_fan_timeout_s: Option<u64>,
+    pub ac_brightness: Option<bool>,
+}
+
+impl UserSettings {
+    pub fn get_path() -> String {
+        get_home_directory() + USER_SETTINGS_FILE
+    }
+
+    /// Reads config.toml with the environment overrides applied. A missing
+    /// file only leaves the environment overrides
+    pub fn read_from_file() -> io::Result<UserSettings> {
+        let mut settings = match fs::read_to_string(UserSettings::get_path()) {
+            Ok(str) => UserSettings::parse(&str)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => UserSettings::default(),
+            Err(e) => return Err(e),
+        };
+        settings.apply_env();
+        Ok(settings)
+    }
+
+    pub fn parse(str: &str) -> io::Result<UserSettings> {
+        toml::from_str(str).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn apply_env(&mut self) {
+        if let Ok(script) = env::var("RAZERCONTROL_AC_EVENT_SCRIPT") {
+            self.ac_event_script = Some(script);
+        }
+        if let Ok(script) = env::var("RAZERCONTROL_BATTERY_EVENT_SCRIPT") {
+            self.battery_event_script = Some(script);
+        }
+        if let Some(idle_ms) = env::var("RAZERCONTROL_IDLE_MS").ok().and_then(|v| v.parse().ok()) {
+            self.idle_ms_ac = Some(idle_ms);
+            self.idle_ms_battery = Some(idle_ms);
+        }
+        if let Some(enabled) = env::var("RAZERCONTROL_POWER_PRESETS").ok().and_then(|v| v.parse().ok()) {
+            self.power_presets_enabled = Some(enabled);
+        }
+    }
+}
+
+fn non_empty(str: &str) -> Option<String> {
+    if str.is_empty() { None } else { Some(str.to_string()) }
+}
+
+/// Returns the file a named effect profile is stored in. Profiles use the
+/// same format as the effects save.
+fn get_profile_path(name: &str) -> io::Result<String> {
+    get_named_path(PROFILES_DIR, name)
+}
+
+/// Returns the json file for `name` in one of the data directories. Names are
+/// limited to letters, digits, '-' and '_' so they can't escape the directory
+fn get_named_path(dir: &str, name: &str) -> io::Result<String> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid name: {:?}", name)));
+    }
+    Ok(get_home_directory() + dir + name + ".json")
 }
 
 fn get_home_directory() -> String {
     env::var("HOME").expect("The \"HOME\" environment variable must be set to a valid directory")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_temperatures() {
+        assert_eq!(TemperatureUnit::Celsius.to_display(37.5), 37.5);
+        assert_eq!(TemperatureUnit::Fahrenheit.to_display(0.0), 32.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.to_display(100.0), 212.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.to_display(-40.0), -40.0);
+    }
+
+    #[test]
+    fn formats_temperatures() {
+        assert_eq!(TemperatureUnit::Celsius.format(50.0), "50.0°C");
+        assert_eq!(TemperatureUnit::Celsius.format(72.26), "72.3°C");
+        assert_eq!(TemperatureUnit::Fahrenheit.format(50.0), "122.0°F");
+        assert_eq!(TemperatureUnit::Fahrenheit.format(-17.5), "0.5°F");
+    }
+
+    /// The example from the README
+    const FULL_SETTINGS: &str = r#"
+idle_ms_ac = 300000
+idle_ms_battery = 60000
+power_presets_enabled = true
+on_ac = [1, 2, 2]
+on_battery = [0, 1, 0]
+ac_event_script = "power_state_handler.sh"
+battery_event_script = ""
+watch_config = true
+auto_brightness = true
+off_on_exit = true
+manual_fan_timeout_s = 3600
+ac_brightness = true
+
+[temperature]
+temp_high = 80.0
+fan_max = 5000
+cpu_temp_mode = "average"
+smoothing_samples = 3
+temperature_unit = "F"
+
+[ambient_light]
+max_lux = 200.0
+"#;
+
+    #[test]
+    fn applies_a_full_settings_file() {
+        let settings = UserSettings::parse(FULL_SETTINGS).unwrap();
+        let mut config = Configuration::new();
+        config.battery_event_script = Some(String::from("battery.sh"));
+        config.apply_user_settings(&settings);
+
+        assert_eq!(config.power[1].idle_ms, 300000);
+        assert_eq!(config.power[0].idle_ms, 60000);
+        assert!(config.power_presets_enabled);
+        assert_eq!(config.on_ac, (1, 2, 2));
+        assert_eq!(config.on_battery, (0, 1, 0));
+        assert_eq!(config.ac_event_script.as_deref(), Some("power_state_handler.sh"));
+        assert_eq!(config.battery_event_script, None, "an empty path disables the script");
+        assert!(config.watch_config);
+        assert!(config.auto_brightness);
+        assert!(config.exit_light_off);
+        assert_eq!(config.manual_fan_timeout_s, 3600);
+        assert!(config.ac_brightness);
+
+        // Partial tables keep the defaults for the rest
+        assert_eq!(config.temperature.temp_high, 80.0);
+        assert_eq!(config.temperature.fan_max, 5000);
+        assert!(config.temperature.cpu_temp_mode == CpuTempMode::Average);
+        assert_eq!(config.temperature.smoothing_samples, 3);
+        assert!(config.temperature.temperature_unit == TemperatureUnit::Fahrenheit);
+        assert_eq!(config.temperature.temp_low, TemperatureProfile::new().temp_low);
+        assert_eq!(config.ambient_light.max_lux, 200.0);
+        assert_eq!(config.ambient_light.min_brightness, AmbientLightProfile::new().min_brightness);
+    }
+
+    #[test]
+    fn missing_keys_keep_the_config() {
+        let mut config = Configuration::new();
+        config.watch_config = true;
+        config.on_ac = (4, 3, 2);
+        config.apply_user_settings(&UserSettings::parse("").unwrap());
+        assert!(config.watch_config);
+        assert_eq!(config.on_ac, (4, 3, 2));
+        assert_eq!(config.ac_event_script.as_deref(), Some(DEFAULT_AC_EVENT_SCRIPT));
+    }
+
+    #[test]
+    fn ignores_unknown_keys() {
+        let settings = UserSettings::parse("no_such_setting = 1\nwatch_config = true\n[no_such_table]\nkey = \"value\"\n").unwrap();
+        assert_eq!(settings.watch_config, Some(true));
+    }
+
+    #[test]
+    fn off_on_exit_alias() {
+        let settings = UserSettings::parse("off_on_exit = true").unwrap();
+        let mut config = Configuration::new();
+        config.apply_user_settings(&settings);
+        assert!(config.exit_light_off);
+
+        // daemon.json files from before the rename use the old name
+        let config: Configuration = serde_json::from_str(r#"{"off_on_exit": true}"#).unwrap();
+        assert!(config.exit_light_off);
+        let config: Configuration = serde_json::from_str(r#"{"exit_light_off": true}"#).unwrap();
+        assert!(config.exit_light_off);
+    }
+
+    #[test]
+    fn bad_types_are_an_error() {
+        for bad in ["watch_config = \"yes\"", "idle_ms_ac = -1", "on_ac = [1, 2]", "temperature = 5", "[temperature]\ntemp_high = \"hot\""] {
+            let err = UserSettings::parse(bad).err().unwrap_or_else(|| panic!("{} was accepted", bad));
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -1,31 +1,133 @@
-use std::io::{Read, Write};
+use std::convert::TryFrom;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
-use std::sync::Mutex;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
 use std::thread::{self, JoinHandle};
 use std::time;
 
 use log::*;
 use lazy_static::lazy_static;
 use signal_hook::iterator::Signals;
-use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
 use dbus::blocking::Connection;
 use dbus::{Message, arg};
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
 
 #[path = "../comms.rs"]
 mod comms;
 mod config;
 mod kbd;
 mod device;
+mod fan;
 mod battery;
 mod dbus_mutter_displayconfig;
 mod dbus_mutter_idlemonitor;
 mod screensaver;
 mod login1;
+mod wallpaper;
+mod input;
+mod dbus_service;
+mod charge;
+mod als;
 
 use crate::kbd::Effect;
 
+/// What happened while restoring the saved state at startup
+#[derive(Default)]
+struct StartupReport {
+    effects_restored: bool,
+    settings_restored: bool,
+    ac_online: Option<bool>,
+    errors: Vec<String>,
+}
+
+/// Maximum number of commands in a batch, responses must fit in the client's buffer
+const MAX_BATCH_SIZE: usize = 32;
+
+/// Failed frames in a row before the device is opened again
+const REDISCOVER_AFTER_FAILURES: u32 = 20;
+const MAX_ANIMATION_BACKOFF_MS: u64 = 2000;
+/// How often the animator looks at the keyboard while only static effects
+/// are active and nothing woke it, catches changes made without a wake up
+const STATIC_EFFECT_RECHECK: time::Duration = time::Duration::from_secs(1);
+/// How often to look for the laptop while none is attached
+const DEVICE_POLL_INTERVAL: time::Duration = time::Duration::from_secs(5);
+/// How often config.toml is checked for changes, when watched
+const CONFIG_POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+/// How long config.toml has to stay unchanged before it is reloaded, so an
+/// editor writing a temporary file and renaming it only causes one reload
+const CONFIG_RELOAD_DEBOUNCE: time::Duration = time::Duration::from_secs(2);
+
+/// Health of the keyboard writes done by the animator
+#[derive(Default)]
+struct KeyboardWriteStatus {
+    consecutive_failures: u32,
+    total_failures: u64,
+    rediscoveries: u32,
+}
+
+/// Last battery state reported by UPower, kept by the battery monitor so
+/// clients don't need a D-Bus round trip
+#[derive(Default, Clone, Copy)]
+struct BatteryStatus {
+    percentage: f64,
+    on_ac: bool,
+    charging: bool,
+}
+
+/// SetBrightness commands that arrived within BRIGHTNESS_COALESCE_WINDOW of
+/// the last write, waiting to be written together
+#[derive(Default)]
+struct PendingBrightness {
+    last_write: Option<time::Instant>,
+    /// Latest brightness for each (device index, AC state)
+    values: Vec<((usize, usize), u8)>,
+    /// Wakes the brightness writer when the first value is queued
+    wake: Option<mpsc::Sender<()>>,
+}
+
+/// Shortest time between two brightness writes, a slider drag sends far more
+/// SetBrightness commands than the keyboard needs
+const BRIGHTNESS_COALESCE_WINDOW: time::Duration = time::Duration::from_millis(50);
+
+/// UPower device state meaning the battery is charging
+const UPOWER_STATE_CHARGING: u32 = 1;
+
+/// Process and user id of a connected client
+#[derive(Clone, Copy)]
+struct PeerCredentials {
+    pid: i32,
+    uid: u32,
+}
+
+/// When and by whom the current effect was set
+struct EffectProvenance {
+    timestamp: u64,
+    peer: Option<PeerCredentials>,
+}
+
 lazy_static! {
+    static ref KEYBOARD_WRITE_STATUS: Mutex<KeyboardWriteStatus> = Mutex::new(KeyboardWriteStatus::default());
+    static ref EFFECT_PROVENANCE: Mutex<Option<EffectProvenance>> = Mutex::new(None);
+    static ref STARTUP_REPORT: Mutex<StartupReport> = Mutex::new(StartupReport::default());
+    static ref BATTERY_STATUS: Mutex<BatteryStatus> = Mutex::new(BatteryStatus::default());
+    static ref PENDING_BRIGHTNESS: Mutex<PendingBrightness> = Mutex::new(PendingBrightness::default());
+    /// Last temperature the fan control acted on
+    static ref LAST_FAN_TEMP: Mutex<Option<f32>> = Mutex::new(None);
+    /// Fan speed the temperature monitor wants and the one it applied after the ceiling
+    static ref FAN_CONTROL: Mutex<(i32, i32)> = Mutex::new((0, 0));
+    /// Kernel thread ids of the animator and monitor threads, with their niceness
+    static ref WORKER_THREADS: Mutex<(Vec<libc::pid_t>, i32)> = Mutex::new((vec![], 0));
     static ref EFFECT_MANAGER: Mutex<kbd::EffectManager> = Mutex::new(kbd::EffectManager::new());
+    /// Wakes the keyboard animator while it waits on static effects, true
+    /// also makes it write the next frame even if it did not change
+    static ref ANIMATOR_WAKE: Mutex<Option<mpsc::Sender<bool>>> = Mutex::new(None);
+    /// Effect managers of the devices after the first one, see effect_manager
+    static ref EXTRA_EFFECT_MANAGERS: Mutex<Vec<&'static Mutex<kbd::EffectManager>>> = Mutex::new(vec![]);
+    /// (AC online, power mode) changes to broadcast on the session bus
+    static ref POWER_STATE_SIGNALS: Mutex<Option<mpsc::Sender<(bool, u8)>>> = Mutex::new(None);
     // static ref CONFIG: Mutex<config::Configuration> = {
         // match config::Configuration::read_from_config() {
             // Ok(c) => Mutex::new(c),
@@ -35,48 +137,110 @@ lazy_static! {
     static ref DEV_MANAGER: Mutex<device::DeviceManager> = {
         match device::DeviceManager::read_laptops_file() {
             Ok(c) => Mutex::new(c),
-            Err(_) => Mutex::new(device::DeviceManager::new()),
+            Err(e) => {
+                eprintln!("Could not read the supported devices: {}", e);
+                Mutex::new(device::DeviceManager::new())
+            }
         }
     };
 }
 
+/// Returns the effect manager of a device. The first device uses EFFECT_MANAGER,
+/// the others get their own manager the first time they are used. Managers are
+/// never freed, there is at most one per device index ever seen
+fn effect_manager(index: usize) -> &'static Mutex<kbd::EffectManager> {
+    if index == 0 {
+        return &EFFECT_MANAGER;
+    }
+    let mut extra = lock_or_recover(&EXTRA_EFFECT_MANAGERS);
+    while extra.len() < index {
+        extra.push(Box::leak(Box::new(Mutex::new(kbd::EffectManager::new()))));
+    }
+    extra[index - 1]
+}
+
 // Main function for daemon
 fn main() {
     setup_panic_hook();
     init_logging();
 
-    if let Ok(mut d) = DEV_MANAGER.lock() {
+    {
+        let mut d = lock_or_recover(&DEV_MANAGER);
         d.discover_devices();
         if let Some(laptop) = d.get_device() {
             println!("supported device: {:?}", laptop.get_name());
         } else {
-            println!("no supported device found");
-            std::process::exit(1);
+            // The hidraw node can show up late, the rediscovery task keeps looking
+            println!("no supported device found, waiting for one to appear");
         }
-    } else {
-        println!("error loading supported devices");
-        std::process::exit(1);
     }
 
 
-    if let Ok(mut d) = DEV_MANAGER.lock() {
+    {
+        let mut d = lock_or_recover(&DEV_MANAGER);
         let dbus_system = Connection::new_system()
             .expect("failed to connect to D-Bus system bus");
         let proxy_ac = dbus_system.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/line_power_AC0", time::Duration::from_millis(5000));
         use battery::OrgFreedesktopUPowerDevice;
         if let Ok(online) = proxy_ac.online() {
             info!("AC0 online: {:?}", online);
+            let mut report = lock_or_recover(&STARTUP_REPORT);
+            report.ac_online = Some(online);
+            report.settings_restored = d.config_loaded;
+            if let Some(e) = d.config_error.as_ref() {
+                report.errors.push(e.clone());
+            }
+            let device_present = d.get_device().is_some();
+            if !device_present {
+                // The settings are restored by the rediscovery task once the device shows up
+                report.errors.push(device::DeviceError::NoDevice.to_string());
+            }
             d.set_ac_state(online);
-            d.restore_standard_effect();
-            if let Ok(json) = config::Configuration::read_effects_file() {
-                EFFECT_MANAGER.lock().unwrap().load_from_save(json);
+            if device_present {
+                if let Err(e) = d.restore_power_mode() {
+                    report.errors.push(format!("Could not restore the power mode: {}", e));
+                }
+                if !d.restore_bho() {
+                    report.errors.push(String::from("Could not restore the battery health optimizer"));
+                }
+            }
+            if let Err(e) = d.restore_charge_limit() {
+                report.errors.push(format!("Could not restore the charge limit: {}", e));
+            }
+            update_power_mode_colour(&mut d);
+            if let Some(fps) = d.get_animation_fps() {
+                lock_or_recover(&EFFECT_MANAGER).set_fps(fps);
+            }
+            let effects = config::Configuration::read_effects_file();
+            if let Ok(json) = effects {
+                if !lock_or_recover(&EFFECT_MANAGER).load_from_save(json) {
+                    report.errors.push(String::from("Some saved effects could not be restored"));
+                }
+                report.effects_restored = true;
             } else {
-                println!("No effects save, creating a new one");
-                // No effects found, start with a green static layer, just like synapse
-                EFFECT_MANAGER.lock().unwrap().push_effect(
-                    kbd::effects::Static::new(vec![0, 255, 0]), 
-                    [true; 90]
-                    );
+                if let Err(e) = effects {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        report.errors.push(format!("Error reading effects: {}", e));
+                    }
+                }
+                if d.has_standard_effect() {
+                    println!("No effects save, keeping the saved standard effect");
+                } else {
+                    println!("No effects save, creating a new one");
+                    // No effects found, start with a green static layer, just like synapse
+                    lock_or_recover(&EFFECT_MANAGER).push_effect(
+                        kbd::effects::Static::new(vec![0, 255, 0]), 
+                        [true; 90],
+                        1.0
+                        );
+                }
+            }
+            if d.has_standard_effect() && lock_or_recover(&EFFECT_MANAGER).get_stack().is_empty() {
+                // The standard effect restored above stays, the indicator doesn't cover it
+                lock_or_recover(&EFFECT_MANAGER).show_standard_effect();
+            }
+            if device_present && !d.restore_lighting() {
+                report.errors.push(String::from("Could not restore the logo state"));
             }
         } else {
             println!("error getting current power state");
@@ -85,9 +249,16 @@ fn main() {
     }
 
     start_keyboard_animator_task();
+    start_device_rediscovery_task();
     start_screensaver_monitor_task();
     start_battery_monitor_task();
     start_temperature_monitor_task();
+    start_wallpaper_monitor_task();
+    start_config_watch_task();
+    start_ambient_light_task();
+    start_brightness_writer_task();
+    start_key_press_monitor_tasks();
+    start_dbus_service_task();
     let clean_thread = start_shutdown_task();
 
     if let Some(listener) = comms::create() {
@@ -104,97 +275,313 @@ fn main() {
     clean_thread.join().unwrap();
 }
 
-/// Installs a custom panic hook to perform cleanup when the daemon crashes
+/// Registers the calling thread so its priority follows SetThreadPriority
+fn register_worker_thread() {
+    let tid = unsafe { libc::gettid() };
+    let mut workers = lock_or_recover(&WORKER_THREADS);
+    if workers.1 != 0 && !set_thread_niceness(tid, workers.1) {
+        error!("Could not set niceness of thread {}", tid);
+    }
+    workers.0.push(tid);
+}
+
+fn set_thread_niceness(tid: libc::pid_t, level: i32) -> bool {
+    // On Linux PRIO_PROCESS with a thread id only affects that thread
+    unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, level) == 0 }
+}
+
+/// Lowest niceness this process may set, according to RLIMIT_NICE
+fn min_allowed_niceness() -> i32 {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NICE, &mut limit) } != 0 {
+        return 0;
+    }
+    // The limit is expressed as 20 - nice
+    (20 - limit.rlim_cur.min(40) as i32).min(0)
+}
+
+/// Sets the niceness of all the worker threads. The level is clamped to
+/// the range an unprivileged process is allowed to use (usually 0..19)
+fn set_worker_priority(level: i32) -> Option<i32> {
+    let level = level.clamp(min_allowed_niceness(), 19);
+    let mut workers = lock_or_recover(&WORKER_THREADS);
+    let mut res = true;
+    for tid in workers.0.iter() {
+        res &= set_thread_niceness(*tid, level);
+    }
+    if !res {
+        error!("Could not set the niceness of every worker thread to {}", level);
+        return None;
+    }
+    info!("Worker thread niceness set to {}", level);
+    workers.1 = level;
+    Some(level)
+}
+
+/// Installs a custom panic hook to perform cleanup when the daemon crashes.
+/// Panics on worker threads don't end the process, so they keep the socket
 fn setup_panic_hook() {
     let default_panic_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |info| {
-        error!("Something went wrong! Removing the socket path");
-        if std::fs::metadata(comms::SOCKET_PATH).is_ok() {
-            std::fs::remove_file(comms::SOCKET_PATH).unwrap();
+        if thread::current().name() == Some("main") {
+            error!("Something went wrong! Removing the socket path");
+            if std::fs::metadata(comms::SOCKET_PATH).is_ok() {
+                std::fs::remove_file(comms::SOCKET_PATH).unwrap();
+            }
+        } else {
+            error!("A worker thread panicked, the daemon keeps running");
         }
         default_panic_hook(info);
     }));
 }
 
+/// Locks a mutex, recovering it if a thread panicked while holding it. The
+/// state may be half updated, but that beats taking fan and battery control down
+fn lock_or_recover<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        error!("Recovering a lock poisoned by a panic on another thread");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
 fn init_logging() {
     let mut builder = env_logger::Builder::from_default_env();
     builder.target(env_logger::Target::Stderr);
     builder.filter_level(log::LevelFilter::Info);
     builder.format_timestamp_millis();
     builder.parse_env("RAZER_LAPTOP_CONTROL_LOG");
+    if std::env::var("RAZER_LOG_FORMAT").is_ok_and(|f| f == "json") {
+        builder.format(format_json_record);
+    }
     builder.init();
 }
 
+/// Writes a log record as one JSON object per line, with the key-values of
+/// the record (e.g. temperature or fan_rpm) as extra fields
+fn format_json_record(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> std::io::Result<()> {
+    struct Fields(serde_json::Map<String, serde_json::Value>);
+    impl<'kvs> log::kv::VisitSource<'kvs> for Fields {
+        fn visit_pair(&mut self, key: log::kv::Key<'kvs>, value: log::kv::Value<'kvs>) -> Result<(), log::kv::Error> {
+            let value = if let Some(v) = value.to_i64() {
+                serde_json::Value::from(v)
+            } else if let Some(v) = value.to_f64() {
+                serde_json::Value::from(v)
+            } else if let Some(v) = value.to_bool() {
+                serde_json::Value::from(v)
+            } else {
+                serde_json::Value::from(value.to_string())
+            };
+            self.0.insert(key.to_string(), value);
+            Ok(())
+        }
+    }
+
+    let mut fields = Fields(serde_json::Map::new());
+    fields.0.insert(String::from("timestamp"), buf.timestamp_millis().to_string().into());
+    fields.0.insert(String::from("level"), record.level().as_str().into());
+    fields.0.insert(String::from("target"), record.target().into());
+    fields.0.insert(String::from("message"), record.args().to_string().into());
+    let _ = record.key_values().visit(&mut fields);
+    writeln!(buf, "{}", serde_json::Value::Object(fields.0))
+}
+
 /// Handles keyboard animations
 pub fn start_keyboard_animator_task() -> JoinHandle<()> {
     // Start the keyboard animator thread,
+    let (sender, wake) = mpsc::channel();
+    *lock_or_recover(&ANIMATOR_WAKE) = Some(sender);
+    thread::spawn(move || {
+        register_worker_thread();
+        let mut next_rediscover = REDISCOVER_AFTER_FAILURES;
+        let mut redraw = false;
+        loop {
+            redraw = wake.try_iter().fold(redraw, |redraw, r| redraw || r);
+            let (written, animated) = render_keyboard_frame(redraw);
+            redraw = false;
+            let failures = record_keyboard_write(written);
+            if failures == 0 {
+                next_rediscover = REDISCOVER_AFTER_FAILURES;
+            } else if failures >= next_rediscover {
+                next_rediscover += REDISCOVER_AFTER_FAILURES;
+                warn!("Keyboard writes keep failing, looking for the device again");
+                let mut d = lock_or_recover(&DEV_MANAGER);
+                d.discover_devices();
+                d.set_ac_state_get();
+                lock_or_recover(&KEYBOARD_WRITE_STATUS).rediscoveries += 1;
+            }
+            if failures == 0 && !animated {
+                // Static effects were written once, wait until something changes
+                redraw = match wake.recv_timeout(STATIC_EFFECT_RECHECK) {
+                    Ok(redraw) => redraw,
+                    Err(mpsc::RecvTimeoutError::Timeout) => false,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                };
+                continue;
+            }
+            // Back off while the device is not answering. The rate is read every
+            // frame so SetAnimationRate applies right away
+            let frame_ms = lock_or_recover(&EFFECT_MANAGER).get_sleep_ms();
+            let sleep_ms = (frame_ms << failures.min(4)).min(MAX_ANIMATION_BACKOFF_MS);
+            thread::sleep(std::time::Duration::from_millis(sleep_ms));
+        }
+    })
+}
+
+/// Renders a frame on every device, with `redraw` even if it looks unchanged.
+/// Returns whether all frames were written and whether any effect is animated.
+/// A panicking effect only costs a frame, the locks it poisoned are recovered
+/// on the next one
+fn render_keyboard_frame(redraw: bool) -> (bool, bool) {
+    let frame = std::panic::catch_unwind(|| {
+        lock_or_recover(&DEV_MANAGER).devices.iter_mut()
+            .enumerate()
+            .fold((true, false), |(written, animated), (i, laptop)| {
+                let mut k = lock_or_recover(effect_manager(i));
+                if redraw {
+                    k.invalidate();
+                }
+                (k.update(laptop) && written, animated || k.is_animated())
+            })
+    });
+    frame.unwrap_or_else(|_| {
+        error!("Keyboard animation panicked, skipping the frame");
+        (false, true)
+    })
+}
+
+/// Looks for the laptop while none is attached, restoring the saved settings
+/// once it appears. Logs when the device is attached or goes away
+fn start_device_rediscovery_task() -> JoinHandle<()> {
     thread::spawn(|| {
+        let mut attached = lock_or_recover(&DEV_MANAGER).get_device().is_some();
         loop {
-            if let Some(laptop) = DEV_MANAGER.lock().unwrap().get_device() {
-                EFFECT_MANAGER.lock().unwrap().update(laptop);
+            thread::sleep(DEVICE_POLL_INTERVAL);
+            let mut d = lock_or_recover(&DEV_MANAGER);
+            if d.get_device().is_none() {
+                if attached {
+                    warn!("Device detached");
+                    attached = false;
+                }
+                d.discover_devices();
+            }
+            if let Some(laptop) = d.get_device() {
+                if !attached {
+                    info!("Device attached: {}", laptop.get_name());
+                    attached = true;
+                    d.restore_device_state();
+                    update_power_mode_colour(&mut d);
+                    notify_power_state(&mut d);
+                    lock_or_recover(&KEYBOARD_WRITE_STATUS).rediscoveries += 1;
+                    wake_animator(true);
+                }
             }
-            thread::sleep(std::time::Duration::from_millis(kbd::ANIMATION_SLEEP_MS));
         }
     })
 }
 
+/// Wakes the keyboard animator if it is waiting on static effects. With
+/// `redraw` the next frame is written even if it looks unchanged, for when the
+/// keyboard may have lost what it showed
+fn wake_animator(redraw: bool) {
+    if let Some(wake) = lock_or_recover(&ANIMATOR_WAKE).as_ref() {
+        let _ = wake.send(redraw);
+    }
+}
+
+/// Updates the keyboard write status after a frame, logging only when writes
+/// start failing and when they recover. Returns the consecutive failures
+fn record_keyboard_write(written: bool) -> u32 {
+    let mut status = lock_or_recover(&KEYBOARD_WRITE_STATUS);
+    if written {
+        if status.consecutive_failures > 0 {
+            info!("Keyboard writes recovered after {} failed frames", status.consecutive_failures);
+        }
+        status.consecutive_failures = 0;
+    } else {
+        if status.consecutive_failures == 0 {
+            warn!("Could not write frame to the keyboard, slowing down the animation");
+        }
+        status.consecutive_failures += 1;
+        status.total_failures += 1;
+    }
+
+    status.consecutive_failures
+}
+
 fn start_screensaver_monitor_task() -> JoinHandle<()> {
     thread::spawn(move || {
-        let dbus_session = Connection::new_session()
-            .expect("failed to connect to D-Bus session bus");
+        register_worker_thread();
+        let dbus_session = connect_session_bus("screensaver monitor");
         let  proxy = dbus_session.with_proxy("org.gnome.Mutter.DisplayConfig", "/org/gnome/Mutter/DisplayConfig", time::Duration::from_millis(5000));
         let _id = proxy.match_signal(|h: dbus_mutter_displayconfig::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
-            let online: Option<&i32> = arg::prop_cast(&h.changed_properties, "PowerSaveMode");
-            if let Some(online) = online {
-                if *online == 3 {
-                    if let Ok(mut d) = DEV_MANAGER.lock() {
-                        d.light_off();
-                    }
+            let mode: Option<&i32> = arg::prop_cast(&h.changed_properties, "PowerSaveMode");
+            if let Some(mode) = mode {
+                let mut d = lock_or_recover(&DEV_MANAGER);
+                match d.get_display_power_action(*mode) {
+                    config::DisplayPowerAction::LightOff => d.light_off(),
+                    config::DisplayPowerAction::Restore => d.restore_light(),
+                    config::DisplayPowerAction::Ignore => {}
                 }
-                else if *online == 0 {
-                    if let Ok(mut d) = DEV_MANAGER.lock() {
-                        d.restore_light();
-                    }
-                }
-
             } 
             true
         });
         let  proxy_idle = dbus_session.with_proxy("org.gnome.Mutter.IdleMonitor", "/org/gnome/Mutter/IdleMonitor/Core", time::Duration::from_millis(5000));
         let _id = proxy_idle.match_signal(|h: dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitorWatchFired, _: &Connection, _: &Message| {
-            if let Ok(mut d) = DEV_MANAGER.lock() {
-                if d.idle_id == h.id {
-                    println!("idle trigger {:?}", h.id);
-                    d.light_off();
-                } else if d.active_id == h.id {
-                    println!("active trigger {:?}", h.id);
-                    d.restore_light();
-                }
+            let mut d = lock_or_recover(&DEV_MANAGER);
+            if d.idle_id == h.id {
+                println!("idle trigger {:?}", h.id);
+                let level = d.get_idle_brightness();
+                d.dim_light(level);
+            } else if d.active_id == h.id {
+                println!("active trigger {:?}", h.id);
+                d.restore_light();
             }
             true
         });
         let proxy = dbus_session.with_proxy("org.freedesktop.ScreenSaver", "/org/freedesktop/ScreenSaver", time::Duration::from_millis(5000));
         let _id = proxy.match_signal(|h: screensaver::OrgFreedesktopScreenSaverActiveChanged, _: &Connection, _: &Message| {
             println!("ActiveChanged {:?}", h.arg0);
-            if let Ok(mut d) = DEV_MANAGER.lock() {
-                if h.arg0 {
-                    d.light_off();
-                } else {
-                    d.restore_light();
-                }
+            let mut d = lock_or_recover(&DEV_MANAGER);
+            if h.arg0 {
+                d.light_off();
+            } else {
+                d.restore_light();
             }
             true
         });
 
+        let backend = detect_idle_backend(&dbus_session);
+        info!("Idle detection backend: {:?}", backend);
+        let dbus_system = match backend {
+            IdleBackend::Logind => Connection::new_system().ok(),
+            _ => None,
+        };
+        let mut polled_idle = false;
+
         loop { 
             if let Ok(res) = dbus_session.process(time::Duration::from_millis(1000)) {
-                if res {
-                    if let Ok(mut d) = DEV_MANAGER.lock() {
+                if backend == IdleBackend::Mutter {
+                    if res {
+                        let mut d = lock_or_recover(&DEV_MANAGER);
                         d.add_active_watch(&proxy_idle);
                     }
-                }
-                if let Ok(mut d) = DEV_MANAGER.lock() {
+                    let mut d = lock_or_recover(&DEV_MANAGER);
                     d.add_idle_watch(&proxy_idle);
+                    continue;
+                }
+                let timeout = lock_or_recover(&DEV_MANAGER).get_idle_timeout_ms();
+                let idle = timeout != 0 && is_session_idle(backend, &dbus_session, dbus_system.as_ref(), timeout);
+                if idle != polled_idle {
+                    info!("session idle: {:?}", idle);
+                    let mut d = lock_or_recover(&DEV_MANAGER);
+                    if idle {
+                        let level = d.get_idle_brightness();
+                        d.dim_light(level);
+                    } else {
+                        d.restore_light();
+                    }
+                    polled_idle = idle;
                 }
             }
         }
@@ -202,8 +589,80 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
     })
 }
 
+/// Session bus connection attempts back off up to this delay
+const MAX_SESSION_BUS_RETRY: time::Duration = time::Duration::from_secs(300);
+
+/// Connects to the session bus, waiting for it to appear if the daemon was
+/// started before the user session
+fn connect_session_bus(task: &str) -> Connection {
+    let mut delay = time::Duration::from_secs(5);
+    loop {
+        match Connection::new_session() {
+            Ok(c) => return c,
+            Err(e) => {
+                warn!("No session bus for the {}, retrying in {:?}: {}", task, delay, e);
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_SESSION_BUS_RETRY);
+            }
+        }
+    }
+}
+
+/// Source of the user idle state
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum IdleBackend {
+    Mutter,      // GNOME idle watches
+    ScreenSaver, // org.freedesktop.ScreenSaver idle time, e.g. KDE Plasma
+    Logind,      // IdleHint of the login1 session
+}
+
+/// Picks the idle source available on the session bus
+fn detect_idle_backend(dbus_session: &Connection) -> IdleBackend {
+    let proxy = dbus_session.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", time::Duration::from_millis(5000));
+    let mutter: Result<(bool,), dbus::Error> = proxy.method_call("org.freedesktop.DBus", "NameHasOwner", ("org.gnome.Mutter.IdleMonitor",));
+    if matches!(mutter, Ok((true,))) {
+        return IdleBackend::Mutter;
+    }
+    use screensaver::OrgFreedesktopScreenSaver;
+    let proxy = dbus_session.with_proxy("org.freedesktop.ScreenSaver", "/org/freedesktop/ScreenSaver", time::Duration::from_millis(5000));
+    if proxy.get_session_idle_time().is_ok() {
+        return IdleBackend::ScreenSaver;
+    }
+    IdleBackend::Logind
+}
+
+/// Polls whether the user has been idle for the timeout. The login1 idle hint
+/// follows the desktop's own timeout instead
+fn is_session_idle(backend: IdleBackend, dbus_session: &Connection, dbus_system: Option<&Connection>, timeout_ms: u64) -> bool {
+    match backend {
+        IdleBackend::ScreenSaver => {
+            use screensaver::OrgFreedesktopScreenSaver;
+            let proxy = dbus_session.with_proxy("org.freedesktop.ScreenSaver", "/org/freedesktop/ScreenSaver", time::Duration::from_millis(5000));
+            // KDE reports the idle time in milliseconds
+            matches!(proxy.get_session_idle_time(), Ok(idle) if idle as u64 >= timeout_ms)
+        }
+        IdleBackend::Logind => {
+            use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+            let dbus_system = match dbus_system {
+                Some(c) => c,
+                None => return false,
+            };
+            let proxy = dbus_system.with_proxy("org.freedesktop.login1", "/org/freedesktop/login1/session/auto", time::Duration::from_millis(5000));
+            proxy.get::<bool>("org.freedesktop.login1.Session", "IdleHint").unwrap_or(false)
+        }
+        IdleBackend::Mutter => false,
+    }
+}
+
+/// Battery percentage and whether it is charging, as last reported by UPower
+fn get_battery_level() -> (f64, bool) {
+    let status = lock_or_recover(&BATTERY_STATUS);
+    (status.percentage, status.charging)
+}
+
 fn start_battery_monitor_task() -> JoinHandle<()> {
     thread::spawn(move || {
+        register_worker_thread();
         let dbus_system = Connection::new_system()
             .expect("should be able to connect to D-Bus system bus");
         info!("Connected to the system D-Bus");
@@ -226,378 +685,2187 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
             time::Duration::from_millis(5000)
         );
 
-        let _id = proxy_ac.match_signal(|h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+        {
+            use battery::OrgFreedesktopUPowerDevice;
+            let mut status = lock_or_recover(&BATTERY_STATUS);
+            status.on_ac = proxy_ac.online().unwrap_or(false);
+            status.percentage = proxy_battery.percentage().unwrap_or(0.0);
+            status.charging = proxy_battery.state().is_ok_and(|s| s == UPOWER_STATE_CHARGING);
+        }
+
+        // UPower may report several Online changes in a row while docking,
+        // so only the last state that stays stable for the debounce window
+        // is acted upon
+        let debounce = time::Duration::from_millis(
+            lock_or_recover(&DEV_MANAGER).get_ac_debounce_ms()
+        );
+        let pending_ac: Arc<Mutex<Option<(bool, time::Instant)>>> = Arc::new(Mutex::new(None));
+        let pending = pending_ac.clone();
+        let _id = proxy_ac.match_signal(move |h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
             let online: Option<&bool> = arg::prop_cast(&h.changed_properties, "Online");
             if let Some(online) = online {
-                info!("AC0 online: {:?}", online);
-                if let Ok(mut d) = DEV_MANAGER.lock() {
-                    d.set_ac_state(*online);
-                }
-                
-                // Run power-handler.sh from user home directory when AC adapter is plugged or unplugged
-                let event_message = if *online {
-                    "AC adapter plugged in, running power-handler.sh"
-                } else {
-                    "AC adapter unplugged, running power-handler.sh"
-                };
-                
-                info!("{}", event_message);
-                
-                // Wait 2 seconds before running the script
-                thread::sleep(std::time::Duration::from_secs(2));
-                
-                // Get user home directory and construct path to power-handler.sh
-                if let Ok(home_dir) = std::env::var("HOME") {
-                    let script_path = std::path::Path::new(&home_dir).join("power_state_handler.sh");
-                    
-                    if script_path.exists() {
-                        let output = std::process::Command::new("bash")
-                            .arg(&script_path)
-                            .arg(if *online { "plugged" } else { "unplugged" })
-                            .output();
-                        
-                        match output {
-                            Ok(result) => {
-                                if result.status.success() {
-                                    info!("power_state_handler.sh executed successfully");
-                                } else {
-                                    error!("power-handler.sh failed with exit code: {:?}, stderr: {}", 
-                                        result.status.code(),
-                                        String::from_utf8_lossy(&result.stderr));
-                                }
-                            }
-                            Err(e) => {
-                                error!("Error executing power_state_handler.sh: {}", e);
-                            }
-                        }
-                    } else {
-                        info!("power_state_handler.sh not found at {}, skipping execution", script_path.display());
-                    }
-                } else {
-                    error!("Could not determine user home directory");
-                }
+                info!("AC0 online: {:?} (pending)", online);
+                lock_or_recover(&BATTERY_STATUS).on_ac = *online;
+                *lock_or_recover(&pending) = Some((*online, time::Instant::now()));
             }
             true
         });
-
-        let _id = proxy_battery.match_signal(|h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+        let mut last_perc: Option<f64> = None;
+        let _id = proxy_battery.match_signal(move |h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+            let state: Option<&u32> = arg::prop_cast(&h.changed_properties, "State");
+            if let Some(state) = state {
+                lock_or_recover(&BATTERY_STATUS).charging = *state == UPOWER_STATE_CHARGING;
+            }
             let perc: Option<&f64> = arg::prop_cast(&h.changed_properties, "Percentage");
             if let Some(perc) = perc {
                 info!("Battery percentage: {:.1}", perc);
+                lock_or_recover(&BATTERY_STATUS).percentage = *perc;
+                let (low, full, script) = lock_or_recover(&DEV_MANAGER).get_battery_hooks();
+                if let (Some(event), Some(script)) = (get_battery_crossing(last_perc, *perc, low, full), script) {
+                    info!("Battery {}, running {}", event, script);
+                    let perc = format!("{:.0}", perc);
+                    thread::spawn(move || run_user_script(&script, &[event, &perc]));
+                }
+                last_perc = Some(*perc);
             }
             true
         });
 
         let _id = proxy_login.match_signal(|h: login1::OrgFreedesktopLogin1ManagerPrepareForSleep, _: &Connection, _: &Message| {
             info!("PrepareForSleep {:?}", h.start);
-            if let Ok(mut d) = DEV_MANAGER.lock() {
-                d.set_ac_state_get();
-                if h.start {
-                    d.light_off();
-                } else {
-                    d.restore_light();
-                }
+            let mut d = lock_or_recover(&DEV_MANAGER);
+            d.set_ac_state_get();
+            if h.start {
+                d.light_off();
+            } else {
+                d.restore_light();
+                // The keyboard may come back from suspend showing something else
+                wake_animator(true);
             }
             true
         });
 
-        loop { dbus_system.process(time::Duration::from_millis(1000)).unwrap(); }
-    })
-}
-
-/// Monitors signals and stops the daemon when receiving one
-pub fn start_shutdown_task() -> JoinHandle<()> {
-    thread::spawn(|| {
-        let mut signals = Signals::new([SIGINT, SIGTERM]).unwrap();
-        let _ = signals.forever().next();
-        
-        // If we reach this point, we have a signal and it is time to exit
-        println!("Received signal, cleaning up");
-        let json = EFFECT_MANAGER.lock().unwrap().save();
-        if let Err(error) = config::Configuration::write_effects_save(json) {
-            error!("Error writing config {}", error);
-        }
-        if std::fs::metadata(comms::SOCKET_PATH).is_ok() {
-            std::fs::remove_file(comms::SOCKET_PATH).unwrap();
-        }
-        std::process::exit(0);
-    })
-}
-
-fn start_temperature_monitor_task() -> JoinHandle<()> {
-    thread::spawn(move || {
-        info!("Starting temperature monitoring task");
-        
-        // Temperature thresholds in Celsius
-        const TEMP_LOW: f32 = 50.0;      // Below this: minimum fan speed
-        const TEMP_MEDIUM: f32 = 65.0;   // Above this: medium fan speed
-        const TEMP_HIGH: f32 = 75.0;     // Above this: high fan speed
-        const TEMP_CRITICAL: f32 = 85.0; // Above this: maximum fan speed
-        
-        // Fan speeds (0 = auto, or RPM values)
-        const FAN_AUTO: i32 = 0;
-        const FAN_LOW: i32 = 2000;
-        const FAN_MEDIUM: i32 = 3500;
-        const FAN_HIGH: i32 = 4500;
-        const FAN_MAX: i32 = 5500;
-        
-        let mut last_fan_speed: i32 = -1; // Track last set speed to avoid unnecessary changes
-        
         loop {
-            if let Some(cpu_temp) = get_cpu_temperature() {
-                info!("CPU Temperature: {:.1}°C", cpu_temp);
-                
-                // Determine required fan speed based on temperature
-                let required_fan_speed = if cpu_temp < TEMP_LOW {
-                    FAN_AUTO
-                } else if cpu_temp < TEMP_MEDIUM {
-                    FAN_LOW
-                } else if cpu_temp < TEMP_HIGH {
-                    FAN_MEDIUM
-                } else if cpu_temp < TEMP_CRITICAL {
-                    FAN_HIGH
-                } else {
-                    FAN_MAX
-                };
-                
-                // Only change fan speed if it's different from last setting
-                if required_fan_speed != last_fan_speed {
-                    if let Ok(mut d) = DEV_MANAGER.lock() {
-                        // Get current AC state to set appropriate fan speed
-                        if let Some(laptop) = d.get_device() {
-                            let ac_state = laptop.get_ac_state();
-                            let success = d.set_fan_rpm(ac_state, required_fan_speed);
-                            
-                            if success {
-                                last_fan_speed = required_fan_speed;
-                                let speed_desc = match required_fan_speed {
-                                    0 => "AUTO",
-                                    FAN_LOW => "LOW",
-                                    FAN_MEDIUM => "MEDIUM", 
-                                    FAN_HIGH => "HIGH",
-                                    FAN_MAX => "MAXIMUM",
-                                    _ => "CUSTOM"
-                                };
-                                info!("Temperature-based fan control: Set fan to {} ({}RPM) due to {:.1}°C", 
-                                     speed_desc, required_fan_speed, cpu_temp);
-                            } else {
-                                error!("Failed to set fan speed to {}", required_fan_speed);
-                            }
-                        }
+            dbus_system.process(time::Duration::from_millis(100)).unwrap();
+            let stable = {
+                let mut p = lock_or_recover(&pending_ac);
+                match *p {
+                    Some((online, since)) if since.elapsed() >= debounce => {
+                        *p = None;
+                        Some(online)
                     }
+                    _ => None,
                 }
-            } else {
-                error!("Could not read CPU temperature");
+            };
+            if let Some(online) = stable {
+                handle_ac_change(online);
             }
-            
-            // Check temperature every 10 seconds
-            thread::sleep(std::time::Duration::from_secs(10));
         }
     })
 }
 
-fn get_cpu_temperature() -> Option<f32> {
-    // Try to get temperature using sensors command
-    match std::process::Command::new("sensors")
-        .arg("-A")  // Show all sensors
-        .arg("-u")  // Raw output
-        .output() 
-    {
-        Ok(output) => {
-            if output.status.success() {
-                let output_str = String::from_utf8_lossy(&output.stdout);
-                
-                // Look for CPU temperature patterns
-                // Common patterns: Core 0, Package id 0, Tctl, CPU
-                for line in output_str.lines() {
-                    if line.contains("_input:") && 
-                       (line.contains("core") || line.contains("package") || 
-                        line.contains("cpu") || line.contains("tctl")) {
-                        
-                        // Extract temperature value
-                        if let Some(temp_str) = line.split(':').nth(1) {
-                            if let Ok(temp) = temp_str.trim().parse::<f32>() {
-                                // Convert from millidegrees if needed, or return as-is if already in celsius
-                                let celsius_temp = if temp > 1000.0 { temp / 1000.0 } else { temp };
-                                
-                                // Sanity check: reasonable CPU temperature range
-                                if celsius_temp > 20.0 && celsius_temp < 120.0 {
-                                    return Some(celsius_temp);
-                                }
-                            }
-                        }
-                    }
-                }
+/// Serves the daemon commands on the session bus and broadcasts the power
+/// state changes
+fn start_dbus_service_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        register_worker_thread();
+        let dbus_session = connect_session_bus("D-Bus service");
+        if let Err(e) = dbus_session.request_name(dbus_service::BUS_NAME, false, true, true) {
+            warn!("D-Bus service disabled, could not own {}: {}", dbus_service::BUS_NAME, e);
+            return;
+        }
+        let (sender, signals) = mpsc::channel();
+        *lock_or_recover(&POWER_STATE_SIGNALS) = Some(sender);
+
+        dbus_session.start_receive(MatchRule::new_method_call(), Box::new(|msg: Message, conn: &Connection| {
+            if msg.path().as_deref() != Some(dbus_service::PATH) {
+                return true;
             }
-            
-            // Fallback: try simpler sensors output format
-            match std::process::Command::new("sensors").output() {
-                Ok(simple_output) => {
-                    if simple_output.status.success() {
-                        let output_str = String::from_utf8_lossy(&simple_output.stdout);
-                        
-                        for line in output_str.lines() {
-                            if (line.contains("Core") || line.contains("Package") || 
-                                line.contains("CPU") || line.contains("Tctl")) &&
-                               line.contains("°C") {
-                                
-                                // Extract temperature using regex-like parsing
-                                if let Some(temp_part) = line.split_whitespace()
-                                    .find(|part| part.contains("°C")) {
-                                    
-                                    let temp_str = temp_part.replace("°C", "").replace("+", "");
-                                    if let Ok(temp) = temp_str.parse::<f32>() {
-                                        if temp > 20.0 && temp < 120.0 {
-                                            return Some(temp);
-                                        }
-                                    }
-                                }
-                            }
-                        }
+            let reply = if msg.interface().as_deref() == Some("org.freedesktop.DBus.Introspectable") {
+                dbus_service::introspect(&msg)
+            } else {
+                match dbus_service::parse_method(&msg) {
+                    // The session bus only accepts clients of the same user
+                    Ok(cmd) => dbus_service::build_reply(&msg, process_client_request(cmd, None)),
+                    Err(e) => {
+                        warn!("Invalid D-Bus call: {}", e);
+                        dbus_service::error_reply(&msg, "org.freedesktop.DBus.Error.InvalidArgs", &e)
                     }
                 }
-                Err(_) => {}
+            };
+            let _ = conn.send(reply);
+            true
+        }));
+
+        loop {
+            if let Err(e) = dbus_session.process(time::Duration::from_millis(100)) {
+                error!("D-Bus service stopped: {}", e);
+                return;
+            }
+            for (ac, pwr) in signals.try_iter() {
+                let _ = dbus_session.send(dbus_service::power_state_changed(ac, pwr));
             }
         }
-        Err(e) => {
-            error!("Error executing sensors command: {}", e);
-        }
-    }
-    
-    None
+    })
 }
 
-fn handle_data(mut stream: UnixStream) {
-    let mut buffer = [0u8; 4096];
-    if stream.read(&mut buffer).is_err() {
-        return;
+/// Queues the PowerStateChanged signal of the D-Bus service
+fn notify_power_state(d: &mut device::DeviceManager) {
+    let ac = match d.get_device() {
+        Some(laptop) => laptop.get_ac_state(),
+        None => return,
+    };
+    let pwr = d.get_power_mode(ac);
+    if let Some(sender) = lock_or_recover(&POWER_STATE_SIGNALS).as_ref() {
+        let _ = sender.send((ac == 1, pwr));
     }
+}
 
-    if let Some(cmd) = comms::read_from_socket_req(&buffer) {
-        if let Some(s) = process_client_request(cmd) {
-            if let Ok(x) = bincode::serialize(&s) {
-                let result = stream.write_all(&x);
+/// Time between an AC state change and running the power handler script
+const POWER_HANDLER_DELAY: time::Duration = time::Duration::from_secs(2);
 
-                if let Err(error) = result {
-                    println!("Client disconnected with error: {error}");
-                }
-            }
+/// Applies a (debounced) AC state change and runs the user power handler script
+fn handle_ac_change(online: bool) {
+    info!("AC0 online: {:?}", online);
+    let mut d = lock_or_recover(&DEV_MANAGER);
+    let script = d.get_ac_event_script();
+    d.set_ac_state(online);
+    if let Some((pwr, cpu, gpu)) = d.get_power_preset(online as usize) {
+        info!("Applying power preset {}/{}/{}", pwr, cpu, gpu);
+        if let Err(e) = d.set_power_mode(online as usize, pwr, cpu, gpu) {
+            error!("Could not apply power preset: {}", e);
         }
     }
+    update_power_mode_colour(&mut d);
+    notify_power_state(&mut d);
+    drop(d);
+
+    // The script runs on its own thread, so the delay or a hung script
+    // doesn't hold up the D-Bus signal processing
+    thread::spawn(move || run_power_handler_script(script, online));
 }
 
-pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
-    if let Ok(mut d) = DEV_MANAGER.lock() {
-        return match cmd {
-            comms::DaemonCommand::SetPowerMode { ac, pwr, cpu, gpu } => {
-                Some(comms::DaemonResponse::SetPowerMode { result: d.set_power_mode(ac, pwr, cpu, gpu) })
-            },
-            comms::DaemonCommand::SetFanSpeed { ac, rpm } => {
-                Some(comms::DaemonResponse::SetFanSpeed { result: d.set_fan_rpm(ac, rpm) })
-            },
-            comms::DaemonCommand::SetLogoLedState{ ac, logo_state } => {
-                Some(comms::DaemonResponse::SetLogoLedState { result: d.set_logo_led_state(ac, logo_state) })
-            },
-            comms::DaemonCommand::SetBrightness { ac, val } => {
-                Some(comms::DaemonResponse::SetBrightness {result: d.set_brightness(ac, val) })
-            }
-            comms::DaemonCommand::SetIdle { ac, val } => {
-                Some(comms::DaemonResponse::SetIdle { result: d.change_idle(ac, val) })
-            }
-            comms::DaemonCommand::SetSync { sync } => {
-                Some(comms::DaemonResponse::SetSync { result: d.set_sync(sync) })
-            }
-            comms::DaemonCommand::GetBrightness{ac} =>  {
-                Some(comms::DaemonResponse::GetBrightness { result: d.get_brightness(ac)})
-            },
-            comms::DaemonCommand::GetLogoLedState{ac} => Some(comms::DaemonResponse::GetLogoLedState {logo_state: d.get_logo_led_state(ac) }),
-            comms::DaemonCommand::GetKeyboardRGB { layer } => {
-                let map = EFFECT_MANAGER.lock().unwrap().get_map(layer);
-                Some(comms::DaemonResponse::GetKeyboardRGB {
-                    layer,
-                    rgbdata: map,
-                })
-            }
-            comms::DaemonCommand::GetSync() => Some(comms::DaemonResponse::GetSync { sync: d.get_sync() }),
-            comms::DaemonCommand::GetFanSpeed{ac} => Some(comms::DaemonResponse::GetFanSpeed { rpm: d.get_fan_rpm(ac)}),
-            comms::DaemonCommand::GetPwrLevel{ac} => Some(comms::DaemonResponse::GetPwrLevel { pwr: d.get_power_mode(ac) }),
-            comms::DaemonCommand::GetCPUBoost{ac} => Some(comms::DaemonResponse::GetCPUBoost { cpu: d.get_cpu_boost(ac) }),
-            comms::DaemonCommand::GetGPUBoost{ac} => Some(comms::DaemonResponse::GetGPUBoost { gpu: d.get_gpu_boost(ac) }),
-            comms::DaemonCommand::SetEffect{ name, params } => {
-                let mut res = false;
-                if let Ok(mut k) = EFFECT_MANAGER.lock() {
-                    res = true;
-                    let effect = match name.as_str() {
-                        "static" => Some(kbd::effects::Static::new(params)),
-                        "static_gradient" => Some(kbd::effects::StaticGradient::new(params)),
-                        "wave_gradient" => Some(kbd::effects::WaveGradient::new(params)),
-                        "breathing_single" => Some(kbd::effects::BreathSingle::new(params)),
-                        _ => None
-                    };
-
-                    if let Some(laptop) = d.get_device() {
-                        if let Some(e) = effect {
-                            k.pop_effect(laptop); // Remove old layer
-                            k.push_effect(
-                                e,
-                                [true; 90]
-                                );
-                        } else {
-                            res = false
-                        }
-                    } else {
-                        res = false;
-                    }
-                }
-                Some(comms::DaemonResponse::SetEffect{result: res})
-            }
+/// Runs the AC event script with "plugged" or "unplugged", after giving the
+/// hardware some time to settle into the new AC state
+fn run_power_handler_script(script: Option<String>, online: bool) {
+    let script = match script {
+        Some(script) if !script.is_empty() => script,
+        _ => {
+            info!("No AC event script configured, skipping execution");
+            return;
+        }
+    };
+    let event = if online { "plugged" } else { "unplugged" };
+    info!("AC adapter {}, running {}", event, script);
 
-            comms::DaemonCommand::SetStandardEffect{ name, params } => {
-                // TODO save standart effect may be struct ?
-                let mut res = false;
-                if let Some(laptop) = d.get_device() {
-                    if let Ok(mut k) = EFFECT_MANAGER.lock() {
-                        k.pop_effect(laptop); // Remove old layer
-                        let _res = match name.as_str() {
-                            "off" => d.set_standard_effect(device::RazerLaptop::OFF, params),
-                            "wave" => d.set_standard_effect(device::RazerLaptop::WAVE, params),
-                            "reactive" => d.set_standard_effect(device::RazerLaptop::REACTIVE, params),
-                            "breathing" => d.set_standard_effect(device::RazerLaptop::BREATHING, params),
-                            "spectrum" => d.set_standard_effect(device::RazerLaptop::SPECTRUM, params),
-                            "static" => d.set_standard_effect(device::RazerLaptop::STATIC, params),
-                            "starlight" => d.set_standard_effect(device::RazerLaptop::STARLIGHT, params), 
-                            _ => false,
-                        };
-                        res = _res;
-                    }
-                } else {
-                    res = false;
-                }
-                Some(comms::DaemonResponse::SetStandardEffect{result: res})
-            }
-            comms::DaemonCommand::SetBatteryHealthOptimizer { is_on, threshold } => { 
-                return Some(comms::DaemonResponse::SetBatteryHealthOptimizer { result: d.set_bho_handler(is_on, threshold)});
-            }
-            comms::DaemonCommand::GetBatteryHealthOptimizer() => {
-                return d.get_bho_handler().map(|result| 
-                    comms::DaemonResponse::GetBatteryHealthOptimizer {
-                        is_on: (result.0), 
-                        threshold: (result.1) 
-                    }
-                );
-            }
-            comms::DaemonCommand::GetDeviceName => {
-                let name = match &d.device {
-                    Some(device) => device.get_name(),
-                    None => "Unknown Device".into()
-                };
-                return Some(comms::DaemonResponse::GetDeviceName { name });
-            }
+    thread::sleep(POWER_HANDLER_DELAY);
+    run_user_script(&script, &[event]);
+}
 
-        };
+/// Which battery threshold was crossed between two percentage readings, if
+/// any. Only crossings fire, so staying below the low threshold doesn't
+/// report it again on every update
+fn get_battery_crossing(last: Option<f64>, perc: f64, low: f64, full: f64) -> Option<&'static str> {
+    let last = last?;
+    if last > low && perc <= low {
+        Some("low")
+    } else if last < full && perc >= full {
+        Some("full")
     } else {
-        return None;
+        None
     }
 }
 
+/// Runs a user script with bash, logging the outcome. Relative paths are
+/// relative to the user home directory and missing scripts are skipped
+fn run_user_script(script: &str, args: &[&str]) {
+    let script_path = if std::path::Path::new(script).is_absolute() {
+        std::path::PathBuf::from(script)
+    } else {
+        match std::env::var("HOME") {
+            Ok(home_dir) => std::path::Path::new(&home_dir).join(script),
+            Err(_) => {
+                error!("Could not determine user home directory");
+                return;
+            }
+        }
+    };
+    if !script_path.exists() {
+        info!("{} not found, skipping execution", script_path.display());
+        return;
+    }
 
+    let output = std::process::Command::new("bash")
+        .arg(&script_path)
+        .args(args)
+        .output();
+
+    match output {
+        Ok(result) => {
+            if result.status.success() {
+                info!("{} executed successfully", script_path.display());
+            } else {
+                error!("{} failed with exit code: {:?}, stderr: {}",
+                    script_path.display(),
+                    result.status.code(),
+                    String::from_utf8_lossy(&result.stderr));
+            }
+        }
+        Err(e) => {
+            error!("Error executing {}: {}", script_path.display(), e);
+        }
+    }
+}
+
+/// Monitors signals and stops the daemon when receiving one
+pub fn start_shutdown_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP]).unwrap();
+        for signal in signals.forever() {
+            if signal != SIGHUP {
+                break;
+            }
+            info!("Received SIGHUP");
+            reload_config();
+        }
+
+        // If we reach this point, we have a termination signal and it is time to exit
+        println!("Received signal, cleaning up");
+        let json = lock_or_recover(&EFFECT_MANAGER).save();
+        if let Err(error) = config::Configuration::write_effects_save(json) {
+            error!("Error writing config {}", error);
+        }
+        // The lock is held until the process exits, so the animator can't
+        // write another frame after the keyboard state is set
+        let mut d = lock_or_recover(&DEV_MANAGER);
+        if let Ok(dbus_session) = Connection::new_session() {
+            let proxy_idle = dbus_session.with_proxy("org.gnome.Mutter.IdleMonitor", "/org/gnome/Mutter/IdleMonitor/Core", time::Duration::from_millis(1000));
+            d.remove_watches(&proxy_idle);
+        }
+        d.prepare_exit();
+        if std::fs::metadata(comms::SOCKET_PATH).is_ok() {
+            std::fs::remove_file(comms::SOCKET_PATH).unwrap();
+        }
+        std::process::exit(0);
+    })
+}
+
+/// Re-reads the settings on SIGHUP or when config.toml changes. The socket
+/// and the effects are kept, the power preset of the current AC state is
+/// applied again if it changed
+fn reload_config() {
+    info!("Reloading the configuration");
+    let mut d = lock_or_recover(&DEV_MANAGER);
+    let changes = match d.reload_config() {
+        Ok(changes) => changes,
+        Err(e) => {
+            error!("Could not reload the configuration: {}", e);
+            return;
+        }
+    };
+    if let Some(fps) = d.get_animation_fps() {
+        lock_or_recover(&EFFECT_MANAGER).set_fps(fps);
+    }
+    if changes.is_empty() {
+        info!("Configuration reloaded, nothing changed");
+        return;
+    }
+    for change in &changes {
+        info!("Configuration reloaded, {} changed", change);
+    }
+    if changes.contains(&"power presets") {
+        let ac = d.get_device().map_or(0, |laptop| laptop.get_ac_state());
+        if let Some((pwr, cpu, gpu)) = d.get_power_preset(ac) {
+            info!("Applying power preset {}/{}/{}", pwr, cpu, gpu);
+            if let Err(e) = d.set_power_mode(ac, pwr, cpu, gpu) {
+                error!("Could not apply power preset: {}", e);
+            }
+        }
+    }
+}
+
+/// Reloads the settings when config.toml changes, if watching is enabled.
+/// The modification time is polled, a change is acted upon once it has
+/// settled for the debounce window
+fn start_config_watch_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        register_worker_thread();
+        let path = config::UserSettings::get_path();
+        let mut last = wallpaper::get_modified(&path);
+        let mut pending: Option<time::Instant> = None;
+        loop {
+            thread::sleep(CONFIG_POLL_INTERVAL);
+            if !lock_or_recover(&DEV_MANAGER).get_watch_config() {
+                last = wallpaper::get_modified(&path);
+                pending = None;
+                continue;
+            }
+            let modified = wallpaper::get_modified(&path);
+            if modified != last {
+                last = modified;
+                pending = Some(time::Instant::now());
+            } else if pending.is_some_and(|since| since.elapsed() >= CONFIG_RELOAD_DEBOUNCE) {
+                pending = None;
+                info!("{} changed", path);
+                reload_config();
+            }
+        }
+    })
+}
+
+/// Names of the fan tiers, from the coolest to the hottest
+const FAN_TIERS: [&str; 5] = ["AUTO", "LOW", "MEDIUM", "HIGH", "MAXIMUM"];
+
+/// Returns the fan tier (index in `FAN_TIERS`) for the given temperature
+fn get_fan_tier(profile: &config::TemperatureProfile, temp: f32) -> usize {
+    if temp < profile.temp_low {
+        0
+    } else if temp < profile.temp_medium {
+        1
+    } else if temp < profile.temp_high {
+        2
+    } else if temp < profile.temp_critical {
+        3
+    } else {
+        4
+    }
+}
+
+/// Returns the fan speed of a tier
+fn get_tier_fan_speed(profile: &config::TemperatureProfile, tier: usize) -> i32 {
+    match tier {
+        0 => profile.fan_auto,
+        1 => profile.fan_low,
+        2 => profile.fan_medium,
+        3 => profile.fan_high,
+        _ => profile.fan_max,
+    }
+}
+
+/// Returns the fan tier for a temperature. Once a tier is entered, the
+/// temperature has to drop `hysteresis` below its threshold to step down,
+/// so the fan doesn't toggle when the temperature sits at a threshold
+fn get_fan_tier_with_hysteresis(profile: &config::TemperatureProfile, temp: f32, current_tier: Option<usize>) -> usize {
+    let tier = get_fan_tier(profile, temp);
+    match current_tier {
+        Some(current) => tier.max(current.min(get_fan_tier(profile, temp + profile.hysteresis))),
+        None => tier,
+    }
+}
+
+/// Returns the fan tier and speed for a temperature. With a fan curve
+/// configured there is no tier and the speed comes from the curve
+fn get_required_fan_speed(profile: &config::TemperatureProfile, temp: f32, current_tier: Option<usize>) -> (Option<usize>, i32) {
+    match fan::FanCurve::new(&profile.fan_curve) {
+        Some(curve) => (None, curve.rpm_for_temp(temp)),
+        None => {
+            let tier = get_fan_tier_with_hysteresis(profile, temp, current_tier);
+            (Some(tier), get_tier_fan_speed(profile, tier))
+        }
+    }
+}
+
+fn get_fan_tier_desc(tier: Option<usize>) -> &'static str {
+    tier.map_or("CURVE", |t| FAN_TIERS[t])
+}
+
+/// Clamps the fan speed required for a temperature to the configured ceiling.
+/// At critical temperatures the ceiling is ignored unless it is strict
+fn apply_fan_ceiling(profile: &config::TemperatureProfile, required: i32, temp: f32, ceiling: i32, strict: bool) -> i32 {
+    // 0 leaves the fan to the firmware, it can't be limited
+    if ceiling <= 0 || required == 0 || required <= ceiling {
+        return required;
+    }
+    if temp >= profile.temp_critical && !strict {
+        return required;
+    }
+
+    ceiling
+}
+
+/// Raises the fan speed to the safety floor once the temperature reaches the
+/// safety threshold, so a lazy firmware curve (0 = auto) or a low user curve
+/// can't let the CPU overheat. Applied after the ceiling, which it overrides
+fn apply_safety_floor(profile: &config::TemperatureProfile, speed: i32, temp: f32) -> i32 {
+    if profile.safety_min_rpm <= 0 || temp < profile.safety_temp {
+        return speed;
+    }
+    if speed == 0 || speed < profile.safety_min_rpm {
+        return profile.safety_min_rpm;
+    }
+    speed
+}
+
+/// The speed the safety floor is checked against: the daemon's own speed in
+/// daemon mode, the speed the user set in manual mode and 0 in auto mode
+fn get_floor_base(mode: config::FanControlMode, daemon_speed: i32, manual_rpm: i32) -> i32 {
+    match mode {
+        config::FanControlMode::Daemon => daemon_speed,
+        config::FanControlMode::Manual => manual_rpm,
+        config::FanControlMode::Auto => 0,
+    }
+}
+
+/// The speed the temperature monitor should write to the fan, if any, and the
+/// last written speed to remember once it succeeds. `last` is -1 while the
+/// monitor isn't controlling the fan. Outside of daemon mode only the safety
+/// floor is written, and the base speed is written back once it releases
+fn get_fan_write(mode: config::FanControlMode, speed: i32, floor: bool, last: i32) -> Option<(i32, i32)> {
+    let daemon = mode == config::FanControlMode::Daemon;
+    if !daemon && !floor && last == -1 {
+        return None;
+    }
+    if speed == last {
+        return None;
+    }
+    Some((speed, if daemon || floor { speed } else { -1 }))
+}
+
+/// Whether the thermal emergency mode should be entered (Some(true)) or left
+/// (Some(false)) at a temperature. The gap between the emergency and the
+/// recovery temperature keeps it from flapping
+fn get_emergency_transition(profile: &config::TemperatureProfile, temp: f32, active: bool) -> Option<bool> {
+    if profile.emergency_temp <= 0.0 {
+        return if active { Some(false) } else { None };
+    }
+    match active {
+        false if temp >= profile.emergency_temp => Some(true),
+        true if temp < profile.emergency_recovery_temp.min(profile.emergency_temp) => Some(false),
+        _ => None,
+    }
+}
+
+/// Drops the power mode to the emergency one when the temperature keeps
+/// climbing past the emergency threshold, and puts the previous mode back once
+/// it has cooled down. The replaced (AC state, power mode, cpu boost, gpu boost)
+/// is kept in `saved`. The emergency mode isn't written to the config, so it
+/// doesn't outlive the daemon
+fn handle_thermal_emergency(d: &mut device::DeviceManager, profile: &config::TemperatureProfile, temp: f32, saved: &mut Option<(usize, u8, u8, u8)>) {
+    let ac = match d.get_device() {
+        Some(laptop) => laptop.get_ac_state(),
+        None => return,
+    };
+    let unit = profile.temperature_unit;
+    match get_emergency_transition(profile, temp, saved.is_some()) {
+        Some(true) => {
+            let (pwr, cpu, gpu) = profile.emergency_mode;
+            let previous = (ac, d.get_power_mode(ac), d.get_cpu_boost(ac), d.get_gpu_boost(ac));
+            let applied = d.get_device().is_some_and(|laptop| laptop.set_power_mode(pwr, cpu, gpu));
+            if applied {
+                warn!(temperature = temp; "Thermal emergency at {}, power mode lowered to {}/{}/{} until {}",
+                      unit.format(temp), pwr, cpu, gpu, unit.format(profile.emergency_recovery_temp));
+                *saved = Some(previous);
+            } else {
+                error!("Thermal emergency at {}, but the power mode could not be lowered", unit.format(temp));
+            }
+        }
+        Some(false) => {
+            let res = match saved.take() {
+                // The AC state changed meanwhile, its own mode applies
+                Some((saved_ac, ..)) if saved_ac != ac => d.restore_power_mode(),
+                Some((_, pwr, cpu, gpu)) => d.set_power_mode(ac, pwr, cpu, gpu),
+                None => Ok(()),
+            };
+            match res {
+                Ok(()) => warn!(temperature = temp; "Thermal emergency over at {}, power mode restored", unit.format(temp)),
+                Err(e) => error!("Could not restore the power mode after the thermal emergency: {}", e),
+            }
+        }
+        None => return,
+    }
+    update_power_mode_colour(d);
+    notify_power_state(d);
+}
+
+fn to_comms_fan_mode(mode: config::FanControlMode) -> comms::FanControlMode {
+    match mode {
+        config::FanControlMode::Daemon => comms::FanControlMode::DaemonManaged,
+        config::FanControlMode::Manual => comms::FanControlMode::Manual,
+        config::FanControlMode::Auto => comms::FanControlMode::FirmwareAuto,
+    }
+}
+
+/// Who controls the fan after a speed is set. A fixed speed is kept from the
+/// temperature monitor, 0 (auto) gives the fan back to the daemon
+fn get_fan_mode_for_rpm(rpm: i32) -> config::FanControlMode {
+    if rpm != 0 {
+        config::FanControlMode::Manual
+    } else {
+        config::FanControlMode::Daemon
+    }
+}
+
+/// Adds a reading to the window of the last `size` readings and returns their average
+fn smooth_temperature(samples: &mut std::collections::VecDeque<f32>, temp: f32, size: usize) -> f32 {
+    samples.push_back(temp);
+    while samples.len() > size.max(1) {
+        samples.pop_front();
+    }
+    samples.iter().sum::<f32>() / samples.len() as f32
+}
+
+/// Cooling margin left, from 100 (at or below the low threshold) down to 0
+/// (at the critical threshold). None if the critical threshold isn't above
+/// the low one, the margin is unknown then
+fn get_thermal_headroom(profile: &config::TemperatureProfile, temp: f32) -> Option<u8> {
+    let range = profile.temp_critical - profile.temp_low;
+    if range.is_nan() || range <= 0.0 {
+        return None;
+    }
+    let margin = (profile.temp_critical - temp) / range;
+    Some((margin * 100.0).clamp(0.0, 100.0).round() as u8)
+}
+
+fn start_temperature_monitor_task() -> JoinHandle<()> {
+    thread::spawn(move || {
+        register_worker_thread();
+        info!("Starting temperature monitoring task");
+        let mut profile = lock_or_recover(&DEV_MANAGER).get_temperature_profile();
+        if !profile.fan_curve.is_empty() {
+            info!("Using the configured fan curve");
+        }
+        
+        let mut last_fan_speed: i32 = -1; // Track last set speed to avoid unnecessary changes
+        let mut current_tier: Option<usize> = None;
+        let mut safety_floor = false;
+        let mut emergency: Option<(usize, u8, u8, u8)> = None;
+        let mut fan_mode = config::FanControlMode::Daemon;
+        let mut samples: std::collections::VecDeque<f32> = std::collections::VecDeque::new();
+        
+        loop {
+            // The profile can change when the configuration is reloaded
+            let latest = lock_or_recover(&DEV_MANAGER).get_temperature_profile();
+            if latest != profile {
+                info!("Using the reloaded temperature profile");
+                profile = latest;
+                current_tier = None;
+            }
+            let raw = get_fan_control_temperature(&profile);
+            // The fan follows a moving average, so a single spike doesn't change the tier
+            let temp = raw.map(|raw| smooth_temperature(&mut samples, raw, profile.smoothing_samples));
+            *lock_or_recover(&LAST_FAN_TEMP) = temp;
+            if let (Some(raw), Some(temp)) = (raw, temp) {
+                let unit = profile.temperature_unit;
+                info!(temperature = raw; "Temperature: {} (average {})", unit.format(raw), unit.format(temp));
+                
+                // Determine required fan speed based on temperature
+                let (tier, required_fan_speed) = get_required_fan_speed(&profile, temp, current_tier);
+                current_tier = tier;
+                let speed_desc = get_fan_tier_desc(tier);
+                
+                let mut d = lock_or_recover(&DEV_MANAGER);
+                handle_thermal_emergency(&mut d, &profile, temp, &mut emergency);
+                if d.expire_manual_fan_mode() {
+                    info!("Manual fan control expired");
+                }
+                let mode = d.get_fan_control_mode();
+                if mode != fan_mode {
+                    info!("Fan control mode changed to {:?}", to_comms_fan_mode(mode));
+                    fan_mode = mode;
+                    last_fan_speed = -1; // Write the speed again when the daemon takes over
+                }
+                // Get current AC state to set appropriate fan speed
+                if let Some(laptop) = d.get_device() {
+                    let ac_state = laptop.get_ac_state();
+                    let (ceiling, strict) = d.get_fan_ceiling(ac_state);
+                    let limited = apply_fan_ceiling(&profile, required_fan_speed, temp, ceiling, strict);
+                    let manual_rpm = d.get_saved_fan_rpm(ac_state);
+                    let base = get_floor_base(fan_mode, limited, manual_rpm);
+                    let fan_speed = apply_safety_floor(&profile, base, temp);
+                    if fan_speed != base && !safety_floor {
+                        error!(temperature = temp, fan_rpm = fan_speed;
+                               "SAFETY: {} reached the {} threshold, forcing the fan to at least {}RPM",
+                               unit.format(temp), unit.format(profile.safety_temp), fan_speed);
+                    } else if fan_speed == base && safety_floor {
+                        warn!(temperature = temp; "Fan safety floor released at {}", unit.format(temp));
+                    }
+                    safety_floor = fan_speed != base;
+                    *lock_or_recover(&FAN_CONTROL) = (required_fan_speed, fan_speed);
+
+                    // Manual and firmware control are left alone, unless the safety floor engages
+                    if let Some((write, last)) = get_fan_write(fan_mode, fan_speed, safety_floor, last_fan_speed) {
+                        // Only the daemon's own speed is saved, the user's setting is kept
+                        let success = match fan_mode {
+                            config::FanControlMode::Daemon => d.set_fan_rpm(ac_state, write).is_ok(),
+                            _ => d.get_device().is_some_and(|laptop| laptop.set_fan_rpm(write as u16)),
+                        };
+
+                        if success {
+                            last_fan_speed = last;
+                            if fan_mode != config::FanControlMode::Daemon {
+                                info!(temperature = temp, fan_rpm = write;
+                                     "Temperature-based fan control: Set fan to {}RPM at {}", write, unit.format(temp));
+                            } else if fan_speed != required_fan_speed {
+                                info!(temperature = temp, fan_rpm = fan_speed;
+                                     "Temperature-based fan control: {} tier limited to {}RPM by ceiling at {}",
+                                     speed_desc, fan_speed, unit.format(temp));
+                            } else {
+                                info!(temperature = temp, fan_rpm = fan_speed;
+                                     "Temperature-based fan control: Set fan to {} ({}RPM) due to {}",
+                                     speed_desc, fan_speed, unit.format(temp));
+                            }
+                        } else {
+                            error!("Failed to set fan speed to {}", write);
+                        }
+                    }
+                }
+            } else {
+                error!("Could not read temperature");
+            }
+            
+            // Check temperature every 10 seconds
+            thread::sleep(std::time::Duration::from_secs(10));
+        }
+    })
+}
+
+/// Scales the keyboard brightness with the ambient light sensor while
+/// auto_brightness is enabled. Readings are smoothed so the keyboard doesn't
+/// flicker when the light changes briefly
+fn start_ambient_light_task() -> Option<JoinHandle<()>> {
+    let sensor = match als::find_sensor() {
+        Some(sensor) => sensor,
+        None => {
+            info!("No ambient light sensor, auto brightness unavailable");
+            return None;
+        }
+    };
+    Some(thread::spawn(move || {
+        register_worker_thread();
+        info!("Starting ambient light task with {}", sensor);
+        let mut smoothed: Option<f32> = None;
+        let mut last_brightness: Option<u8> = None;
+        loop {
+            thread::sleep(time::Duration::from_secs(1));
+            let profile = match lock_or_recover(&DEV_MANAGER).get_auto_brightness() {
+                Some(profile) => profile,
+                None => {
+                    smoothed = None;
+                    last_brightness = None;
+                    continue;
+                }
+            };
+            let lux = match als::read_lux(&sensor) {
+                Some(lux) => lux,
+                None => continue,
+            };
+            let weight = profile.smoothing.clamp(0.01, 1.0);
+            let lux = smoothed.map_or(lux, |s| s + (lux - s) * weight);
+            smoothed = Some(lux);
+            let brightness = profile.get_brightness(lux);
+            // Small steps aren't visible, skip them to avoid writing every second
+            if last_brightness.is_some_and(|last| last.abs_diff(brightness) < 2) {
+                continue;
+            }
+            let grace = time::Duration::from_secs(profile.manual_grace_s);
+            if lock_or_recover(&DEV_MANAGER).set_auto_brightness(brightness, grace) {
+                debug!("Ambient light {:.0} lux, brightness {}%", lux, brightness);
+                last_brightness = Some(brightness);
+            }
+        }
+    }))
+}
+
+/// Writes the brightness values queued by set_brightness_coalesced once the
+/// coalescing window of the last write is over
+fn start_brightness_writer_task() -> JoinHandle<()> {
+    let (sender, wake) = mpsc::channel();
+    lock_or_recover(&PENDING_BRIGHTNESS).wake = Some(sender);
+    thread::spawn(move || {
+        while wake.recv().is_ok() {
+            let wait = lock_or_recover(&PENDING_BRIGHTNESS).last_write
+                .map_or(time::Duration::ZERO, |t| BRIGHTNESS_COALESCE_WINDOW.saturating_sub(t.elapsed()));
+            thread::sleep(wait);
+            let mut d = lock_or_recover(&DEV_MANAGER);
+            let values = {
+                let mut pending = lock_or_recover(&PENDING_BRIGHTNESS);
+                pending.last_write = Some(time::Instant::now());
+                std::mem::take(&mut pending.values)
+            };
+            let previous = d.get_selected_device();
+            for ((index, ac), val) in values {
+                if !d.select_device(index) {
+                    continue;
+                }
+                if let Err(e) = d.set_brightness(ac, val) {
+                    warn!("Could not set the brightness to {}: {}", val, e);
+                }
+            }
+            d.select_device(previous);
+        }
+    })
+}
+
+/// Sets the brightness, or queues it if the last write was less than
+/// BRIGHTNESS_COALESCE_WINDOW ago. Queued values replace each other, so a
+/// burst of commands ends in one write of the latest value
+fn set_brightness_coalesced(d: &mut device::DeviceManager, ac: usize, val: u8) -> Result<(), device::DeviceError> {
+    d.check_brightness(ac, val)?;
+    let mut pending = lock_or_recover(&PENDING_BRIGHTNESS);
+    let busy = !pending.values.is_empty()
+        || pending.last_write.is_some_and(|t| t.elapsed() < BRIGHTNESS_COALESCE_WINDOW);
+    if let (true, Some(wake)) = (busy, pending.wake.clone()) {
+        let key = (d.get_selected_device(), ac);
+        if pending.values.is_empty() {
+            let _ = wake.send(());
+        }
+        match pending.values.iter_mut().find(|(k, _)| *k == key) {
+            Some(queued) => queued.1 = val,
+            None => pending.values.push((key, val)),
+        }
+        return Ok(());
+    }
+    pending.last_write = Some(time::Instant::now());
+    drop(pending);
+    d.set_brightness(ac, val)
+}
+
+/// Keeps the keyboard colour in sync with the desktop wallpaper, if enabled
+fn start_wallpaper_monitor_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        register_worker_thread();
+        let mut last: Option<(String, Option<time::SystemTime>)> = None;
+        loop {
+            let (follow, fallback) = lock_or_recover(&DEV_MANAGER).get_wallpaper_settings();
+            if follow {
+                if let Some(path) = wallpaper::get_wallpaper_path().or(fallback) {
+                    let current = (path.clone(), wallpaper::get_modified(&path));
+                    if last.as_ref() != Some(&current) {
+                        info!("Wallpaper changed to {}", path);
+                        let mut d = lock_or_recover(&DEV_MANAGER);
+                        set_colour_from_wallpaper(&mut d, &path);
+                        last = Some(current);
+                    }
+                }
+            } else {
+                last = None;
+            }
+            thread::sleep(std::time::Duration::from_secs(30));
+        }
+    })
+}
+
+/// Feeds the key presses of every readable keyboard to the effect manager,
+/// for effects like ripple. Does nothing if /dev/input can't be read
+fn start_key_press_monitor_tasks() {
+    let keyboards = input::find_keyboards();
+    if keyboards.is_empty() {
+        info!("No keyboard input devices found, reactive effects disabled");
+    }
+    for path in keyboards {
+        thread::spawn(move || {
+            register_worker_thread();
+            input::read_key_presses(&path, |code| {
+                if let Some(index) = input::get_key_index(code) {
+                    lock_or_recover(&EFFECT_MANAGER).key_pressed(index);
+                }
+            });
+        });
+    }
+}
+
+/// Shows the active power mode as a keyboard colour, or removes the indicator
+/// if power mode colour sync is disabled
+fn update_power_mode_colour(d: &mut device::DeviceManager) {
+    let sync = d.get_power_mode_colour_sync();
+    let ac = match d.get_device() {
+        Some(laptop) => laptop.get_ac_state(),
+        None => return,
+    };
+    let indicator = sync.map(|(overlay, colours)| {
+        let mode = d.get_power_mode(ac) as usize;
+        let colour = colours.get(mode).copied().unwrap_or(colours[0]);
+        let mut mask = [true; 90];
+        if overlay {
+            // Only the top row (function keys)
+            mask = [false; 90];
+            mask[..kbd::board::KEYS_PER_ROW].iter_mut().for_each(|k| *k = true);
+        }
+        (kbd::effects::Static::new(colour.to_vec()), mask)
+    });
+    let effects = effect_manager(d.get_selected_device());
+    if let Some(laptop) = d.get_device() {
+        lock_or_recover(effects).set_indicator(laptop, indicator);
+    }
+    wake_animator(false);
+}
+
+/// Replaces the top effect layer with a static colour taken from the wallpaper
+fn set_colour_from_wallpaper(d: &mut device::DeviceManager, path: &str) -> bool {
+    let colour = match wallpaper::dominant_colour(path) {
+        Some(c) => c,
+        None => return false,
+    };
+    info!("Wallpaper dominant colour: {:?}", colour);
+    let effects = effect_manager(d.get_selected_device());
+    if let Some(laptop) = d.get_device() {
+        let mut k = lock_or_recover(effects);
+        k.pop_effect(laptop); // Remove old layer
+        k.push_effect(
+            kbd::effects::Static::new(colour.to_vec()),
+            [true; 90],
+            1.0
+            );
+        wake_animator(false);
+        return true;
+    }
+
+    false
+}
+
+/// Returns the temperature the fan control should use
+fn get_fan_control_temperature(profile: &config::TemperatureProfile) -> Option<f32> {
+    match profile.fan_control_source {
+        config::FanControlSource::Cpu => get_cpu_temperature(profile.cpu_temp_mode),
+        config::FanControlSource::Gpu => get_gpu_temperature(),
+        config::FanControlSource::Max => {
+            let cpu = get_cpu_temperature(profile.cpu_temp_mode);
+            let gpu = get_gpu_temperature();
+            match (cpu, gpu) {
+                (Some(cpu), Some(gpu)) if gpu > cpu => {
+                    debug!("GPU is hotter ({} vs {})", profile.temperature_unit.format(gpu), profile.temperature_unit.format(cpu));
+                    Some(gpu)
+                }
+                (Some(cpu), _) => {
+                    debug!("CPU is hotter ({} vs {:?})", profile.temperature_unit.format(cpu), gpu.map(|gpu| profile.temperature_unit.format(gpu)));
+                    Some(cpu)
+                }
+                (None, gpu) => gpu,
+            }
+        }
+    }
+}
+
+/// Sensor labels of whole CPU packages (Intel, AMD)
+const CPU_PACKAGE_LABELS: [&str; 3] = ["Package id ", "Tctl", "Tdie"];
+/// Sensor labels of single cores or dies, used when there is no package sensor
+const CPU_CORE_LABELS: [&str; 2] = ["Core ", "Tccd"];
+
+/// Reads the CPU temperature. With several packages (or only core sensors)
+/// the readings are combined as the mode says
+fn get_cpu_temperature(mode: config::CpuTempMode) -> Option<f32> {
+    let mut readings = read_hwmon_labelled_temps(&["coretemp", "k10temp", "zenpower"]);
+    if readings.is_empty() {
+        readings = read_sensors_temps();
+    }
+    combine_cpu_temps(&readings, mode)
+}
+
+/// Combines the CPU temperatures out of (label, celsius) readings. Package
+/// sensors are used if there are any, the core ones otherwise, readings
+/// outside of a sane range are ignored
+fn combine_cpu_temps(readings: &[(String, f32)], mode: config::CpuTempMode) -> Option<f32> {
+    let matching = |prefixes: &[&str]| -> Vec<f32> {
+        readings.iter()
+            .filter(|(label, _)| prefixes.iter().any(|p| label.starts_with(p)))
+            .map(|(_, celsius)| *celsius)
+            .filter(|celsius| (1.0..120.0).contains(celsius))
+            .collect()
+    };
+    let mut temps = matching(&CPU_PACKAGE_LABELS);
+    if temps.is_empty() {
+        temps = matching(&CPU_CORE_LABELS);
+    }
+    if temps.is_empty() {
+        return None;
+    }
+    Some(match mode {
+        config::CpuTempMode::Max => temps.iter().cloned().fold(f32::MIN, f32::max),
+        config::CpuTempMode::Average => temps.iter().sum::<f32>() / temps.len() as f32,
+    })
+}
+
+fn get_gpu_temperature() -> Option<f32> {
+    read_hwmon_temp(&["GPU", "edge"], &["amdgpu", "nouveau"])
+        .or_else(read_nvidia_smi_temp)
+}
+
+/// Reads the temperature of the NVIDIA proprietary driver, which has no hwmon interface
+fn read_nvidia_smi_temp() -> Option<f32> {
+    let output = std::process::Command::new("nvidia-smi")
+        .arg("--query-gpu=temperature.gpu")
+        .arg("--format=csv,noheader,nounits")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()?
+        .trim()
+        .parse::<f32>()
+        .ok()
+}
+
+/// Reads a temperature from the hwmon sysfs interface. Sensors with one of
+/// the labels are preferred, in order, then any sensor of one of the drivers
+fn read_hwmon_temp(labels: &[&str], drivers: &[&str]) -> Option<f32> {
+    let mut best: Option<(usize, f32)> = None; // (rank, celsius), lower rank wins
+    for hwmon in std::fs::read_dir("/sys/class/hwmon").ok()?.flatten() {
+        let dir = hwmon.path();
+        let driver = std::fs::read_to_string(dir.join("name")).unwrap_or_default();
+        let is_known_driver = drivers.contains(&driver.trim());
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let sensor = match file_name.strip_prefix("temp").and_then(|f| f.strip_suffix("_input")) {
+                Some(sensor) => sensor.to_string(),
+                None => continue,
+            };
+            let millidegrees = std::fs::read_to_string(entry.path()).ok()
+                .and_then(|v| v.trim().parse::<i64>().ok());
+            let celsius = match millidegrees {
+                Some(m) => m as f32 / 1000.0,
+                None => continue,
+            };
+            let label = std::fs::read_to_string(dir.join(format!("temp{}_label", sensor))).unwrap_or_default();
+            let rank = match labels.iter().position(|l| *l == label.trim()) {
+                Some(rank) => rank,
+                None if is_known_driver => labels.len(),
+                None => continue,
+            };
+            if !matches!(best, Some((best_rank, _)) if best_rank <= rank) {
+                best = Some((rank, celsius));
+            }
+        }
+    }
+
+    best.map(|(_, celsius)| celsius)
+}
+
+/// Reads the labelled temperatures of the hwmon devices of the given drivers
+fn read_hwmon_labelled_temps(drivers: &[&str]) -> Vec<(String, f32)> {
+    let mut readings = vec![];
+    let hwmons = match std::fs::read_dir("/sys/class/hwmon") {
+        Ok(hwmons) => hwmons,
+        Err(_) => return readings,
+    };
+    for hwmon in hwmons.flatten() {
+        let dir = hwmon.path();
+        let driver = std::fs::read_to_string(dir.join("name")).unwrap_or_default();
+        if !drivers.contains(&driver.trim()) {
+            continue;
+        }
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let sensor = match file_name.strip_prefix("temp").and_then(|f| f.strip_suffix("_input")) {
+                Some(sensor) => sensor.to_string(),
+                None => continue,
+            };
+            let label = match std::fs::read_to_string(dir.join(format!("temp{}_label", sensor))) {
+                Ok(label) => label.trim().to_string(),
+                Err(_) => continue,
+            };
+            let millidegrees = std::fs::read_to_string(entry.path()).ok()
+                .and_then(|v| v.trim().parse::<i64>().ok());
+            if let Some(m) = millidegrees {
+                readings.push((label, m as f32 / 1000.0));
+            }
+        }
+    }
+    readings
+}
+
+/// Reads the temperatures reported by lm-sensors, for systems where the
+/// hwmon drivers aren't the usual ones
+fn read_sensors_temps() -> Vec<(String, f32)> {
+    match std::process::Command::new("sensors").arg("-u").output() {
+        Ok(output) if output.status.success() => parse_sensors_output(&String::from_utf8_lossy(&output.stdout)),
+        Ok(_) => vec![],
+        Err(e) => {
+            error!("Error executing sensors command: {}", e);
+            vec![]
+        }
+    }
+}
+
+/// Parses the raw output of `sensors -u` into (label, celsius) pairs. A
+/// label line ("Package id 0:") is followed by indented values
+/// ("  temp1_input: 45.000"), only the temperature inputs are kept
+fn parse_sensors_output(output: &str) -> Vec<(String, f32)> {
+    let mut readings = vec![];
+    let mut label: Option<&str> = None;
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            label = line.strip_suffix(':');
+            continue;
+        }
+        let (key, value) = match line.trim().split_once(':') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        if !(key.starts_with("temp") && key.ends_with("_input")) {
+            continue;
+        }
+        if let (Some(label), Ok(celsius)) = (label, value.trim().parse::<f32>()) {
+            readings.push((label.to_string(), celsius));
+        }
+    }
+    readings
+}
+
+/// How long a client may take to send its request or to read the response
+/// before it is dropped, so a stuck client can't hang the daemon
+const CLIENT_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+fn handle_data(mut stream: UnixStream) {
+    if let Err(error) = stream.set_read_timeout(Some(CLIENT_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT))) {
+        error!("Could not set client socket timeouts: {error}");
+        return;
+    }
+
+    let peer = get_peer_credentials(&stream);
+
+    let buffer = match comms::read_frame(&mut stream) {
+        Ok(buffer) => buffer,
+        Err(error) => {
+            warn!("Dropping client, read failed: {error}");
+            return;
+        }
+    };
+
+    // Always answer, so the client doesn't wait for its timeout
+    let s = match comms::read_from_socket_req(&buffer) {
+        Some(cmd) => process_client_request(cmd, peer),
+        None => error_response(comms::ErrorCode::InvalidCommand, "Could not parse the command, is the client up to date?"),
+    };
+    if let Ok(x) = bincode::serialize(&s) {
+        // The frame is written with write_all, which keeps writing until
+        // the whole response is sent and gives up once the write timeout is hit
+        let result = comms::write_frame(&mut stream, &x);
+
+        match result {
+            Err(error) if matches!(error.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                warn!("Dropping client, response write timed out");
+            }
+            Err(error) => {
+                println!("Client disconnected with error: {error}");
+            }
+            Ok(_) => {}
+        }
+    }
+}
+
+type EffectConstructor = fn(Vec<u8>) -> Box<dyn Effect>;
+type LayeredEffect = (&'static str, EffectConstructor, fn() -> kbd::ParamSpec);
+
+/// Effects accepted by `set_effect`, with their constructors and param specs
+const LAYERED_EFFECTS: [LayeredEffect; 13] = [
+    ("static", kbd::effects::Static::new, kbd::effects::Static::param_spec),
+    ("static_gradient", kbd::effects::StaticGradient::new, kbd::effects::StaticGradient::param_spec),
+    ("wave_gradient", kbd::effects::WaveGradient::new, kbd::effects::WaveGradient::param_spec),
+    ("breathing_single", kbd::effects::BreathSingle::new, kbd::effects::BreathSingle::param_spec),
+    ("split", kbd::effects::Split::new, kbd::effects::Split::param_spec),
+    ("scroll_text", kbd::effects::ScrollText::new, kbd::effects::ScrollText::param_spec),
+    ("scripted", kbd::effects::Scripted::new, kbd::effects::Scripted::param_spec),
+    ("spectrum_cycle", kbd::effects::SpectrumCycle::new, kbd::effects::SpectrumCycle::param_spec),
+    ("ripple", kbd::effects::Ripple::new, kbd::effects::Ripple::param_spec),
+    ("custom_matrix", kbd::effects::CustomMatrix::new, kbd::effects::CustomMatrix::param_spec),
+    ("load_meter", kbd::effects::LoadMeter::new, kbd::effects::LoadMeter::param_spec),
+    ("temp_gauge", kbd::effects::TempGauge::new, kbd::effects::TempGauge::param_spec),
+    ("battery_gauge", kbd::effects::BatteryGauge::new, kbd::effects::BatteryGauge::param_spec),
+];
+
+/// Standard (firmware) effects accepted by `SetStandardEffect`, with their ids
+const STANDARD_EFFECTS: [(&str, u8); 7] = [
+    ("off", device::RazerLaptop::OFF),
+    ("wave", device::RazerLaptop::WAVE),
+    ("reactive", device::RazerLaptop::REACTIVE),
+    ("breathing", device::RazerLaptop::BREATHING),
+    ("spectrum", device::RazerLaptop::SPECTRUM),
+    ("static", device::RazerLaptop::STATIC),
+    ("starlight", device::RazerLaptop::STARLIGHT),
+];
+
+/// Maps a standard (firmware) effect name to its id
+fn get_standard_effect_id(name: &str) -> Option<u8> {
+    STANDARD_EFFECTS.iter().find(|(n, _)| *n == name).map(|(_, id)| *id)
+}
+
+/// Checks that the parameters can be used to build the given effect
+fn validate_effect_params(name: &str, spec: &kbd::ParamSpec, params: &[u8]) -> Result<(), String> {
+    if !spec.accepts(params.len()) {
+        let expected = match spec.max_len {
+            Some(max) if max == spec.min_len => format!("{}", max),
+            Some(max) => format!("{} to {}", spec.min_len, max),
+            None => format!("at least {}", spec.min_len),
+        };
+        return Err(format!("Effect {} expects {} params ({}), got {}",
+            name, expected, spec.fields.join(", "), params.len()));
+    }
+    match name {
+        "wave_gradient" => {
+            kbd::effects::WaveParams::parse(params)?;
+        },
+        "scroll_text" => {
+            let text = String::from_utf8_lossy(&params[4..]);
+            if !kbd::effects::ScrollText::is_supported(&text) {
+                return Err(format!("Text {:?} contains characters that can't be displayed", text));
+            }
+        },
+        "scripted" => {
+            kbd::effects::ScriptDefinition::parse(&String::from_utf8_lossy(params))
+                .map_err(|e| format!("Invalid scripted effect: {}", e))?;
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+fn colour_to_rgb(colour: comms::Colour) -> [u8; 3] {
+    match colour {
+        comms::Colour::Rgb(rgb) => rgb,
+        comms::Colour::Hsv { h, s, v } => kbd::colour::hsv_to_rgb(h, s, v),
+    }
+}
+
+/// Replaces the top layer with the effect, or pushes it as a new layer above
+/// the others if a key mask is given
+fn set_effect(d: &mut device::DeviceManager, name: &str, params: Vec<u8>, mask: Option<[bool; 90]>, peer: Option<PeerCredentials>) -> Result<(), String> {
+    let (_, new, spec) = LAYERED_EFFECTS.iter()
+        .find(|(n, _, _)| *n == name)
+        .ok_or_else(|| format!("Unknown effect {}", name))?;
+    validate_effect_params(name, &spec(), &params)?;
+
+    let mut k = lock_or_recover(effect_manager(d.get_selected_device()));
+    let fade_ms = d.get_effect_fade_ms();
+    let laptop = d.get_device().ok_or_else(|| device::DeviceError::NoDevice.to_string())?;
+    match mask {
+        Some(mask) => k.push_effect(new(params), mask, 1.0),
+        None => k.replace_effect(laptop, new(params), fade_ms), // Replaces the old layer
+    }
+    record_effect_provenance(peer);
+    Ok(())
+}
+
+/// Replaces the effect stack with a saved profile, false if it can't be read
+fn load_effect_profile(d: &mut device::DeviceManager, name: &str) -> bool {
+    let json = match config::Configuration::read_effects_profile(name) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Error loading profile {}: {}", name, e);
+            return false;
+        }
+    };
+    let effects = effect_manager(d.get_selected_device());
+    match d.get_device() {
+        Some(laptop) => {
+            let result = lock_or_recover(effects).load_profile(laptop, name, json);
+            if !result {
+                error!("Some effects of profile {} could not be loaded", name);
+            }
+            result
+        },
+        None => false,
+    }
+}
+
+/// Remembers which client changed the effects last
+fn record_effect_provenance(peer: Option<PeerCredentials>) {
+    let timestamp = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    *lock_or_recover(&EFFECT_PROVENANCE) = Some(EffectProvenance { timestamp, peer });
+}
+
+/// Whether the client runs as the same user as the daemon, or as root
+fn is_trusted_peer(peer: Option<PeerCredentials>) -> bool {
+    let uid = unsafe { libc::geteuid() };
+    matches!(peer, Some(p) if p.uid == uid || p.uid == 0)
+}
+
+/// Reads the credentials of the process on the other end of the socket
+fn get_peer_credentials(stream: &UnixStream) -> Option<PeerCredentials> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res != 0 {
+        warn!("Could not read client credentials: {}", std::io::Error::last_os_error());
+        return None;
+    }
+
+    Some(PeerCredentials { pid: cred.pid, uid: cred.uid })
+}
+
+fn process_client_request(cmd: comms::DaemonCommand, peer: Option<PeerCredentials>) -> comms::DaemonResponse {
+    // A panic on another thread must not lock out the clients, the lock is recovered
+    let response = process_command(&mut lock_or_recover(&DEV_MANAGER), cmd, peer)
+        .unwrap_or_else(|| error_response(comms::ErrorCode::Failed, "The command failed"));
+    // The command may have changed the effects, unchanged frames are not written again
+    wake_animator(false);
+    response
+}
+
+fn error_response(code: comms::ErrorCode, message: &str) -> comms::DaemonResponse {
+    comms::DaemonResponse::Error { code, message: message.into() }
+}
+
+/// Splits the result of a device operation into the result and error fields of a response
+fn device_result(res: Result<(), device::DeviceError>) -> (bool, Option<String>) {
+    match res {
+        Ok(()) => (true, None),
+        Err(e) => (false, Some(e.to_string())),
+    }
+}
+
+/// Power mode, CPU boost, GPU boost and fan speed of a performance preset.
+/// Max is custom mode with the highest CPU and GPU boost and the fan at the
+/// model's top speed
+fn get_performance_preset(laptop: &device::RazerLaptop, preset: comms::PerformancePreset) -> (u8, u8, u8, i32) {
+    match preset {
+        comms::PerformancePreset::Silent => (3, 0, 0, 0),
+        comms::PerformancePreset::Balanced => (0, 0, 0, 0),
+        comms::PerformancePreset::Performance => (1, 0, 0, 0),
+        comms::PerformancePreset::Max => (4, laptop.get_max_cpu_boost(), laptop.get_max_gpu_boost(), laptop.get_fan_limits().1 as i32),
+    }
+}
+
+/// Names of the boost levels, starting at 0
+const BOOST_LEVELS: [&str; 4] = ["Low", "Medium", "High", "Boost"];
+
+/// Levels 0 to `max`, a maximum of 0 means the model has no boost
+fn get_boost_range(max: u8) -> comms::BoostRange {
+    comms::BoostRange {
+        supported: max > 0,
+        min: 0,
+        max,
+        steps: BOOST_LEVELS[..=max as usize].iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+/// Runs a single command, with the device manager already locked
+fn process_command(d: &mut device::DeviceManager, cmd: comms::DaemonCommand, peer: Option<PeerCredentials>) -> Option<comms::DaemonResponse> {
+    return match cmd {
+        comms::DaemonCommand::Batch { commands } => {
+            let nested = commands.iter().any(|c| matches!(c, comms::DaemonCommand::Batch { .. }));
+            if nested || commands.len() > MAX_BATCH_SIZE {
+                warn!("Rejecting batch of {} commands", commands.len());
+                return Some(comms::DaemonResponse::Batch { responses: None });
+            }
+            let responses = commands.into_iter()
+                .map(|c| process_command(d, c, peer))
+                .collect();
+            Some(comms::DaemonResponse::Batch { responses })
+        }
+        comms::DaemonCommand::OnDevice { index, command } => {
+            if matches!(*command, comms::DaemonCommand::OnDevice { .. }) {
+                warn!("Rejecting nested device command");
+                return Some(error_response(comms::ErrorCode::InvalidCommand, "Device commands can't be nested"));
+            }
+            let previous = d.get_selected_device();
+            if !d.select_device(index) {
+                return Some(error_response(comms::ErrorCode::NoDevice, &format!("No device with index {}", index)));
+            }
+            let response = process_command(d, *command, peer)
+                .unwrap_or_else(|| error_response(comms::ErrorCode::Failed, "The command failed"));
+            d.select_device(previous);
+            Some(comms::DaemonResponse::OnDevice { response: Box::new(response) })
+        }
+        comms::DaemonCommand::Handshake { client_version } => {
+            if client_version != comms::PROTOCOL_VERSION {
+                warn!("Client speaks protocol version {}, the daemon speaks {}", client_version, comms::PROTOCOL_VERSION);
+            }
+            Some(comms::DaemonResponse::Handshake {
+                daemon_version: env!("CARGO_PKG_VERSION").into(),
+                protocol_version: comms::PROTOCOL_VERSION,
+            })
+        }
+        comms::DaemonCommand::SetPerformancePreset { ac, preset } => {
+            let (pwr, cpu, gpu, fan_rpm) = match d.get_device() {
+                Some(laptop) => get_performance_preset(laptop, preset),
+                None => return Some(error_response(comms::ErrorCode::NoDevice, &device::DeviceError::NoDevice.to_string())),
+            };
+            let res = d.set_power_mode(ac, pwr, cpu, gpu)
+                .and_then(|_| d.set_fan_rpm(ac, fan_rpm))
+                .and_then(|_| d.set_fan_control_mode(get_fan_mode_for_rpm(fan_rpm)));
+            update_power_mode_colour(d);
+            notify_power_state(d);
+            let (result, error) = device_result(res);
+            Some(comms::DaemonResponse::SetPerformancePreset { result, error, pwr, cpu, gpu, fan_rpm })
+        }
+        comms::DaemonCommand::GetBoostRange { ac: _ } => {
+            // The range belongs to the model, so it is the same for both AC states
+            let (max_cpu, max_gpu) = d.get_device()
+                .map_or((2, 2), |laptop| (laptop.get_max_cpu_boost(), laptop.get_max_gpu_boost()));
+            Some(comms::DaemonResponse::GetBoostRange {
+                cpu: get_boost_range(max_cpu),
+                gpu: get_boost_range(max_gpu),
+            })
+        }
+        comms::DaemonCommand::SetChargeLimit { percent } => {
+            let (result, error) = device_result(d.set_charge_limit(percent));
+            Some(comms::DaemonResponse::SetChargeLimit { result, error })
+        }
+        comms::DaemonCommand::GetChargeLimit => {
+            Some(comms::DaemonResponse::GetChargeLimit { percent: d.get_charge_limit() })
+        }
+        comms::DaemonCommand::ListDevices => {
+            let devices = d.devices.iter()
+                .map(|laptop| comms::DeviceInfo {
+                    name: laptop.get_name(),
+                    has_fan_control: laptop.has_fan_control(),
+                    has_bho: laptop.capabilities().bho,
+                    has_logo_led: laptop.capabilities().logo,
+                    // Every supported model uses the same matrix, see kbd::board
+                    key_matrix: (kbd::board::ROWS, kbd::board::KEYS_PER_ROW),
+                })
+                .collect();
+            Some(comms::DaemonResponse::ListDevices { devices })
+        }
+        comms::DaemonCommand::SetPowerMode { ac, pwr, cpu, gpu } => {
+            let res = d.set_power_mode(ac, pwr, cpu, gpu);
+            update_power_mode_colour(d);
+            notify_power_state(d);
+            let (result, error) = device_result(res);
+            Some(comms::DaemonResponse::SetPowerMode { result, error })
+        },
+        comms::DaemonCommand::SetFanSpeed { ac, rpm } => {
+            // The safety floor also applies to manual speeds while it is hot
+            let profile = d.get_temperature_profile();
+            let floored = match *lock_or_recover(&LAST_FAN_TEMP) {
+                Some(temp) => apply_safety_floor(&profile, rpm, temp),
+                None => rpm,
+            };
+            if floored != rpm {
+                error!("SAFETY: fan speed {} raised to {}RPM, the temperature is above {}", rpm, floored, profile.temperature_unit.format(profile.safety_temp));
+            }
+            // The requested speed picks the mode, a floored auto speed still goes back to the daemon
+            let res = d.set_fan_rpm(ac, floored)
+                .and_then(|clamped| d.set_fan_control_mode(get_fan_mode_for_rpm(rpm)).map(|_| clamped));
+            let clamped = floored != rpm || matches!(res, Ok(true));
+            let (result, error) = device_result(res.map(|_| ()));
+            Some(comms::DaemonResponse::SetFanSpeed { result, error, clamped })
+        },
+        comms::DaemonCommand::SetLogoLedState{ ac, logo_state } => {
+            Some(comms::DaemonResponse::SetLogoLedState { result: d.set_logo_led_state(ac, logo_state) })
+        },
+        comms::DaemonCommand::SetBrightness { ac, val } => {
+            let (result, error) = device_result(set_brightness_coalesced(d, ac, val));
+            Some(comms::DaemonResponse::SetBrightness { result, error })
+        }
+        comms::DaemonCommand::SetIdle { ac, val } => {
+            Some(comms::DaemonResponse::SetIdle { result: d.change_idle(ac, val) })
+        }
+        comms::DaemonCommand::GetIdle { ac } => {
+            match d.get_idle(ac) {
+                Ok((val, timeout_ms)) => Some(comms::DaemonResponse::GetIdle { val, timeout_ms }),
+                Err(e) => Some(error_response(comms::ErrorCode::InvalidCommand, &e.to_string())),
+            }
+        }
+        comms::DaemonCommand::SetIdleTimeout { ac, timeout_ms } => {
+            Some(comms::DaemonResponse::SetIdleTimeout { result: d.set_idle_timeout_ms(ac, timeout_ms) })
+        }
+        comms::DaemonCommand::GetFanRpmRange { ac: _ } => {
+            // The range belongs to the model, so it is the same for both AC states
+            let (min, max) = d.get_fan_limits();
+            Some(comms::DaemonResponse::GetFanRpmRange { min: min as i32, max: max as i32, supports_auto: true })
+        }
+        comms::DaemonCommand::SetSync { sync } => {
+            Some(comms::DaemonResponse::SetSync { result: d.set_sync(sync) })
+        }
+        comms::DaemonCommand::GetBrightness{ac} =>  {
+            Some(comms::DaemonResponse::GetBrightness { result: d.get_brightness(ac)})
+        },
+        comms::DaemonCommand::SetLogoLedMode { ac, mode, brightness } => {
+            let logo_state = match mode {
+                comms::LogoMode::Off => 0,
+                comms::LogoMode::On => 1,
+                comms::LogoMode::Breathing => 2,
+            };
+            let (result, error) = device_result(d.set_logo_led_mode(ac, logo_state, brightness));
+            Some(comms::DaemonResponse::SetLogoLedMode { result, error })
+        }
+        comms::DaemonCommand::SetKeyboardPower { on } => {
+            let (result, error) = device_result(d.set_keyboard_power(on));
+            Some(comms::DaemonResponse::SetKeyboardPower { result, error })
+        }
+        comms::DaemonCommand::GetLogoLedState{ac} => Some(comms::DaemonResponse::GetLogoLedState {logo_state: d.get_logo_led_state(ac) }),
+        comms::DaemonCommand::GetKeyboardRGB { layer } => {
+            let mut effects = lock_or_recover(effect_manager(d.get_selected_device()));
+            let active_effect = effects.get_effect(layer)
+                .map(|(name, params)| comms::EffectDescriptor { name, params });
+            Some(comms::DaemonResponse::GetKeyboardRGB {
+                layer,
+                rgbdata: effects.get_map(layer),
+                active_effect,
+            })
+        }
+        comms::DaemonCommand::GetKeyboardRGBDiff { since_seq } => {
+            let (seq, full, keys) = lock_or_recover(effect_manager(d.get_selected_device())).get_frame_diff(since_seq);
+            Some(comms::DaemonResponse::GetKeyboardRGBDiff { seq, full, keys })
+        }
+        comms::DaemonCommand::SetKeyColors { layer, colors } => {
+            let result = if colors.len() == 90 {
+                let args = colors.into_iter().flat_map(|(r, g, b)| [r, g, b]).collect();
+                lock_or_recover(effect_manager(d.get_selected_device())).set_layer_effect(layer, kbd::effects::CustomMatrix::new(args))
+            } else {
+                error!("SetKeyColors needs 90 colours, got {}", colors.len());
+                false
+            };
+            if result {
+                record_effect_provenance(peer);
+            }
+            Some(comms::DaemonResponse::SetKeyColors { result })
+        }
+        comms::DaemonCommand::GetEffectStack => {
+            let layers = lock_or_recover(effect_manager(d.get_selected_device())).get_stack().into_iter()
+                .map(|(name, keys)| comms::EffectInfo { name, key_mask_summary: keys as u8 })
+                .collect();
+            Some(comms::DaemonResponse::GetEffectStack { layers })
+        }
+        comms::DaemonCommand::RemoveEffectLayer { index } => {
+            let effects = effect_manager(d.get_selected_device());
+            let result = match d.get_device() {
+                Some(laptop) => lock_or_recover(effects).remove_effect(laptop, index),
+                None => false,
+            };
+            if result {
+                record_effect_provenance(peer);
+            }
+            Some(comms::DaemonResponse::RemoveEffectLayer { result })
+        }
+        comms::DaemonCommand::SetLayerOpacity { index, opacity } => {
+            let result = lock_or_recover(effect_manager(d.get_selected_device())).set_layer_opacity(index, opacity);
+            Some(comms::DaemonResponse::SetLayerOpacity { result })
+        }
+        comms::DaemonCommand::SetLayerIgnoreBrightness { index, ignore } => {
+            let result = lock_or_recover(effect_manager(d.get_selected_device())).set_layer_ignore_brightness(index, ignore);
+            Some(comms::DaemonResponse::SetLayerIgnoreBrightness { result })
+        }
+        comms::DaemonCommand::GetSync() => Some(comms::DaemonResponse::GetSync { sync: d.get_sync() }),
+        comms::DaemonCommand::GetFanSpeed{ac} => Some(comms::DaemonResponse::GetFanSpeed { rpm: d.get_fan_rpm(ac)}),
+        comms::DaemonCommand::GetPwrLevel{ac} => Some(comms::DaemonResponse::GetPwrLevel { pwr: d.get_power_mode(ac) }),
+        comms::DaemonCommand::GetCPUBoost{ac} => Some(comms::DaemonResponse::GetCPUBoost { cpu: d.get_cpu_boost(ac) }),
+        comms::DaemonCommand::GetGPUBoost{ac} => Some(comms::DaemonResponse::GetGPUBoost { gpu: d.get_gpu_boost(ac) }),
+        comms::DaemonCommand::SetEffect{ name, params } => {
+            let res = set_effect(d, &name, params, None, peer);
+            if let Err(e) = &res {
+                error!("Could not set effect: {}", e);
+            }
+            Some(comms::DaemonResponse::SetEffect { result: res.is_ok(), error: res.err() })
+        }
+        comms::DaemonCommand::PushEffect { name, params, mask } => {
+            let res = match <[bool; 90]>::try_from(mask.as_slice()) {
+                Ok(mask) => set_effect(d, &name, params, Some(mask), peer),
+                Err(_) => Err(format!("The key mask needs 90 entries, got {}", mask.len())),
+            };
+            if let Err(e) = &res {
+                error!("Could not push effect: {}", e);
+            }
+            Some(comms::DaemonResponse::PushEffect { result: res.is_ok(), error: res.err() })
+        }
+        comms::DaemonCommand::SetEffectColours { name, colours, params } => {
+            let mut args: Vec<u8> = colours.into_iter().flat_map(colour_to_rgb).collect();
+            args.extend(params);
+            let result = match set_effect(d, &name, args, None, peer) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("Could not set effect: {}", e);
+                    false
+                }
+            };
+            Some(comms::DaemonResponse::SetEffectColours { result })
+        }
+
+        comms::DaemonCommand::LoadEffectDefinition { json } => {
+            let error = match kbd::effects::ScriptDefinition::parse(&json) {
+                Ok(def) => config::Configuration::write_effect_definition(&def.name, &json)
+                    .err()
+                    .map(|e| format!("Could not save effect: {}", e)),
+                Err(e) => Some(e),
+            };
+            Some(comms::DaemonResponse::LoadEffectDefinition { error })
+        }
+        comms::DaemonCommand::SetScriptedEffect { name } => {
+            let result = match config::Configuration::read_effect_definition(&name) {
+                Ok(json) => match set_effect(d, "scripted", json.into_bytes(), None, peer) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        error!("Could not set scripted effect {}: {}", name, e);
+                        false
+                    }
+                },
+                Err(e) => {
+                    error!("Could not read scripted effect {}: {}", name, e);
+                    false
+                }
+            };
+            Some(comms::DaemonResponse::SetScriptedEffect { result })
+        }
+        comms::DaemonCommand::GetCpuTemperature => {
+            let profile = d.get_temperature_profile();
+            let unit = profile.temperature_unit;
+            Some(comms::DaemonResponse::GetCpuTemperature {
+                temp: get_cpu_temperature(profile.cpu_temp_mode).map(|t| unit.to_display(t)),
+                fahrenheit: unit == config::TemperatureUnit::Fahrenheit,
+            })
+        }
+        comms::DaemonCommand::GetBatteryStatus => {
+            let status = *lock_or_recover(&BATTERY_STATUS);
+            Some(comms::DaemonResponse::GetBatteryStatus {
+                percentage: status.percentage,
+                on_ac: status.on_ac,
+                charging: status.charging,
+            })
+        }
+        comms::DaemonCommand::GetHardwareStatus => {
+            let status = lock_or_recover(&KEYBOARD_WRITE_STATUS);
+            Some(comms::DaemonResponse::GetHardwareStatus {
+                device_present: d.get_device().is_some(),
+                write_failures: status.consecutive_failures,
+                total_write_failures: status.total_failures,
+                rediscoveries: status.rediscoveries,
+            })
+        }
+        comms::DaemonCommand::GetStandardEffectRanges => {
+            let ranges = d.get_device()
+                .map(|laptop| laptop.get_standard_effect_ranges())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(effect, param, min, max)| comms::EffectParamRange {
+                    effect: effect.to_string(),
+                    param: param.to_string(),
+                    min,
+                    max,
+                })
+                .collect();
+            Some(comms::DaemonResponse::GetStandardEffectRanges { ranges })
+        }
+        comms::DaemonCommand::AdjustBrightness { ac, delta } => {
+            // Read and write under the same lock so concurrent adjustments can't get lost
+            let (result, brightness) = match d.adjust_brightness(ac, delta) {
+                Ok(brightness) => (true, brightness),
+                Err(_) => (false, 0),
+            };
+            Some(comms::DaemonResponse::AdjustBrightness { result, brightness })
+        }
+        comms::DaemonCommand::StepBrightness { ac, up } => {
+            let step = d.get_brightness_step() as i32;
+            let (result, brightness) = match d.adjust_brightness(ac, if up { step } else { -step }) {
+                Ok(brightness) => (true, brightness),
+                Err(_) => (false, 0),
+            };
+            Some(comms::DaemonResponse::StepBrightness { result, brightness })
+        }
+        comms::DaemonCommand::ListEffects => {
+            Some(comms::DaemonResponse::ListEffects {
+                custom: LAYERED_EFFECTS.iter().map(|(n, _, _)| n.to_string()).collect(),
+                standard: STANDARD_EFFECTS.iter().map(|(n, _)| n.to_string()).collect(),
+            })
+        }
+        comms::DaemonCommand::GetEffectParamSpec { name } => {
+            let response = LAYERED_EFFECTS.iter()
+                .find(|(n, _, _)| *n == name)
+                .map(|(_, _, spec)| {
+                    let spec = spec();
+                    comms::DaemonResponse::GetEffectParamSpec {
+                        min_len: spec.min_len,
+                        max_len: spec.max_len,
+                        fields: spec.fields.iter().map(|f| f.to_string()).collect(),
+                    }
+                })
+                .unwrap_or_else(|| error_response(comms::ErrorCode::NotFound, &format!("Unknown effect {}", name)));
+            Some(response)
+        }
+        comms::DaemonCommand::IsEffectSupported { name } => {
+            Some(comms::DaemonResponse::IsEffectSupported {
+                layered: LAYERED_EFFECTS.iter().any(|(n, _, _)| *n == name),
+                standard: get_standard_effect_id(&name).is_some(),
+            })
+        }
+        comms::DaemonCommand::GetEffectProvenance => {
+            let provenance = lock_or_recover(&EFFECT_PROVENANCE);
+            let response = match provenance.as_ref() {
+                Some(p) => comms::DaemonResponse::GetEffectProvenance {
+                    timestamp: Some(p.timestamp),
+                    pid: p.peer.map(|c| c.pid),
+                    uid: p.peer.map(|c| c.uid),
+                },
+                None => comms::DaemonResponse::GetEffectProvenance { timestamp: None, pid: None, uid: None },
+            };
+            Some(response)
+        }
+
+        comms::DaemonCommand::SetStandardEffect{ name, params } => {
+            // The standard effect is saved in the config and restored at startup.
+            // Software layers and the indicator would draw over it, so the layers
+            // are removed and no frames are written until a new one is added
+            let index = d.get_selected_device();
+            let effects = effect_manager(index);
+            let res = match (get_standard_effect_id(&name), d.get_device()) {
+                (Some(effect_id), Some(laptop)) => {
+                    let mut k = lock_or_recover(effects);
+                    k.clear_effects(laptop);
+                    if index == 0 {
+                        // Save the empty stack now, the old layers would cover
+                        // the standard effect if the daemon doesn't exit cleanly
+                        if let Err(e) = config::Configuration::write_effects_save(k.save()) {
+                            error!("Error writing effects {}", e);
+                        }
+                    }
+                    d.set_standard_effect(effect_id, params)
+                }
+                _ => false,
+            };
+            Some(comms::DaemonResponse::SetStandardEffect{result: res})
+        }
+        comms::DaemonCommand::SetBatteryHealthOptimizer { is_on, threshold } => { 
+            return Some(comms::DaemonResponse::SetBatteryHealthOptimizer { result: d.set_bho_handler(is_on, threshold)});
+        }
+        comms::DaemonCommand::GetBatteryHealthOptimizer() => {
+            if d.get_device().is_none() {
+                return Some(error_response(comms::ErrorCode::NoDevice, &device::DeviceError::NoDevice.to_string()));
+            }
+            let response = d.get_bho_handler().map(|result| 
+                comms::DaemonResponse::GetBatteryHealthOptimizer {
+                    is_on: (result.0), 
+                    threshold: (result.1) 
+                }
+            );
+            Some(response.unwrap_or_else(|| error_response(comms::ErrorCode::Unsupported, "The battery health optimizer is not supported or could not be read")))
+        }
+        comms::DaemonCommand::GetDeviceName => {
+            let name = match d.get_device() {
+                Some(device) => device.get_name(),
+                None => "Unknown Device".into()
+            };
+            return Some(comms::DaemonResponse::GetDeviceName { name });
+        }
+        comms::DaemonCommand::SetColorFromWallpaper { path } => {
+            let path = path
+                .or_else(wallpaper::get_wallpaper_path)
+                .or_else(|| d.get_wallpaper_settings().1);
+            let result = match path {
+                Some(path) => set_colour_from_wallpaper(d, &path),
+                None => {
+                    error!("Could not determine the wallpaper path");
+                    false
+                }
+            };
+            Some(comms::DaemonResponse::SetColorFromWallpaper { result })
+        }
+        comms::DaemonCommand::GetStartupReport => {
+            let device = d.get_device().map(|laptop| laptop.get_name());
+            let report = lock_or_recover(&STARTUP_REPORT);
+            Some(comms::DaemonResponse::GetStartupReport {
+                device,
+                effects_restored: report.effects_restored,
+                settings_restored: report.settings_restored,
+                ac_online: report.ac_online,
+                errors: report.errors.clone(),
+            })
+        }
+        comms::DaemonCommand::ToggleProfiles { a, b } => {
+            // If neither profile is active, the first one is applied
+            let active = lock_or_recover(effect_manager(d.get_selected_device())).get_active_profile().map(String::from);
+            let name = if active.as_deref() == Some(a.as_str()) { b } else { a };
+            let profile = if load_effect_profile(d, &name) { Some(name) } else { None };
+            Some(comms::DaemonResponse::ToggleProfiles { profile })
+        }
+        comms::DaemonCommand::SaveEffectProfile { name } => {
+            let mut k = lock_or_recover(effect_manager(d.get_selected_device()));
+            let result = match config::Configuration::write_effects_profile(&name, k.save()) {
+                Ok(()) => {
+                    k.set_active_profile(&name);
+                    true
+                },
+                Err(e) => {
+                    error!("Error saving profile {}: {}", name, e);
+                    false
+                }
+            };
+            Some(comms::DaemonResponse::SaveEffectProfile { result })
+        }
+        comms::DaemonCommand::LoadEffectProfile { name } => {
+            let result = load_effect_profile(d, &name);
+            if result {
+                record_effect_provenance(peer);
+            }
+            Some(comms::DaemonResponse::LoadEffectProfile { result })
+        }
+        comms::DaemonCommand::ListEffectProfiles => {
+            let names = config::Configuration::list_effects_profiles().unwrap_or_else(|e| {
+                error!("Error listing profiles: {}", e);
+                vec![]
+            });
+            Some(comms::DaemonResponse::ListEffectProfiles { names })
+        }
+        comms::DaemonCommand::SetThreadPriority { level } => {
+            Some(comms::DaemonResponse::SetThreadPriority { level: set_worker_priority(level) })
+        }
+        comms::DaemonCommand::GetThreadPriority => {
+            let level = lock_or_recover(&WORKER_THREADS).1;
+            Some(comms::DaemonResponse::GetThreadPriority { level })
+        }
+        comms::DaemonCommand::SetPowerModeColorSync { enabled, overlay, colors } => {
+            let result = d.set_power_mode_colour_sync(enabled, overlay, colors);
+            update_power_mode_colour(d);
+            Some(comms::DaemonResponse::SetPowerModeColorSync { result })
+        }
+        comms::DaemonCommand::SetFanCeiling { ac, max_rpm, strict } => {
+            Some(comms::DaemonResponse::SetFanCeiling { result: d.set_fan_ceiling(ac, max_rpm, strict) })
+        }
+        comms::DaemonCommand::GetFanControlState => {
+            let ac = d.get_device().map_or(0, |laptop| laptop.get_ac_state());
+            let (ceiling, strict) = d.get_fan_ceiling(ac);
+            let (required_rpm, applied_rpm) = *lock_or_recover(&FAN_CONTROL);
+            Some(comms::DaemonResponse::GetFanControlState {
+                temp: *lock_or_recover(&LAST_FAN_TEMP),
+                required_rpm,
+                applied_rpm,
+                ceiling,
+                strict,
+                ceiling_limiting: applied_rpm != required_rpm,
+            })
+        }
+        comms::DaemonCommand::GetFanControlMode => {
+            Some(comms::DaemonResponse::GetFanControlMode { mode: to_comms_fan_mode(d.get_fan_control_mode()) })
+        }
+        comms::DaemonCommand::SetFanControlMode { mode } => {
+            let mode = match mode {
+                comms::FanControlMode::DaemonManaged => config::FanControlMode::Daemon,
+                comms::FanControlMode::Manual => config::FanControlMode::Manual,
+                comms::FanControlMode::FirmwareAuto => config::FanControlMode::Auto,
+            };
+            let (result, error) = device_result(d.set_fan_control_mode(mode));
+            Some(comms::DaemonResponse::SetFanControlMode { result, error })
+        }
+        comms::DaemonCommand::GetAnimationRate => {
+            Some(comms::DaemonResponse::GetAnimationRate { fps: lock_or_recover(&EFFECT_MANAGER).get_fps() })
+        }
+        comms::DaemonCommand::SetAnimationRate { fps } => {
+            // All devices are animated by the same loop, the rate is kept by the first manager
+            let fps = lock_or_recover(&EFFECT_MANAGER).set_fps(fps);
+            d.set_animation_fps(fps);
+            Some(comms::DaemonResponse::SetAnimationRate { result: true, fps })
+        }
+        comms::DaemonCommand::TriggerIdle => {
+            let result = is_trusted_peer(peer);
+            if result {
+                info!("idle triggered by client");
+                let level = d.get_idle_brightness();
+                d.dim_light(level);
+            }
+            Some(comms::DaemonResponse::TriggerIdle { result })
+        }
+        comms::DaemonCommand::TriggerActive => {
+            let result = is_trusted_peer(peer);
+            if result {
+                info!("active triggered by client");
+                d.restore_light();
+            }
+            Some(comms::DaemonResponse::TriggerActive { result })
+        }
+        comms::DaemonCommand::GetThermalHeadroom => {
+            let profile = d.get_temperature_profile();
+            let cached = *lock_or_recover(&LAST_FAN_TEMP);
+            let response = match cached.or_else(|| get_fan_control_temperature(&profile)) {
+                Some(temp) => {
+                    let (tier, fan_rpm) = get_required_fan_speed(&profile, temp, None);
+                    comms::DaemonResponse::GetThermalHeadroom {
+                        headroom: get_thermal_headroom(&profile, temp),
+                        fan_tier: get_fan_tier_desc(tier).to_string(),
+                        fan_rpm,
+                    }
+                }
+                None => comms::DaemonResponse::GetThermalHeadroom {
+                    headroom: None,
+                    fan_tier: String::from("UNKNOWN"),
+                    fan_rpm: -1,
+                },
+            };
+            Some(response)
+        }
+        comms::DaemonCommand::SetSplitColors { left, right, blend } => {
+            let mut params = colour_to_rgb(left).to_vec();
+            params.extend_from_slice(&colour_to_rgb(right));
+            params.push(blend);
+            let res = set_effect(d, "split", params, None, peer);
+            if let Err(e) = &res {
+                error!("Could not set split colours: {}", e);
+            }
+            Some(comms::DaemonResponse::SetSplitColors { result: res.is_ok(), error: res.err() })
+        }
+
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn animator_recovers_poisoned_device_lock() {
+        let _ = thread::spawn(|| {
+            let _d = DEV_MANAGER.lock().unwrap();
+            panic!("poisoning the device manager");
+        }).join();
+        assert!(DEV_MANAGER.is_poisoned());
+        render_keyboard_frame(false);
+        assert!(DEV_MANAGER.lock().is_ok());
+    }
+
+    #[test]
+    fn models_without_gpu_boost_report_no_range() {
+        let (laptop, _) = device::testing::fake_laptop();
+        assert_eq!(get_performance_preset(&laptop, comms::PerformancePreset::Max), (4, 3, 2, 5000));
+        assert!(get_boost_range(laptop.get_max_gpu_boost()).supported);
+
+        let capabilities = device::Capabilities { gpu_boost: false, ..device::testing::capabilities() };
+        let (laptop, _) = device::testing::fake_laptop_with(capabilities);
+        assert_eq!(get_performance_preset(&laptop, comms::PerformancePreset::Max), (4, 3, 0, 5000));
+        let range = get_boost_range(laptop.get_max_gpu_boost());
+        assert!(!range.supported);
+        assert_eq!(range.steps, vec!["Low"]);
+        assert!(laptop.supports_power_mode(4, 3, 0));
+        assert!(!laptop.supports_power_mode(4, 3, 1));
+    }
+
+    fn tier_profile() -> config::TemperatureProfile {
+        config::TemperatureProfile {
+            temp_low: 50.0,
+            temp_medium: 65.0,
+            temp_high: 75.0,
+            temp_critical: 85.0,
+            hysteresis: 3.0,
+            ..config::TemperatureProfile::new()
+        }
+    }
+
+    #[test]
+    fn fan_tier_hysteresis() {
+        let profile = tier_profile();
+        let thresholds = [50.0, 65.0, 75.0, 85.0];
+        for (below, threshold) in thresholds.iter().enumerate() {
+            let above = below + 1;
+            // Stepping up happens at the threshold
+            assert_eq!(get_fan_tier_with_hysteresis(&profile, threshold - 0.1, Some(below)), below);
+            assert_eq!(get_fan_tier_with_hysteresis(&profile, *threshold, Some(below)), above);
+            // Inside the band the tier is kept
+            assert_eq!(get_fan_tier_with_hysteresis(&profile, threshold - 0.1, Some(above)), above);
+            assert_eq!(get_fan_tier_with_hysteresis(&profile, threshold - 2.0, Some(above)), above);
+            assert_eq!(get_fan_tier_with_hysteresis(&profile, threshold - 3.0, Some(above)), above);
+            // Stepping down needs more than the hysteresis below the threshold
+            assert_eq!(get_fan_tier_with_hysteresis(&profile, threshold - 3.1, Some(above)), below);
+        }
+    }
+
+    #[test]
+    fn fan_tier_without_history() {
+        let profile = tier_profile();
+        assert_eq!(get_fan_tier_with_hysteresis(&profile, 49.0, None), 0);
+        assert_eq!(get_fan_tier_with_hysteresis(&profile, 64.0, None), 1);
+        assert_eq!(get_fan_tier_with_hysteresis(&profile, 84.9, None), 3);
+        assert_eq!(get_fan_tier_with_hysteresis(&profile, 85.0, None), 4);
+        // Large jumps skip the tiers in between
+        assert_eq!(get_fan_tier_with_hysteresis(&profile, 90.0, Some(0)), 4);
+        assert_eq!(get_fan_tier_with_hysteresis(&profile, 40.0, Some(4)), 0);
+        // A drop past several thresholds keeps the tier whose band it is in
+        assert_eq!(get_fan_tier_with_hysteresis(&profile, 63.0, Some(4)), 2);
+    }
+
+    #[test]
+    fn battery_crossings() {
+        // Crossing down through low and up through full
+        assert_eq!(get_battery_crossing(Some(21.0), 20.0, 20.0, 80.0), Some("low"));
+        assert_eq!(get_battery_crossing(Some(25.0), 15.0, 20.0, 80.0), Some("low"));
+        assert_eq!(get_battery_crossing(Some(79.5), 80.0, 20.0, 80.0), Some("full"));
+        assert_eq!(get_battery_crossing(Some(70.0), 95.0, 20.0, 80.0), Some("full"));
+        // Moving between the thresholds, or back out, reports nothing
+        assert_eq!(get_battery_crossing(Some(50.0), 49.0, 20.0, 80.0), None);
+        assert_eq!(get_battery_crossing(Some(20.0), 21.0, 20.0, 80.0), None);
+        assert_eq!(get_battery_crossing(Some(80.0), 79.0, 20.0, 80.0), None);
+    }
+
+    #[test]
+    fn battery_crossing_fires_once() {
+        // Staying past a threshold doesn't report it again
+        assert_eq!(get_battery_crossing(Some(20.0), 19.0, 20.0, 80.0), None);
+        assert_eq!(get_battery_crossing(Some(19.0), 19.0, 20.0, 80.0), None);
+        assert_eq!(get_battery_crossing(Some(80.0), 81.0, 20.0, 80.0), None);
+        assert_eq!(get_battery_crossing(Some(100.0), 100.0, 20.0, 80.0), None);
+    }
+
+    #[test]
+    fn first_battery_sample_is_no_crossing() {
+        assert_eq!(get_battery_crossing(None, 10.0, 20.0, 80.0), None);
+        assert_eq!(get_battery_crossing(None, 50.0, 20.0, 80.0), None);
+        assert_eq!(get_battery_crossing(None, 90.0, 20.0, 80.0), None);
+    }
+
+    /// `sensors -u` on an Intel Blade, shortened
+    const INTEL_SENSORS: &str = "\
+coretemp-isa-0000
+Adapter: ISA adapter
+Package id 0:
+  temp1_input: 52.000
+  temp1_max: 100.000
+  temp1_crit: 100.000
+  temp1_crit_alarm: 0.000
+Core 0:
+  temp2_input: 49.000
+  temp2_max: 100.000
+Core 1:
+  temp3_input: 51.000
+  temp3_max: 100.000
+
+acpitz-acpi-0
+Adapter: ACPI interface
+temp1:
+  temp1_input: 27.800
+";
+
+    /// `sensors -u` on an AMD laptop with k10temp
+    const AMD_SENSORS: &str = "\
+k10temp-pci-00c3
+Adapter: PCI adapter
+Tctl:
+  temp1_input: 61.875
+Tccd1:
+  temp3_input: 55.250
+";
+
+    fn readings(pairs: &[(&str, f32)]) -> Vec<(String, f32)> {
+        pairs.iter().map(|(label, celsius)| (label.to_string(), *celsius)).collect()
+    }
+
+    #[test]
+    fn parses_sensors_output() {
+        assert_eq!(parse_sensors_output(INTEL_SENSORS), readings(&[
+            ("Package id 0", 52.0), ("Core 0", 49.0), ("Core 1", 51.0), ("temp1", 27.8),
+        ]));
+        assert_eq!(parse_sensors_output(AMD_SENSORS), readings(&[("Tctl", 61.875), ("Tccd1", 55.25)]));
+        assert_eq!(parse_sensors_output(""), vec![]);
+        // Values before the first label have nothing to belong to
+        assert_eq!(parse_sensors_output("  temp1_input: 40.000\n"), vec![]);
+    }
+
+    #[test]
+    fn uses_package_sensors() {
+        let intel = parse_sensors_output(INTEL_SENSORS);
+        assert_eq!(combine_cpu_temps(&intel, config::CpuTempMode::Max), Some(52.0));
+        let amd = parse_sensors_output(AMD_SENSORS);
+        assert_eq!(combine_cpu_temps(&amd, config::CpuTempMode::Max), Some(61.875));
+
+        // Every package counts on multi socket machines
+        let packages = readings(&[("Package id 0", 50.0), ("Package id 1", 60.0), ("Core 0", 90.0)]);
+        assert_eq!(combine_cpu_temps(&packages, config::CpuTempMode::Max), Some(60.0));
+        assert_eq!(combine_cpu_temps(&packages, config::CpuTempMode::Average), Some(55.0));
+    }
+
+    #[test]
+    fn falls_back_to_core_sensors() {
+        let cores = readings(&[("Core 0", 40.0), ("Core 1", 50.0), ("temp1", 100.0)]);
+        assert_eq!(combine_cpu_temps(&cores, config::CpuTempMode::Max), Some(50.0));
+        assert_eq!(combine_cpu_temps(&cores, config::CpuTempMode::Average), Some(45.0));
+        let dies = readings(&[("Tccd1", 55.0), ("Tccd2", 57.0)]);
+        assert_eq!(combine_cpu_temps(&dies, config::CpuTempMode::Max), Some(57.0));
+        assert_eq!(combine_cpu_temps(&readings(&[("temp1", 30.0)]), config::CpuTempMode::Max), None);
+    }
+
+    #[test]
+    fn ignores_out_of_range_readings() {
+        let broken = readings(&[("Package id 0", -273.0), ("Package id 1", 127.0), ("Core 0", 45.0)]);
+        // No sane package reading, so the cores are used
+        assert_eq!(combine_cpu_temps(&broken, config::CpuTempMode::Max), Some(45.0));
+        let mixed = readings(&[("Package id 0", 0.0), ("Package id 1", 48.0), ("Package id 2", 120.0)]);
+        assert_eq!(combine_cpu_temps(&mixed, config::CpuTempMode::Average), Some(48.0));
+        assert_eq!(combine_cpu_temps(&readings(&[("Core 0", 255.0)]), config::CpuTempMode::Max), None);
+    }
+
+    #[test]
+    fn smoothing_window_fills_up() {
+        let mut samples = std::collections::VecDeque::new();
+        assert_eq!(smooth_temperature(&mut samples, 40.0, 3), 40.0);
+        assert_eq!(smooth_temperature(&mut samples, 50.0, 3), 45.0);
+        assert_eq!(smooth_temperature(&mut samples, 60.0, 3), 50.0);
+        assert_eq!(samples.len(), 3);
+    }
+
+    #[test]
+    fn smoothing_window_evicts_the_oldest() {
+        let mut samples = std::collections::VecDeque::from(vec![40.0, 50.0, 60.0]);
+        assert_eq!(smooth_temperature(&mut samples, 70.0, 3), 60.0);
+        assert_eq!(samples, vec![50.0, 60.0, 70.0]);
+        // A smaller window drops the extra readings at once
+        assert_eq!(smooth_temperature(&mut samples, 80.0, 2), 75.0);
+        assert_eq!(samples, vec![70.0, 80.0]);
+    }
+
+    #[test]
+    fn smoothing_window_of_one_or_zero() {
+        for size in [0, 1] {
+            let mut samples = std::collections::VecDeque::new();
+            assert_eq!(smooth_temperature(&mut samples, 40.0, size), 40.0);
+            assert_eq!(smooth_temperature(&mut samples, 70.0, size), 70.0);
+            assert_eq!(samples.len(), 1);
+        }
+    }
+
+    fn emergency_profile() -> config::TemperatureProfile {
+        config::TemperatureProfile {
+            emergency_temp: 95.0,
+            emergency_recovery_temp: 85.0,
+            ..config::TemperatureProfile::new()
+        }
+    }
+
+    #[test]
+    fn thermal_headroom() {
+        let profile = tier_profile();
+        assert_eq!(get_thermal_headroom(&profile, 40.0), Some(100));
+        assert_eq!(get_thermal_headroom(&profile, 67.5), Some(50));
+        assert_eq!(get_thermal_headroom(&profile, 90.0), Some(0));
+    }
+
+    #[test]
+    fn thermal_headroom_without_a_range() {
+        // A critical threshold at or below the low one can't give a margin
+        for critical in [50.0, 40.0] {
+            let profile = config::TemperatureProfile { temp_critical: critical, ..tier_profile() };
+            assert_eq!(get_thermal_headroom(&profile, 45.0), None);
+            assert_eq!(get_thermal_headroom(&profile, 60.0), None);
+        }
+    }
+
+    #[test]
+    fn emergency_starts_at_the_threshold() {
+        let profile = emergency_profile();
+        assert_eq!(get_emergency_transition(&profile, 94.9, false), None);
+        assert_eq!(get_emergency_transition(&profile, 95.0, false), Some(true));
+        assert_eq!(get_emergency_transition(&profile, 105.0, false), Some(true));
+    }
+
+    #[test]
+    fn emergency_ends_below_the_recovery_temperature() {
+        let profile = emergency_profile();
+        // Staying active while hot and inside the gap
+        assert_eq!(get_emergency_transition(&profile, 100.0, true), None);
+        assert_eq!(get_emergency_transition(&profile, 90.0, true), None);
+        assert_eq!(get_emergency_transition(&profile, 85.0, true), None);
+        assert_eq!(get_emergency_transition(&profile, 84.9, true), Some(false));
+        // Cooling down inside the gap doesn't enter it either
+        assert_eq!(get_emergency_transition(&profile, 90.0, false), None);
+    }
+
+    #[test]
+    fn emergency_recovery_above_the_threshold() {
+        // A recovery temperature above the emergency one is capped at it
+        let profile = config::TemperatureProfile { emergency_recovery_temp: 99.0, ..emergency_profile() };
+        assert_eq!(get_emergency_transition(&profile, 96.0, true), None);
+        assert_eq!(get_emergency_transition(&profile, 94.9, true), Some(false));
+    }
+
+    #[test]
+    fn disabled_emergency_is_left() {
+        let profile = config::TemperatureProfile { emergency_temp: 0.0, ..emergency_profile() };
+        assert_eq!(get_emergency_transition(&profile, 120.0, false), None);
+        assert_eq!(get_emergency_transition(&profile, 120.0, true), Some(false));
+    }
+
+    /// Runs the temperature monitor's floor decisions over a series of
+    /// temperatures and returns the speeds it writes to the fan
+    fn floor_writes(mode: config::FanControlMode, daemon_speed: i32, manual_rpm: i32, temps: &[f32]) -> Vec<i32> {
+        let profile = config::TemperatureProfile {
+            safety_temp: 90.0,
+            safety_min_rpm: 3500,
+            ..config::TemperatureProfile::new()
+        };
+        let base = get_floor_base(mode, daemon_speed, manual_rpm);
+        let mut last = -1;
+        let mut writes = Vec::new();
+        for &temp in temps {
+            let speed = apply_safety_floor(&profile, base, temp);
+            if let Some((write, next)) = get_fan_write(mode, speed, speed != base, last) {
+                writes.push(write);
+                last = next;
+            }
+        }
+        writes
+    }
+
+    #[test]
+    fn safety_floor_in_manual_mode() {
+        let mode = config::FanControlMode::Manual;
+        // The floor is checked against the user's speed, not the daemon's
+        assert_eq!(floor_writes(mode, 5000, 2000, &[80.0, 92.0, 95.0, 85.0, 80.0]), vec![3500, 2000]);
+        // A faster manual speed is kept, the floor doesn't lower it
+        assert_eq!(floor_writes(mode, 0, 4000, &[80.0, 95.0, 80.0]), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn safety_floor_in_auto_mode() {
+        let mode = config::FanControlMode::Auto;
+        // Releasing the floor hands the fan back to the firmware
+        assert_eq!(floor_writes(mode, 2000, 4000, &[80.0, 92.0, 85.0, 92.0]), vec![3500, 0, 3500]);
+        assert_eq!(floor_writes(mode, 2000, 4000, &[80.0, 85.0]), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn safety_floor_in_daemon_mode() {
+        let mode = config::FanControlMode::Daemon;
+        assert_eq!(floor_writes(mode, 2000, 0, &[80.0, 92.0, 95.0, 85.0]), vec![2000, 3500, 2000]);
+    }
+
+    #[test]
+    fn auto_fan_speed_goes_back_to_the_daemon() {
+        assert!(get_fan_mode_for_rpm(0) == config::FanControlMode::Daemon);
+        assert!(get_fan_mode_for_rpm(3500) == config::FanControlMode::Manual);
+    }
+
+    #[test]
+    fn effect_params_too_short() {
+        let spec = kbd::effects::Static::param_spec();
+        let err = validate_effect_params("static", &spec, &[255, 0]).unwrap_err();
+        assert_eq!(err, "Effect static expects 3 params (red, green, blue), got 2");
+        let spec = kbd::effects::WaveGradient::param_spec();
+        let err = validate_effect_params("wave_gradient", &spec, &[0; 5]).unwrap_err();
+        assert_eq!(err, "Effect wave_gradient expects 6 to 8 params \
+            (red_1, green_1, blue_1, red_2, green_2, blue_2, direction, speed), got 5");
+    }
+
+    #[test]
+    fn effect_params_too_long() {
+        let spec = kbd::effects::Static::param_spec();
+        let err = validate_effect_params("static", &spec, &[255, 0, 0, 0]).unwrap_err();
+        assert_eq!(err, "Effect static expects 3 params (red, green, blue), got 4");
+        let spec = kbd::effects::WaveGradient::param_spec();
+        let err = validate_effect_params("wave_gradient", &spec, &[0; 9]).unwrap_err();
+        assert!(err.contains("expects 6 to 8 params") && err.ends_with("got 9"), "{}", err);
+    }
+
+    #[test]
+    fn effect_params_unbounded() {
+        let spec = kbd::effects::ScrollText::param_spec();
+        let err = validate_effect_params("scroll_text", &spec, &[255, 0, 0]).unwrap_err();
+        assert_eq!(err, "Effect scroll_text expects at least 4 params (red, green, blue, columns_per_sec, text), got 3");
+        let mut params = vec![255, 0, 0, 10];
+        params.extend(b"HELLO WORLD ".repeat(20));
+        assert_eq!(validate_effect_params("scroll_text", &spec, &params), Ok(()));
+    }
+
+    #[test]
+    fn effect_params_are_checked_by_the_effect() {
+        let spec = kbd::effects::WaveGradient::param_spec();
+        assert_eq!(validate_effect_params("wave_gradient", &spec, &[255, 0, 0, 0, 0, 255, 1, 5]), Ok(()));
+        let err = validate_effect_params("wave_gradient", &spec, &[255, 0, 0, 0, 0, 255, 1, 0]).unwrap_err();
+        assert!(err.contains("speed 0"), "{}", err);
+    }
+}
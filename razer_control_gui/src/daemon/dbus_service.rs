@@ -0,0 +1,163 @@
+// Session bus frontend of the daemon. Method calls are translated to the same
+// commands the Unix socket accepts, so both frontends behave the same
+use std::ffi::CString;
+
+use dbus::arg::{Append, IterAppend};
+use dbus::Message;
+
+use crate::comms;
+
+pub const BUS_NAME: &str = "org.razer.LaptopControl";
+pub const PATH: &str = "/org/razer/LaptopControl";
+pub const INTERFACE: &str = "org.razer.LaptopControl";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+"http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.razer.LaptopControl">
+    <method name="GetFanSpeed">
+      <arg name="ac" type="u" direction="in"/>
+      <arg name="rpm" type="i" direction="out"/>
+    </method>
+    <method name="SetFanSpeed">
+      <arg name="ac" type="u" direction="in"/>
+      <arg name="rpm" type="i" direction="in"/>
+      <arg name="result" type="b" direction="out"/>
+    </method>
+    <method name="GetPowerMode">
+      <arg name="ac" type="u" direction="in"/>
+      <arg name="pwr" type="y" direction="out"/>
+    </method>
+    <method name="SetPowerMode">
+      <arg name="ac" type="u" direction="in"/>
+      <arg name="pwr" type="y" direction="in"/>
+      <arg name="cpu" type="y" direction="in"/>
+      <arg name="gpu" type="y" direction="in"/>
+      <arg name="result" type="b" direction="out"/>
+    </method>
+    <method name="GetBrightness">
+      <arg name="ac" type="u" direction="in"/>
+      <arg name="brightness" type="y" direction="out"/>
+    </method>
+    <method name="SetBrightness">
+      <arg name="ac" type="u" direction="in"/>
+      <arg name="brightness" type="y" direction="in"/>
+      <arg name="result" type="b" direction="out"/>
+    </method>
+    <method name="GetLogoLedState">
+      <arg name="ac" type="u" direction="in"/>
+      <arg name="logo_state" type="y" direction="out"/>
+    </method>
+    <method name="SetLogoLedState">
+      <arg name="ac" type="u" direction="in"/>
+      <arg name="logo_state" type="y" direction="in"/>
+      <arg name="result" type="b" direction="out"/>
+    </method>
+    <method name="GetBatteryHealthOptimizer">
+      <arg name="is_on" type="b" direction="out"/>
+      <arg name="threshold" type="y" direction="out"/>
+    </method>
+    <method name="SetBatteryHealthOptimizer">
+      <arg name="is_on" type="b" direction="in"/>
+      <arg name="threshold" type="y" direction="in"/>
+      <arg name="result" type="b" direction="out"/>
+    </method>
+    <method name="GetDeviceName">
+      <arg name="name" type="s" direction="out"/>
+    </method>
+    <signal name="PowerStateChanged">
+      <arg name="ac" type="b"/>
+      <arg name="pwr" type="y"/>
+    </signal>
+  </interface>
+</node>
+"#;
+
+/// Translates a method call to a daemon command
+pub fn parse_method(msg: &Message) -> Result<comms::DaemonCommand, String> {
+    let member = msg.member().ok_or_else(|| String::from("Missing method name"))?;
+    let err = |e: dbus::arg::TypeMismatchError| e.to_string();
+    Ok(match &*member {
+        "GetFanSpeed" => comms::DaemonCommand::GetFanSpeed { ac: msg.read1::<u32>().map_err(err)? as usize },
+        "SetFanSpeed" => {
+            let (ac, rpm): (u32, i32) = msg.read2().map_err(err)?;
+            comms::DaemonCommand::SetFanSpeed { ac: ac as usize, rpm }
+        }
+        "GetPowerMode" => comms::DaemonCommand::GetPwrLevel { ac: msg.read1::<u32>().map_err(err)? as usize },
+        "SetPowerMode" => {
+            let (ac, pwr, cpu, gpu): (u32, u8, u8, u8) = msg.read4().map_err(err)?;
+            comms::DaemonCommand::SetPowerMode { ac: ac as usize, pwr, cpu, gpu }
+        }
+        "GetBrightness" => comms::DaemonCommand::GetBrightness { ac: msg.read1::<u32>().map_err(err)? as usize },
+        "SetBrightness" => {
+            let (ac, val): (u32, u8) = msg.read2().map_err(err)?;
+            comms::DaemonCommand::SetBrightness { ac: ac as usize, val }
+        }
+        "GetLogoLedState" => comms::DaemonCommand::GetLogoLedState { ac: msg.read1::<u32>().map_err(err)? as usize },
+        "SetLogoLedState" => {
+            let (ac, logo_state): (u32, u8) = msg.read2().map_err(err)?;
+            comms::DaemonCommand::SetLogoLedState { ac: ac as usize, logo_state }
+        }
+        "GetBatteryHealthOptimizer" => comms::DaemonCommand::GetBatteryHealthOptimizer(),
+        "SetBatteryHealthOptimizer" => {
+            let (is_on, threshold): (bool, u8) = msg.read2().map_err(err)?;
+            comms::DaemonCommand::SetBatteryHealthOptimizer { is_on, threshold }
+        }
+        "GetDeviceName" => comms::DaemonCommand::GetDeviceName,
+        _ => return Err(format!("Unknown method {}", member)),
+    })
+}
+
+/// Builds the reply to a method call from the daemon response. Failed device
+/// operations become error replies carrying the reason
+pub fn build_reply(msg: &Message, response: comms::DaemonResponse) -> Message {
+    match &response {
+        comms::DaemonResponse::SetFanSpeed { error: Some(e), .. }
+        | comms::DaemonResponse::SetPowerMode { error: Some(e), .. }
+        | comms::DaemonResponse::SetBrightness { error: Some(e), .. }
+        | comms::DaemonResponse::Error { message: e, .. } => {
+            return error_reply(msg, "org.freedesktop.DBus.Error.Failed", e);
+        }
+        _ => {}
+    }
+    let mut reply = msg.method_return();
+    {
+        let mut args = IterAppend::new(&mut reply);
+        match response {
+            comms::DaemonResponse::GetFanSpeed { rpm } => rpm.append(&mut args),
+            comms::DaemonResponse::GetPwrLevel { pwr } => pwr.append(&mut args),
+            comms::DaemonResponse::GetBrightness { result } => result.append(&mut args),
+            comms::DaemonResponse::GetLogoLedState { logo_state } => logo_state.append(&mut args),
+            comms::DaemonResponse::GetBatteryHealthOptimizer { is_on, threshold } => {
+                is_on.append(&mut args);
+                threshold.append(&mut args);
+            }
+            comms::DaemonResponse::GetDeviceName { name } => name.append(&mut args),
+            comms::DaemonResponse::SetFanSpeed { result, .. }
+            | comms::DaemonResponse::SetPowerMode { result, .. }
+            | comms::DaemonResponse::SetBrightness { result, .. }
+            | comms::DaemonResponse::SetLogoLedState { result }
+            | comms::DaemonResponse::SetBatteryHealthOptimizer { result } => result.append(&mut args),
+            _ => {}
+        }
+    }
+    reply
+}
+
+/// Builds an error reply to a method call
+pub fn error_reply(msg: &Message, name: &str, text: &str) -> Message {
+    let text = CString::new(text.replace('\0', "")).unwrap();
+    msg.error(&name.into(), &text)
+}
+
+/// Answers org.freedesktop.DBus.Introspectable calls
+pub fn introspect(msg: &Message) -> Message {
+    msg.method_return().append1(INTROSPECTION)
+}
+
+/// Builds the signal sent when the AC state or the power mode changes
+pub fn power_state_changed(ac: bool, pwr: u8) -> Message {
+    Message::new_signal(PATH, INTERFACE, "PowerStateChanged")
+        .unwrap()
+        .append2(ac, pwr)
+}
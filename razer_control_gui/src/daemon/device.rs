@@ -6,9 +6,80 @@ use hidapi::HidApi;
 use crate::dbus_mutter_idlemonitor;
 use crate::config;
 use crate::battery;
+use crate::charge;
 use dbus::blocking::Connection;
 
 const RAZER_VENDOR_ID: u16 = 0x1532;
+/// Manual fan speed range used when no laptop is detected, shared by most models
+pub const DEFAULT_FAN_LIMITS: (u16, u16) = (3500, 5000);
+
+/// Why a device operation failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceError {
+    /// No supported laptop was found
+    NoDevice,
+    /// A parameter is outside of the range the device accepts
+    InvalidArgument(String),
+    /// The laptop did not accept the command
+    WriteFailed,
+    /// Neither the kernel nor the laptop support the operation
+    Unsupported,
+    /// A sysfs file could not be written
+    Io(String),
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceError::NoDevice => write!(f, "No supported device found"),
+            DeviceError::InvalidArgument(e) => write!(f, "Invalid argument: {}", e),
+            DeviceError::WriteFailed => write!(f, "The device rejected the command"),
+            DeviceError::Unsupported => write!(f, "Not supported on this laptop"),
+            DeviceError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+/// Brightness adjustments start from this when the current value is unknown
+const DEFAULT_BRIGHTNESS_PERCENT: u8 = 50;
+
+/// Converts a hardware brightness (0-255) to percent, rounded
+fn to_percent(val: u8) -> u8 {
+    ((val as u32 * 100 + 127) / 255) as u8
+}
+
+/// Checks the AC state index used to pick the power profile
+fn check_ac(ac: usize) -> Result<(), DeviceError> {
+    if ac > 1 {
+        return Err(DeviceError::InvalidArgument(format!("AC state must be 0 or 1, got {}", ac)));
+    }
+    Ok(())
+}
+
+/// Applies a setting to the laptop if it belongs to the current AC state.
+/// Settings for the other AC state are only stored and applied on the next change
+fn apply_for_ac<F: FnOnce(&mut RazerLaptop) -> bool>(laptop: Option<&mut RazerLaptop>, ac: usize, apply: F) -> Result<(), DeviceError> {
+    let laptop = laptop.ok_or(DeviceError::NoDevice)?;
+    if laptop.get_ac_state() != ac || apply(laptop) {
+        Ok(())
+    } else {
+        Err(DeviceError::WriteFailed)
+    }
+}
+
+/// Clamps a fan speed to the (min, max) limits of the model, if known. 0
+/// selects automatic fan control and is kept, negative speeds are rejected
+fn clamp_fan_rpm(rpm: i32, limits: Option<(u16, u16)>) -> Result<i32, DeviceError> {
+    if rpm < 0 {
+        return Err(DeviceError::InvalidArgument(format!("Fan speed can't be negative, got {}", rpm)));
+    }
+    Ok(match limits {
+        Some((min, max)) if rpm != 0 => rpm.clamp(min as i32, max as i32),
+        _ => rpm,
+    })
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SupportedDevice {
@@ -19,6 +90,76 @@ pub struct SupportedDevice {
     pub fan: Vec<u16>,
 }
 
+impl SupportedDevice {
+    /// Checks the fields serde can't, the ids must be hexadecimal and the
+    /// fan range empty or a min and max
+    fn validate(&self) -> Result<(), String> {
+        for (field, id) in [("vid", &self.vid), ("pid", &self.pid)] {
+            if u16::from_str_radix(id, 16).is_err() {
+                return Err(format!("{} {:?} is not a hexadecimal USB id", field, id));
+            }
+        }
+        match self.fan[..] {
+            [] => Ok(()),
+            [min, max] if min <= max => Ok(()),
+            _ => Err(format!("fan must be [min, max], got {:?}", self.fan)),
+        }
+    }
+
+    fn same_usb_id(&self, other: &SupportedDevice) -> bool {
+        self.vid.eq_ignore_ascii_case(&other.vid) && self.pid.eq_ignore_ascii_case(&other.pid)
+    }
+
+    /// Parses the features and fan range of the model
+    pub fn capabilities(&self) -> Capabilities {
+        let has = |feature: &str| self.features.iter().any(|f| f == feature);
+        Capabilities {
+            logo: has("logo"),
+            // models marked "logo_static" only turn the logo on and off
+            logo_breathing: has("logo") && !has("logo_static"),
+            cpu_boost: has("boost"),
+            // older models marked "no_gpu_boost" have no GPU boost levels
+            gpu_boost: !has("no_gpu_boost"),
+            creator_mode: has("creator_mode"),
+            bho: has("bho"),
+            fan_limits: match self.fan[..] {
+                [min, max] => Some((min, max)),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// What a model supports, parsed from the laptops file when the device is
+/// discovered
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub logo: bool,
+    pub logo_breathing: bool,
+    pub cpu_boost: bool, // CPU boost level 3
+    pub gpu_boost: bool, // GPU boost levels 1 and 2
+    pub creator_mode: bool,
+    pub bho: bool,
+    pub fan_limits: Option<(u16, u16)>, // lowest and highest manual fan speed in RPM, None = no fan control
+}
+
+/// The hidraw device of a laptop. Tests use a fake one that records the
+/// reports, see `testing`
+pub trait ReportDevice: Send {
+    fn send_feature_report(&self, data: &[u8]) -> hidapi::HidResult<()>;
+    fn get_feature_report(&self, buf: &mut [u8]) -> hidapi::HidResult<usize>;
+}
+
+impl ReportDevice for hidapi::HidDevice {
+    fn send_feature_report(&self, data: &[u8]) -> hidapi::HidResult<()> {
+        hidapi::HidDevice::send_feature_report(self, data)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> hidapi::HidResult<usize> {
+        hidapi::HidDevice::get_feature_report(self, buf)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RazerPacket {
     report: u8,
@@ -73,39 +214,164 @@ impl RazerPacket {
 }
 
 const DEVICE_FILE: &str = "/usr/share/razercontrol/laptops.json";
+const LOCAL_DEVICE_FILE: &str = "/usr/local/share/razercontrol/laptops.json";
+/// The copy in the source tree, for running the daemon from the build tree
+const SOURCE_DEVICE_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/devices/laptops.json");
+
+/// Places the laptops file is looked for, the first one that exists is used
+fn get_device_file_paths() -> Vec<String> {
+    let data_home = std::env::var("XDG_DATA_HOME").ok()
+        .filter(|d| !d.is_empty())
+        .or_else(|| std::env::var("HOME").ok().map(|home| home + "/.local/share"));
+    data_home.map(|d| d + "/razercontrol/laptops.json")
+        .into_iter()
+        .chain([LOCAL_DEVICE_FILE, DEVICE_FILE, SOURCE_DEVICE_FILE].iter().map(|p| p.to_string()))
+        .collect()
+}
+
+/// User definitions added to, or replacing, the ones of the laptops file
+fn get_user_device_file_path() -> Option<String> {
+    std::env::var("XDG_CONFIG_HOME").ok()
+        .filter(|d| !d.is_empty())
+        .or_else(|| std::env::var("HOME").ok().map(|home| home + "/.config"))
+        .map(|d| d + "/razercontrol/laptops.json")
+}
+
+/// Parses a laptops file, skipping the entries that are malformed
+fn parse_device_file(data: &[u8], path: &str) -> io::Result<Vec<SupportedDevice>> {
+    let entries: Vec<serde_json::Value> = serde_json::from_slice(data)?;
+    Ok(entries.into_iter()
+        .enumerate()
+        .filter_map(|(i, entry)| {
+            match serde_json::from_value::<SupportedDevice>(entry).map_err(|e| e.to_string()).and_then(|d| d.validate().map(|_| d)) {
+                Ok(device) => Some(device),
+                Err(e) => {
+                    eprintln!("Skipping entry {} of {}: {}", i, path, e);
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
 pub struct DeviceManager {
-    pub device: Option <RazerLaptop>,
+    pub devices: Vec<RazerLaptop>,
+    selected: usize, // index of the device commands apply to
     supported_devices: Vec<SupportedDevice>,
     pub config: Option <config::Configuration>,
     pub idle_id: u32,
     pub active_id: u32,
     add_active: bool,
     pub change_idle: bool,
+    light_is_off: bool, // set by light_off until restore_light, so repeated events don't write again
+    manual_light_off: bool, // turned off by SetKeyboardPower, only it turns the light back on
+    pub config_loaded: bool, // settings were read from the config file
+    pub config_error: Option<String>,
+    pre_idle_brightness: Option<u8>, // brightness before dim_light, restored by restore_light
+    manual_brightness_at: Option<time::Instant>, // last set_brightness, pauses auto brightness
+    manual_fan_since: Option<time::Instant>, // start of the manual fan mode, for manual_fan_timeout_s
 }
 
 impl DeviceManager {
     pub fn new () -> DeviceManager {
         return DeviceManager {
-            device: None,
+            devices: vec![],
+            selected: 0,
             supported_devices: vec![],
             config: None,
             idle_id: 0,
             active_id: 0,
             add_active: false,
             change_idle: false,
+            light_is_off: false,
+            manual_light_off: false,
+            config_loaded: false,
+            config_error: None,
+            pre_idle_brightness: None,
+            manual_brightness_at: None,
+            manual_fan_since: None,
         };
     }
 
-    pub fn add_idle_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
-        if self.change_idle {
-            let mut timeout: u64 = 0;
-            let mut state: usize = 0;
-            if let Some(laptop) = self.get_device() {
-                state = laptop.get_ac_state();
+    /// Returns the idle timeout of the current AC state in milliseconds, 0 if disabled
+    pub fn get_idle_timeout_ms(&mut self) -> u64 {
+        let mut timeout: u64 = 0;
+        let mut state: usize = 0;
+        if let Some(laptop) = self.get_device() {
+            state = laptop.get_ac_state();
+        }
+        if let Some(config) = self.get_config() {
+            let power = &config.power[state];
+            timeout = if power.idle_ms != 0 {
+                power.idle_ms.max(config::MIN_IDLE_TIMEOUT_MS)
+            } else {
+                power.idle as u64 * 60 * 1000 // idle is in minutes timeout is in miliseconds
+            };
+        }
+        timeout
+    }
+
+    /// Sets the idle timeout in milliseconds, 0 falls back to the timeout in minutes
+    pub fn set_idle_timeout_ms(&mut self, ac: usize, timeout_ms: u64) -> bool {
+        let timeout_ms = if timeout_ms == 0 { 0 } else { timeout_ms.max(config::MIN_IDLE_TIMEOUT_MS) };
+        if let Some(config) = self.get_config() {
+            if config.power[ac].idle_ms != timeout_ms {
+                config.power[ac].idle_ms = timeout_ms;
+                if config.sync {
+                    let other = (ac + 1) & 0x01;
+                    config.power[other].idle_ms = timeout_ms;
+                }
+                if let Err(e) = config.write_to_file() {
+                    eprintln!("Error write config {:?}", e);
+                    return false;
+                }
+                self.change_idle = true;
+            }
+            return true;
+        }
+
+        false
+    }
+
+    /// Reads the settings again from daemon.json and config.toml, returning
+    /// the names of the settings that changed. The hardware is left alone,
+    /// the new values are picked up by the tasks using them
+    pub fn reload_config(&mut self) -> io::Result<Vec<&'static str>> {
+        let mut config = match config::Configuration::read_from_config() {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => config::Configuration::new(),
+            Err(e) => return Err(e),
+        };
+        config.apply_user_settings(&config::UserSettings::read_from_file()?);
+        let mut changes = vec![];
+        if let Some(old) = self.config.as_ref() {
+            if old.temperature != config.temperature {
+                changes.push("temperature profile");
+            }
+            if old.power.iter().zip(config.power.iter()).any(|(o, n)| o.idle != n.idle || o.idle_ms != n.idle_ms) {
+                changes.push("idle timeout");
             }
-            if let Some(config) = self.get_config() {
-                timeout = config.power[state].idle as u64 * 60 * 1000; // idle is in minutes timeout is in miliseconds
+            if (old.power_presets_enabled, old.on_ac, old.on_battery) != (config.power_presets_enabled, config.on_ac, config.on_battery) {
+                changes.push("power presets");
             }
+            if (&old.ac_event_script, &old.battery_event_script) != (&config.ac_event_script, &config.battery_event_script) {
+                changes.push("event scripts");
+            }
+            if (old.auto_brightness, old.ambient_light) != (config.auto_brightness, config.ambient_light) {
+                changes.push("auto brightness");
+            }
+        }
+        if changes.contains(&"idle timeout") {
+            self.change_idle = true;
+        }
+        self.config = Some(config);
+        self.config_error = None;
+        Ok(changes)
+    }
+
+    pub fn add_idle_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
+        if self.change_idle {
+            let timeout = self.get_idle_timeout_ms();
             if timeout != 0 {
                 if self.idle_id != 0 {
                     self.remove_watch(proxy_idle);
@@ -143,6 +409,100 @@ impl DeviceManager {
         return true;
     }
 
+    pub fn get_ac_debounce_ms(&self) -> u64 {
+        if let Some(config) = self.config.as_ref() {
+            return config.ac_debounce_ms;
+        }
+
+        500
+    }
+
+    /// Returns the script run on AC changes, absolute or relative to $HOME
+    pub fn get_ac_event_script(&self) -> Option<String> {
+        match self.config.as_ref() {
+            Some(config) => config.ac_event_script.clone(),
+            None => Some(String::from(config::DEFAULT_AC_EVENT_SCRIPT)),
+        }
+    }
+
+    /// Returns the low and full battery thresholds and the script run when
+    /// the battery crosses them
+    pub fn get_battery_hooks(&self) -> (f64, f64, Option<String>) {
+        match self.config.as_ref() {
+            Some(config) => (config.battery_low, config.battery_full, config.battery_event_script.clone()),
+            None => (15.0, 95.0, None),
+        }
+    }
+
+    /// Returns whether the wallpaper should be followed and the fallback path
+    pub fn get_wallpaper_settings(&self) -> (bool, Option<String>) {
+        if let Some(config) = self.config.as_ref() {
+            return (config.wallpaper_follow, config.wallpaper_path.clone());
+        }
+
+        (false, None)
+    }
+
+    /// Whether config.toml is watched for changes
+    pub fn get_watch_config(&self) -> bool {
+        self.config.as_ref().is_some_and(|c| c.watch_config)
+    }
+
+    pub fn get_temperature_profile(&self) -> config::TemperatureProfile {
+        self.config.as_ref().map_or(config::TemperatureProfile::new(), |c| c.temperature.clone())
+    }
+
+    /// Returns the configured keyboard action for a display PowerSaveMode value
+    pub fn get_display_power_action(&self, mode: i32) -> config::DisplayPowerAction {
+        let actions = match self.config.as_ref() {
+            Some(config) => config.display_power_actions,
+            None => config::Configuration::new().display_power_actions,
+        };
+        if mode < 0 || mode as usize >= actions.len() {
+            return config::DisplayPowerAction::Ignore;
+        }
+
+        actions[mode as usize]
+    }
+
+    /// Returns the overlay flag and per mode colours if power mode colour sync is enabled
+    pub fn get_power_mode_colour_sync(&self) -> Option<(bool, [[u8; 3]; 5])> {
+        let config = self.config.as_ref()?;
+        if !config.power_mode_colour_sync {
+            return None;
+        }
+
+        Some((config.power_mode_colour_overlay, config.power_mode_colours))
+    }
+
+    pub fn set_power_mode_colour_sync(&mut self, enabled: bool, overlay: bool, colours: Option<[[u8; 3]; 5]>) -> bool {
+        if let Some(config) = self.get_config() {
+            config.power_mode_colour_sync = enabled;
+            config.power_mode_colour_overlay = overlay;
+            if let Some(colours) = colours {
+                config.power_mode_colours = colours;
+            }
+            if let Err(e) = config.write_to_file() {
+                eprintln!("Error write config {:?}", e);
+                return false;
+            }
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns the (power mode, cpu boost, gpu boost) preset for the given AC
+    /// state, None unless power presets are enabled
+    pub fn get_power_preset(&self, ac: usize) -> Option<(u8, u8, u8)> {
+        let config = self.config.as_ref()?;
+        if !config.power_presets_enabled {
+            return None;
+        }
+
+        Some(if ac == 1 { config.on_ac } else { config.on_battery })
+    }
+
     pub fn get_sync(&mut self) -> bool {
         if let Some(config) = self.get_config() {
             return config.sync;
@@ -157,6 +517,32 @@ impl DeviceManager {
         }
     }
 
+    /// Removes the idle and user active watches from the idle monitor
+    pub fn remove_watches(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
+        for id in [self.idle_id, self.active_id] {
+            if id != 0 {
+                if let Err(e) = proxy_idle.remove_watch(id) {
+                    eprintln!("Could not remove idle monitor watch {}: {}", id, e);
+                }
+            }
+        }
+        self.idle_id = 0;
+        self.active_id = 0;
+        self.add_active = false;
+    }
+
+    /// Leaves the keyboard in a defined state when the daemon exits, either
+    /// off or showing the standard effect, rather than on the last frame of
+    /// an animation
+    pub fn prepare_exit(&mut self) {
+        if self.config.as_ref().is_some_and(|c| c.exit_light_off) {
+            self.light_off();
+        } else {
+            self.restore_light();
+            self.restore_standard_effect();
+        }
+    }
+
     pub fn add_active_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
         if self.add_active {
             if let Ok(id) = proxy_idle.add_user_active_watch() {
@@ -167,13 +553,45 @@ impl DeviceManager {
     }
 
     pub fn read_laptops_file() -> io::Result<DeviceManager > {
-        let str: Vec<u8> = fs::read(DEVICE_FILE)?;
+        let paths = get_device_file_paths();
+        let path = paths.iter()
+            .find(|p| fs::metadata(p).is_ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No laptops file in {}", paths.join(", "))))?;
+        println!("reading supported devices from {}", path);
+        let str: Vec<u8> = fs::read(path)?;
         let mut res: DeviceManager = DeviceManager::new();
-        res.supported_devices = serde_json::from_slice(str.as_slice())?;
+        res.supported_devices = parse_device_file(&str, path)?;
+        if let Some(user_path) = get_user_device_file_path() {
+            match fs::read(&user_path).and_then(|data| parse_device_file(&data, &user_path)) {
+                Ok(devices) => res.add_supported_devices(devices),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => eprintln!("Could not read {}: {}", user_path, e),
+            }
+        }
         println!("suported devices found: {:?}", res.supported_devices.len());
         match config::Configuration::read_from_config() {
-            Ok(c) => res.config = Some(c),
-            Err(_) => res.config = Some(config::Configuration::new()),
+            Ok(c) => {
+                res.config = Some(c);
+                res.config_loaded = true;
+            },
+            Err(e) => {
+                let mut config = config::Configuration::new();
+                if e.kind() != io::ErrorKind::NotFound {
+                    res.config_error = Some(format!("Error reading config: {}", e));
+                } else if let Err(e) = config.write_to_file() {
+                    // First run, write the defaults so the settings can be found and edited
+                    eprintln!("Error write config {:?}", e);
+                }
+                res.config = Some(config);
+            },
+        }
+        match config::UserSettings::read_from_file() {
+            Ok(settings) => {
+                if let Some(config) = res.config.as_mut() {
+                    config.apply_user_settings(&settings);
+                }
+            },
+            Err(e) => res.config_error = Some(format!("Error reading config.toml: {}", e)),
         }
 
         Ok(res)
@@ -187,36 +605,103 @@ impl DeviceManager {
         return None;
     }
 
+    /// Turns the keyboard and logo light off. Several sources (display power
+    /// save, screensaver, idle) can ask for it at once, only the first call
+    /// writes to the device
     pub fn light_off(&mut self) {
         if self.idle_id != 0 {
             self.add_active = true;
         }
+        if self.light_is_off {
+            return;
+        }
         if let Some(laptop) = self.get_device() {
             laptop.set_screensaver(true);
             laptop.set_brightness(0);
             laptop.set_logo_led_state(0);
         }
+        self.light_is_off = true;
     }
 
+    /// Returns the brightness (percent) the keyboard dims to on idle, 0 = off
+    pub fn get_idle_brightness(&self) -> u8 {
+        self.config.as_ref().map_or(0, |c| c.idle_brightness)
+    }
+
+    /// Dims the keyboard to the given brightness (percent) until restore_light,
+    /// or turns it off like light_off if the level is 0
+    pub fn dim_light(&mut self, level: u8) {
+        if level == 0 {
+            self.light_off();
+            return;
+        }
+        if self.idle_id != 0 {
+            self.add_active = true;
+        }
+        if self.light_is_off {
+            return;
+        }
+        let target = (level.min(100) as u16 * 255 / 100) as u8;
+        let mut pre_idle = self.pre_idle_brightness;
+        if let Some(laptop) = self.get_device() {
+            let current = *pre_idle.get_or_insert_with(|| laptop.get_brightness());
+            if current > target {
+                laptop.set_brightness(target);
+            }
+        }
+        self.pre_idle_brightness = pre_idle;
+    }
+
+    /// Turns the backlight off or back on on request. While it is off this
+    /// way, restore_light leaves it off, and the brightness it had is kept
+    /// for when it is turned on
+    pub fn set_keyboard_power(&mut self, on: bool) -> Result<(), DeviceError> {
+        if self.get_device().is_none() {
+            return Err(DeviceError::NoDevice);
+        }
+        if on {
+            self.manual_light_off = false;
+            self.restore_light();
+        } else {
+            self.light_off();
+            self.manual_light_off = true;
+        }
+        Ok(())
+    }
+
+    /// Undoes light_off and dim_light, a no-op if the light is neither off
+    /// nor dimmed, or was turned off by set_keyboard_power
     pub fn restore_light(&mut self) {
         self.add_active = false;
+        if self.manual_light_off || (!self.light_is_off && self.pre_idle_brightness.is_none()) {
+            return;
+        }
+        self.light_is_off = false;
+        let pre_idle = self.pre_idle_brightness.take();
         let mut brightness = 0;
         let mut logo_state = 0;
+        let mut logo_brightness = None;
         let mut ac:usize = 0;
         if let Some(laptop) = self.get_device() {
             ac = laptop.get_ac_state();
         }
         if let Some(config) = self.get_ac_config(ac) {
-            brightness = config.brightness;
+            brightness = pre_idle.unwrap_or(config.brightness);
             logo_state = config.logo_state;
+            logo_brightness = config.logo_brightness;
         }
         if let Some(laptop) = self.get_device() {
             laptop.set_screensaver(false);
             laptop.set_brightness(brightness);
-            laptop.set_logo_led_state(logo_state);
+            laptop.set_logo(logo_state, logo_brightness);
         }
     }
 
+    /// Whether a standard effect other than "off" is saved in the config
+    pub fn has_standard_effect(&self) -> bool {
+        self.config.as_ref().is_some_and(|c| c.standard_effect != RazerLaptop::OFF)
+    }
+
     pub fn restore_standard_effect(&mut self) {
         let mut effect = 0;
         let mut params: Vec<u8> = vec![];
@@ -229,6 +714,49 @@ impl DeviceManager {
         }
     }
 
+    /// Re-applies the saved power, effect and logo settings after the device
+    /// was opened again
+    pub fn restore_device_state(&mut self) {
+        self.set_ac_state_get();
+        if let Err(e) = self.restore_power_mode() {
+            eprintln!("Could not restore the power mode: {}", e);
+        }
+        if !self.restore_bho() {
+            eprintln!("Could not restore the battery health optimizer");
+        }
+        if let Err(e) = self.restore_charge_limit() {
+            eprintln!("Could not restore the charge limit: {}", e);
+        }
+        if !self.restore_lighting() {
+            eprintln!("Could not restore the logo state");
+        }
+    }
+
+    /// Writes the saved standard effect and then the logo state, which the
+    /// effect can reset. False if the logo state could not be restored
+    pub fn restore_lighting(&mut self) -> bool {
+        self.restore_standard_effect();
+        self.restore_logo_state()
+    }
+
+    /// Re-applies the saved logo state for the current AC state
+    pub fn restore_logo_state(&mut self) -> bool {
+        let ac = match self.get_device() {
+            Some(laptop) => laptop.get_ac_state(),
+            None => return false,
+        };
+        let logo_state = self.get_logo_led_state(ac);
+        let logo_brightness = self.get_ac_config(ac).and_then(|c| c.logo_brightness);
+        if let Some(laptop) = self.get_device() {
+            if !laptop.capabilities().logo {
+                return true;
+            }
+            return laptop.set_logo(logo_state, logo_brightness);
+        }
+
+        false
+    }
+
     pub fn change_idle(&mut self, ac: usize, timeout: u32) -> bool {
         // let mut arm: bool = false;
         if let Some(config) = self.get_config() {
@@ -249,8 +777,23 @@ impl DeviceManager {
         return true;
     }
 
-    pub fn set_power_mode(&mut self, ac: usize, pwr: u8, cpu: u8, gpu: u8) -> bool {
-        let mut res: bool = false;
+    /// Returns the stored idle timeouts of an AC state, in minutes (SetIdle)
+    /// and in milliseconds (SetIdleTimeout)
+    pub fn get_idle(&mut self, ac: usize) -> Result<(u32, u64), DeviceError> {
+        check_ac(ac)?;
+        Ok(self.get_ac_config(ac).map_or((0, 0), |power| (power.idle, power.idle_ms)))
+    }
+
+    pub fn set_power_mode(&mut self, ac: usize, pwr: u8, cpu: u8, gpu: u8) -> Result<(), DeviceError> {
+        check_ac(ac)?;
+        if pwr > 4 {
+            return Err(DeviceError::InvalidArgument(format!("Power mode must be between 0 and 4, got {}", pwr)));
+        }
+        if let Some(laptop) = self.get_device() {
+            if !laptop.supports_power_mode(pwr, cpu, gpu) {
+                return Err(DeviceError::InvalidArgument(format!("Power mode {}/{}/{} is not supported by this laptop", pwr, cpu, gpu)));
+            }
+        }
         if let Some(config) = self.get_config() {
             config.power[ac].power_mode = pwr;
             config.power[ac].cpu_boost = cpu;
@@ -259,16 +802,25 @@ impl DeviceManager {
                 eprintln!("Error write config {:?}", e);
             }
         }
-        if let Some(laptop) = self.get_device() {
-            let state = laptop.get_ac_state();
-            if state != ac {
-                res = true;
-            } else {
-                res = laptop.set_power_mode(pwr, cpu, gpu);
+        apply_for_ac(self.get_device(), ac, |laptop| laptop.set_power_mode(pwr, cpu, gpu))
+    }
+
+    /// Applies the power mode stored for the current AC state. A mode the
+    /// laptop doesn't support, e.g. from a config written on another model,
+    /// is replaced by balanced
+    pub fn restore_power_mode(&mut self) -> Result<(), DeviceError> {
+        let ac = self.get_device().ok_or(DeviceError::NoDevice)?.get_ac_state();
+        let config = match self.get_ac_config(ac) {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        match self.set_power_mode(ac, config.power_mode, config.cpu_boost, config.gpu_boost) {
+            Err(DeviceError::InvalidArgument(e)) => {
+                eprintln!("Stored power mode is invalid ({}), falling back to balanced", e);
+                self.set_power_mode(ac, 0, 0, 0)
             }
+            res => res,
         }
-
-        return res;
     }
 
     pub fn set_standard_effect(&mut self, effect_id: u8, params: Vec<u8>) -> bool {
@@ -286,25 +838,91 @@ impl DeviceManager {
         return true;
     }
 
-    pub fn set_fan_rpm(&mut self, ac:usize, rpm: i32) -> bool {
-        let mut res: bool = false;
+    /// Sets the fan speed, 0 meaning automatic. Other speeds are clamped to the
+    /// limits of the laptop model, returns whether that happened
+    pub fn set_fan_rpm(&mut self, ac:usize, rpm: i32) -> Result<bool, DeviceError> {
+        check_ac(ac)?;
+        let requested = rpm;
+        let rpm = clamp_fan_rpm(rpm, self.devices.get(self.selected).map(|laptop| laptop.get_fan_limits()))?;
+        let clamped = rpm != requested;
+        if clamped {
+            eprintln!("Fan speed {} clamped to {}", requested, rpm);
+        }
         if let Some(config) = self.get_config() {
             config.power[ac].fan_rpm = rpm;
             if let Err(e) = config.write_to_file() {
                 eprintln!("Error write config {:?}", e);
             }
         }
-             
-        if let Some(laptop) = self.get_device() {
-            let state = laptop.get_ac_state();
-            if state != ac {
-                res = true;
-            } else {
-                res = laptop.set_fan_rpm(rpm as u16);
+
+        apply_for_ac(self.get_device(), ac, |laptop| laptop.set_fan_rpm(rpm as u16))?;
+        Ok(clamped)
+    }
+
+    pub fn set_fan_ceiling(&mut self, ac: usize, max_rpm: i32, strict: bool) -> bool {
+        if let Some(config) = self.get_config() {
+            config.power[ac].fan_ceiling = max_rpm;
+            config.power[ac].fan_ceiling_strict = strict;
+            if let Err(e) = config.write_to_file() {
+                eprintln!("Error write config {:?}", e);
+                return false;
             }
+            return true;
         }
 
-        return res;
+        false
+    }
+
+    /// Returns what the selected device supports, None without a device
+    pub fn capabilities(&self) -> Option<&Capabilities> {
+        self.devices.get(self.selected).map(|laptop| laptop.capabilities())
+    }
+
+    /// Returns the manual fan speed range of the laptop, or the default range without one
+    pub fn get_fan_limits(&self) -> (u16, u16) {
+        self.capabilities().and_then(|c| c.fan_limits).unwrap_or(DEFAULT_FAN_LIMITS)
+    }
+
+    pub fn get_fan_control_mode(&self) -> config::FanControlMode {
+        self.config.as_ref().map_or(config::FanControlMode::Daemon, |c| c.fan_control_mode)
+    }
+
+    /// Sets who controls the fan. Auto hands the fan back to the firmware
+    /// right away, the other modes take effect on the next fan change
+    pub fn set_fan_control_mode(&mut self, mode: config::FanControlMode) -> Result<(), DeviceError> {
+        let ac = self.get_device().ok_or(DeviceError::NoDevice)?.get_ac_state();
+        self.manual_fan_since = Some(time::Instant::now());
+        if let Some(config) = self.get_config() {
+            config.fan_control_mode = mode;
+            if let Err(e) = config.write_to_file() {
+                eprintln!("Error write config {:?}", e);
+            }
+        }
+        if mode == config::FanControlMode::Auto {
+            self.set_fan_rpm(ac, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Goes back to daemon mode once manual mode is older than the configured
+    /// timeout, true if it did. A manual mode restored from the config counts
+    /// from the first check
+    pub fn expire_manual_fan_mode(&mut self) -> bool {
+        let timeout = match self.config.as_ref() {
+            Some(c) if c.fan_control_mode == config::FanControlMode::Manual && c.manual_fan_timeout_s > 0 => c.manual_fan_timeout_s,
+            _ => return false,
+        };
+        let since = *self.manual_fan_since.get_or_insert_with(time::Instant::now);
+        if since.elapsed() < time::Duration::from_secs(timeout) {
+            return false;
+        }
+        self.set_fan_control_mode(config::FanControlMode::Daemon).is_ok()
+    }
+
+    /// Returns the fan ceiling (0 = no limit) and whether it also applies at critical temperatures
+    pub fn get_fan_ceiling(&mut self, ac: usize) -> (i32, bool) {
+        self.get_ac_config(ac)
+            .map_or((0, false), |c| (c.fan_ceiling, c.fan_ceiling_strict))
     }
 
     pub fn set_logo_led_state(&mut self, ac:usize, logo_state: u8) -> bool {
@@ -333,6 +951,39 @@ impl DeviceManager {
         return res;
     }
 
+    /// Sets the logo mode (0 = off, 1 = on, 2 = breathing) and optionally its
+    /// brightness in percent. Models without a logo, or without a breathing
+    /// one, reject the mode instead of ignoring it
+    pub fn set_logo_led_mode(&mut self, ac: usize, logo_state: u8, brightness: Option<u8>) -> Result<(), DeviceError> {
+        check_ac(ac)?;
+        if logo_state > 2 {
+            return Err(DeviceError::InvalidArgument(format!("Logo mode must be between 0 and 2, got {}", logo_state)));
+        }
+        if let Some(b) = brightness.filter(|b| *b > 100) {
+            return Err(DeviceError::InvalidArgument(format!("Brightness must be between 0 and 100, got {}", b)));
+        }
+        let laptop = self.get_device().ok_or(DeviceError::NoDevice)?;
+        let capabilities = laptop.capabilities();
+        if !capabilities.logo || (logo_state == 2 && !capabilities.logo_breathing) {
+            return Err(DeviceError::Unsupported);
+        }
+        let brightness = brightness.map(|b| (b as u16 * 255 / 100) as u8);
+        if let Some(config) = self.get_config() {
+            let others = if config.sync { vec![0, 1] } else { vec![ac] };
+            for i in others {
+                config.power[i].logo_state = logo_state;
+                if brightness.is_some() {
+                    config.power[i].logo_brightness = brightness;
+                }
+            }
+            if let Err(e) = config.write_to_file() {
+                eprintln!("Error write config {:?}", e);
+            }
+        }
+
+        apply_for_ac(self.get_device(), ac, |laptop| laptop.set_logo(logo_state, brightness))
+    }
+
     pub fn get_logo_led_state(&mut self, ac: usize) -> u8 {
         // if let Some(laptop) = self.get_device() {
             // if laptop.ac_state as usize == ac {
@@ -347,9 +998,82 @@ impl DeviceManager {
         return 0;
     }
 
-    pub fn set_brightness(&mut self, ac:usize, brightness: u8) -> bool {
-        let mut res: bool = false;
+    /// Changes the brightness by `delta` percent, clamped to 0-100, and returns
+    /// the new value. If the device can't be read the stored brightness is
+    /// the starting point, or half brightness without a config
+    pub fn adjust_brightness(&mut self, ac: usize, delta: i32) -> Result<u8, DeviceError> {
+        check_ac(ac)?;
+        let current = self.get_device()
+            .filter(|laptop| laptop.ac_state as usize == ac)
+            .and_then(|laptop| laptop.read_brightness())
+            .or_else(|| self.get_ac_config(ac).map(|c| c.brightness))
+            .map_or(DEFAULT_BRIGHTNESS_PERCENT, to_percent);
+        let brightness = (current as i32 + delta).clamp(0, 100) as u8;
+        self.set_brightness(ac, brightness)?;
+        Ok(brightness)
+    }
+
+    /// Percent the brightness is stepped by, see `adjust_brightness`
+    pub fn get_brightness_step(&self) -> u8 {
+        self.config.as_ref().map_or(10, |c| c.brightness_step)
+    }
+
+    /// Returns the ambient light profile if auto brightness is enabled
+    pub fn get_auto_brightness(&self) -> Option<config::AmbientLightProfile> {
+        self.config.as_ref().filter(|c| c.auto_brightness).map(|c| c.ambient_light)
+    }
+
+    /// Sets the brightness chosen from the ambient light, without saving it.
+    /// Returns false if it was left alone because the light is off or dimmed,
+    /// or the brightness was set by hand less than `grace` ago
+    pub fn set_auto_brightness(&mut self, brightness: u8, grace: time::Duration) -> bool {
+        if self.light_is_off || self.pre_idle_brightness.is_some() {
+            return false;
+        }
+        if self.manual_brightness_at.is_some_and(|at| at.elapsed() < grace) {
+            return false;
+        }
+        match self.get_device() {
+            Some(laptop) => laptop.set_brightness((brightness.min(100) as u16 * 255 / 100) as u8),
+            None => false,
+        }
+    }
+
+    /// Duration of the crossfade between effects, 0 if they switch instantly
+    pub fn get_effect_fade_ms(&self) -> u32 {
+        self.config.as_ref().map_or(0, |c| c.effect_fade_ms)
+    }
+
+    /// Frames per second of keyboard animations stored in the config
+    pub fn get_animation_fps(&self) -> Option<u32> {
+        self.config.as_ref().map(|c| c.animation_fps)
+    }
+
+    pub fn set_animation_fps(&mut self, fps: u32) {
+        if let Some(config) = self.get_config() {
+            config.animation_fps = fps;
+            if let Err(e) = config.write_to_file() {
+                eprintln!("Error write config {:?}", e);
+            }
+        }
+    }
+
+    /// Checks the arguments of set_brightness without writing anything
+    pub fn check_brightness(&mut self, ac: usize, brightness: u8) -> Result<(), DeviceError> {
+        check_ac(ac)?;
+        if brightness > 100 {
+            return Err(DeviceError::InvalidArgument(format!("Brightness must be between 0 and 100, got {}", brightness)));
+        }
+        self.get_device().map(|_| ()).ok_or(DeviceError::NoDevice)
+    }
+
+    pub fn set_brightness(&mut self, ac:usize, brightness: u8) -> Result<(), DeviceError> {
+        check_ac(ac)?;
+        if brightness > 100 {
+            return Err(DeviceError::InvalidArgument(format!("Brightness must be between 0 and 100, got {}", brightness)));
+        }
         let _val = brightness as u16  * 255 / 100;
+        self.manual_brightness_at = Some(time::Instant::now());
         if let Some(config) = self.get_config() {
             config.power[ac].brightness = _val as u8;
             if config.sync {
@@ -361,16 +1085,7 @@ impl DeviceManager {
             }
         }
  
-        if let Some(laptop) = self.get_device() {
-            let state = laptop.get_ac_state();
-            if state != ac {
-                res = true;
-            } else {
-                res = laptop.set_brightness(_val as u8);
-            }
-        }
-
-        return res;
+        apply_for_ac(self.get_device(), ac, |laptop| laptop.set_brightness(_val as u8))
     }
 
     pub fn get_brightness(&mut self, ac: usize) -> u8 {
@@ -409,6 +1124,12 @@ impl DeviceManager {
         return 0;
     }
 
+    /// The fan speed stored in the configuration. Unlike get_fan_rpm this
+    /// ignores a safety floor the temperature monitor wrote to the laptop
+    pub fn get_saved_fan_rpm(&mut self, ac: usize) -> i32 {
+        self.get_ac_config(ac).map_or(0, |config| config.fan_rpm)
+    }
+
     pub fn get_power_mode(&mut self, ac:usize) -> u8 {
         if let Some(laptop) = self.get_device() {
             if laptop.ac_state as usize == ac {
@@ -451,13 +1172,24 @@ impl DeviceManager {
         return 0;
     }
 
+    /// Applies the settings stored for the new AC state. The stored brightness
+    /// is only applied if ac_brightness is set, and while idle has dimmed the
+    /// keyboard it becomes the brightness restore_light goes back to
     pub fn set_ac_state(&mut self, ac: bool) {
-        if let Some(laptop) = self.get_device() {
+        let current = self.get_device().map(|laptop| {
             laptop.set_ac_state(ac);
-        }
+            laptop.get_brightness()
+        });
         self.change_idle = true;
+        let apply_brightness = self.config.as_ref().is_some_and(|c| c.ac_brightness);
         let config: Option<config::PowerConfig> = self.get_ac_config(ac as usize);
-        if let Some(config) = config {
+        if let (Some(mut config), Some(current)) = (config, current) {
+            if !apply_brightness {
+                config.brightness = current;
+            } else if self.pre_idle_brightness.is_some() {
+                self.pre_idle_brightness = Some(config.brightness);
+                config.brightness = current.min(config.brightness);
+            }
             if let Some(laptop) = self.get_device() {
                 laptop.set_config(config);
             }
@@ -484,21 +1216,114 @@ impl DeviceManager {
 
     }
 
+    /// Returns the device commands currently apply to, the first one by default
     pub fn get_device(&mut self) -> Option<&mut RazerLaptop> {
-        return self.device.as_mut();
+        return self.devices.get_mut(self.selected);
     }
 
+    pub fn get_selected_device(&self) -> usize {
+        self.selected
+    }
+
+    /// Makes the following commands apply to another device, false if there is
+    /// no device with that index
+    pub fn select_device(&mut self, index: usize) -> bool {
+        if index >= self.devices.len() && index != 0 {
+            return false;
+        }
+        self.selected = index;
+        true
+    }
+
+    /// Sets the battery health optimizer and stores it so it survives reboots
     pub fn set_bho_handler(&mut self, is_on: bool, threshold: u8) -> bool {
+        if !self.apply_bho(is_on, threshold) {
+            return false;
+        }
+        if let Some(config) = self.get_config() {
+            config.bho = Some((is_on, threshold));
+            if let Err(e) = config.write_to_file() {
+                eprintln!("Error write config {:?}", e);
+            }
+        }
+        true
+    }
+
+    fn apply_bho(&mut self, is_on: bool, threshold: u8) -> bool {
+        if is_on && !is_valid_bho_threshold(threshold) {
+            eprintln!("Battery health optimizer threshold {} is not supported", threshold);
+            return false;
+        }
         return self.get_device()
             .map_or(false, |laptop| laptop.set_bho(is_on, threshold));
     }
 
+    /// Re-applies the stored battery health optimizer state, false if it
+    /// could not be written
+    pub fn restore_bho(&mut self) -> bool {
+        match self.get_config().and_then(|c| c.bho) {
+            Some((is_on, threshold)) => self.apply_bho(is_on, threshold),
+            None => true,
+        }
+    }
+
     pub fn get_bho_handler(&mut self) -> Option<(bool, u8)> {
         return self.get_device()
             .and_then(|laptop| laptop.get_bho()
             .map(|result| byte_to_bho(result)));
     } 
 
+    /// Sets the percentage the battery stops charging at and stores it. The
+    /// kernel threshold is used where it exists, otherwise the battery health
+    /// optimizer, which only takes multiples of 5 up to 80 (100 turns it off)
+    pub fn set_charge_limit(&mut self, percent: u8) -> Result<(), DeviceError> {
+        if !(50..=100).contains(&percent) {
+            return Err(DeviceError::InvalidArgument(format!("Charge limit must be between 50 and 100, got {}", percent)));
+        }
+        self.apply_charge_limit(percent)?;
+        if let Some(config) = self.get_config() {
+            config.charge_limit = Some(percent);
+            if let Err(e) = config.write_to_file() {
+                eprintln!("Error write config {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_charge_limit(&mut self, percent: u8) -> Result<(), DeviceError> {
+        if charge::is_supported() {
+            return charge::write_limit(percent)
+                .map_err(|e| DeviceError::Io(format!("Could not write the charge threshold: {}", e)));
+        }
+        let laptop = self.get_device().ok_or(DeviceError::NoDevice)?;
+        if !laptop.capabilities().bho {
+            return Err(DeviceError::Unsupported);
+        }
+        let written = match percent {
+            100 => laptop.set_bho(false, 80),
+            p if p <= 80 && p.is_multiple_of(5) => laptop.set_bho(true, p),
+            _ => return Err(DeviceError::InvalidArgument(String::from(
+                "The battery health optimizer only takes multiples of 5 between 50 and 80, or 100 to turn it off"))),
+        };
+        if written { Ok(()) } else { Err(DeviceError::WriteFailed) }
+    }
+
+    /// Returns the percentage the battery stops charging at, None if it can't be limited
+    pub fn get_charge_limit(&mut self) -> Option<u8> {
+        if charge::is_supported() {
+            return charge::read_limit();
+        }
+        self.get_bho_handler().map(|(is_on, threshold)| if is_on { threshold } else { 100 })
+    }
+
+    /// Applies the stored charge limit, the kernel resets its threshold on reboot
+    pub fn restore_charge_limit(&mut self) -> Result<(), DeviceError> {
+        match self.get_config().and_then(|c| c.charge_limit) {
+            Some(percent) => self.apply_charge_limit(percent),
+            None => Ok(()),
+        }
+    }
+
     fn get_config(&mut  self) -> Option<&mut config::Configuration> {
         return self.config.as_mut();
     }
@@ -507,9 +1332,20 @@ impl DeviceManager {
         // self.device = Some(device);
     // }
 
+    /// Adds user definitions, replacing the built-in ones with the same USB ids
+    fn add_supported_devices(&mut self, devices: Vec<SupportedDevice>) {
+        for device in devices {
+            println!("user defined device: {} ({}:{})", device.name, device.vid, device.pid);
+            match self.supported_devices.iter_mut().find(|d| d.same_usb_id(&device)) {
+                Some(existing) => *existing = device,
+                None => self.supported_devices.push(device),
+            }
+        }
+    }
+
     pub fn find_supported_device(&mut self, vid: u16, pid: u16) -> Option<&SupportedDevice> {
         for device in &self.supported_devices {
-            // Unwrap: we control the strings and know they are are valid
+            // Unwrap: the ids are validated when the laptops files are read
             let svid = u16::from_str_radix(&device.vid, 16).unwrap();
             let spid = u16::from_str_radix(&device.pid, 16).unwrap();
 
@@ -522,6 +1358,8 @@ impl DeviceManager {
     }
 
     pub fn discover_devices(&mut self)  {
+        // Forget the old devices, so a device that went away shows up as None
+        self.devices.clear();
         // Check if socket is OK
         match HidApi::new() {
             Ok(api) => {
@@ -536,13 +1374,12 @@ impl DeviceManager {
 
                         match api.open_path(device.path()) {
                             Ok(dev) => {
-                                self.device = Some(RazerLaptop::new(
+                                let laptop = RazerLaptop::new(
                                     supported_device.name.clone(),
-                                    supported_device.features.clone(),
-                                    supported_device.fan.clone(),
-                                    dev
-                                ));
-                                break;
+                                    supported_device.capabilities(),
+                                    Box::new(dev)
+                                );
+                                self.devices.push(laptop);
                             },
                             Err(e) => {
                                 eprintln!("Error: {}", e);
@@ -560,13 +1397,14 @@ impl DeviceManager {
 
 pub struct RazerLaptop {
     name: String,
-    features: Vec<String>,
-    fan: Vec<u16>,
-    device: hidapi::HidDevice,
+    capabilities: Capabilities,
+    device: Box<dyn ReportDevice>,
     power: u8, // need for fan
     fan_rpm: u8, // need for power
     ac_state: u8, // index config array
     screensaver: bool,
+    brightness: u8, // last brightness requested
+    software_dimming: bool, // keep the hardware at full brightness, effects do the dimming
 }
 //
 impl RazerLaptop {
@@ -588,16 +1426,17 @@ impl RazerLaptop {
     #[allow(dead_code)]
     pub const STARLIGHT:u8 = 0x19;
 
-    pub fn new(name: String, features: Vec<String>, fan: Vec<u16>, device: hidapi::HidDevice) -> RazerLaptop {
+    pub fn new(name: String, capabilities: Capabilities, device: Box<dyn ReportDevice>) -> RazerLaptop {
         return RazerLaptop{
             name,
-            features,
-            fan,
+            capabilities,
             device,
             power: 0,
             fan_rpm: 0,
             ac_state: 0,
-            screensaver: false
+            screensaver: false,
+            brightness: 255,
+            software_dimming: false,
         };
     }
 
@@ -610,7 +1449,7 @@ impl RazerLaptop {
 
         if !self.screensaver {
             ret |= self.set_brightness(config.brightness);
-            ret |= self.set_logo_led_state(config.logo_state);
+            ret |= self.set_logo(config.logo_state, config.logo_brightness);
         } else {
             ret |= self.set_brightness(0);
             ret |= self.set_logo_led_state(0);
@@ -639,19 +1478,23 @@ impl RazerLaptop {
         return self.name.clone();
     }
 
-    pub fn have_feature(&mut self, fch: String) -> bool {
-        return self.features.contains(&fch);
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
     }
 
-    fn clamp_fan(&mut self, rpm: u16) -> u8 {
-        if rpm > self.fan[1] {
-            return (self.fan[1] / 100) as u8;
-        }
-        if rpm < self.fan[0] {
-            return (self.fan[0] / 100) as u8;
-        }
+    /// Returns the lowest and highest manual fan speed of the model in RPM
+    pub fn get_fan_limits(&self) -> (u16, u16) {
+        self.capabilities.fan_limits.unwrap_or(DEFAULT_FAN_LIMITS)
+    }
 
-        return (rpm / 100) as u8;
+    /// Whether the fan speed can be set manually, models without a fan range can't
+    pub fn has_fan_control(&self) -> bool {
+        self.capabilities.fan_limits.is_some()
+    }
+
+    fn clamp_fan(&mut self, rpm: u16) -> u8 {
+        let (min, max) = self.get_fan_limits();
+        (rpm.clamp(min, max) / 100) as u8
     }
 
     fn clamp_u8(&mut self, value: u8, min: u8, max: u8) ->u8 {
@@ -665,6 +1508,19 @@ impl RazerLaptop {
         return value;
     }
 
+    /// Valid ranges of the non colour standard effect parameters, as
+    /// (effect, parameter, min, max). The firmware doesn't report these, so
+    /// this is the range known to work across models
+    pub fn get_standard_effect_ranges(&self) -> Vec<(&'static str, &'static str, u8, u8)> {
+        vec![
+            ("wave", "direction", 0, 1),
+            ("reactive", "speed", 1, 3),
+            ("breathing", "kind", 0, 2),
+            ("starlight", "kind", 0, 2),
+            ("starlight", "speed", 1, 3),
+        ]
+    }
+
     pub fn set_standard_effect(&mut self, effect_id: u8, params: Vec<u8>) -> bool {
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x0a, 80);
         report.args[0] = effect_id; // effect id
@@ -680,7 +1536,7 @@ impl RazerLaptop {
         return false;
     }
 
-    pub fn set_custom_frame_data(&mut self, row: u8, data: Vec<u8>) {
+    pub fn set_custom_frame_data(&mut self, row: u8, data: Vec<u8>) -> bool {
         // if data.len() == kbd::board::KEYS_PER_ROW {
         if data.len() == 45 {
             let mut report: RazerPacket = RazerPacket::new(0x03, 0x0b, 0x34);
@@ -691,8 +1547,10 @@ impl RazerLaptop {
             for idx in 0..data.len() {
                 report.args[idx + 7] = data[idx];
             }
-            self.send_report(report);
+            return self.send_report(report).is_some();
         }
+
+        false
     }
 
     pub fn set_custom_frame(&mut self) -> bool {
@@ -747,7 +1605,7 @@ impl RazerLaptop {
 
     fn set_cpu_boost(&mut self, mut boost: u8) -> bool {
         let mut report: RazerPacket = RazerPacket::new(0x0d, 0x07, 0x03);
-        if boost == 3 && !self.have_feature("boost".to_string()) {
+        if boost == 3 && !self.capabilities.cpu_boost {
             boost = 2;
         }
         report.args[0] = 0x00;
@@ -782,6 +1640,28 @@ impl RazerLaptop {
         return false;
     }
 
+    /// Highest CPU boost of the model, only models with the "boost" feature have level 3
+    pub fn get_max_cpu_boost(&self) -> u8 {
+        if self.capabilities.cpu_boost { 3 } else { 2 }
+    }
+
+    /// Highest GPU boost of the model, models with the "no_gpu_boost" feature only have level 0
+    pub fn get_max_gpu_boost(&self) -> u8 {
+        if self.capabilities.gpu_boost { 2 } else { 0 }
+    }
+
+    /// Whether the model accepts a power mode. Creator mode needs the
+    /// "creator_mode" feature, and custom mode takes CPU boost 0-3 and GPU boost
+    /// up to `get_max_gpu_boost`
+    pub fn supports_power_mode(&self, mode: u8, cpu_boost: u8, gpu_boost: u8) -> bool {
+        match mode {
+            0 | 1 | 3 => true,
+            2 => self.capabilities.creator_mode,
+            4 => cpu_boost <= 3 && gpu_boost <= self.get_max_gpu_boost(),
+            _ => false,
+        }
+    }
+
     pub fn set_power_mode(&mut self, mode: u8, cpu_boost: u8, gpu_boost: u8) -> bool {
         if mode <= 3 {
             self.power = mode;
@@ -866,6 +1746,24 @@ impl RazerLaptop {
         return false;
     }
 
+    /// Sets the logo brightness (0-255)
+    pub fn set_logo_brightness(&mut self, brightness: u8) -> bool {
+        let mut report: RazerPacket = RazerPacket::new(0x03, 0x03, 0x03);
+        report.args[0] = RazerLaptop::VARSTORE;
+        report.args[1] = RazerLaptop::LOGO_LED;
+        report.args[2] = brightness;
+        self.send_report(report).is_some()
+    }
+
+    /// Sets the logo state and, when it is lit, the brightness if one is given
+    pub fn set_logo(&mut self, mode: u8, brightness: Option<u8>) -> bool {
+        let res = self.set_logo_led_state(mode);
+        match brightness {
+            Some(brightness) if mode != 0 => self.set_logo_brightness(brightness) && res,
+            _ => res,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn get_logo_led_state(&mut self) -> u8 {
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x82, 0x03);
@@ -878,10 +1776,11 @@ impl RazerLaptop {
     }
 
     pub fn set_brightness(&mut self, brightness: u8) -> bool {
+        self.brightness = brightness;
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x03, 0x03);
         report.args[0] = RazerLaptop::VARSTORE;
         report.args[1] = RazerLaptop::BACKLIGHT_LED;
-        report.args[2] = brightness;
+        report.args[2] = if self.software_dimming && brightness > 0 { 255 } else { brightness };
         if let Some(_) = self.send_report(report) {
             return true;
         }
@@ -890,18 +1789,40 @@ impl RazerLaptop {
     }
 
     pub fn get_brightness(&mut self) -> u8 {
+        self.read_brightness().unwrap_or(0)
+    }
+
+    /// Reads the brightness (0-255), None if the device didn't answer
+    pub fn read_brightness(&mut self) -> Option<u8> {
+        if self.software_dimming {
+            return Some(self.brightness);
+        }
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x83, 0x03);
         report.args[0] = RazerLaptop::VARSTORE;
         report.args[1] = RazerLaptop::BACKLIGHT_LED;
         report.args[2] = 0x00;
-        if let Some(response) = self.send_report(report){
-            return response.args[2];
+        self.send_report(report).map(|response| response.args[2])
+    }
+
+    /// Moves the brightness scaling from the hardware to the effect manager or back
+    pub fn set_software_dimming(&mut self, enabled: bool) {
+        if self.software_dimming != enabled {
+            self.software_dimming = enabled;
+            self.set_brightness(self.brightness);
         }
-        return 0;
+    }
+
+    /// Returns the brightness the effect manager should apply, if any
+    pub fn get_software_dimming(&self) -> Option<u8> {
+        if self.software_dimming {
+            return Some(self.brightness);
+        }
+
+        None
     }
 
     pub fn get_bho(&mut self) -> Option<u8> {
-        if !self.have_feature("bho".to_string()) {
+        if !self.capabilities.bho {
             return None;
         }
 
@@ -913,7 +1834,7 @@ impl RazerLaptop {
     }
 
     pub fn set_bho(&mut self, is_on: bool, threshold: u8) -> bool {
-        if !self.have_feature("bho".to_string()) {
+        if !self.capabilities.bho {
             return false;
         }
 
@@ -982,6 +1903,11 @@ impl RazerLaptop {
 
 }
 
+/// The thresholds the battery health optimizer accepts, as offered by Synapse
+fn is_valid_bho_threshold(threshold: u8) -> bool {
+    (50..=80).contains(&threshold) && threshold.is_multiple_of(5)
+}
+
 // top bit flags whether battery health optimization is on or off
 // bottom bits are the actual threshold that it is set to
 fn byte_to_bho(u: u8) -> (bool, u8) {
@@ -994,3 +1920,408 @@ fn bho_to_byte(is_on: bool, threshold: u8) -> u8 {
     }
     return threshold;
 }
+
+/// A laptop without hardware for tests
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Command class, command id and args of the reports sent, oldest first
+    pub type Reports = Arc<Mutex<Vec<(u8, u8, Vec<u8>)>>>;
+
+    /// Answers every report as successful and records it
+    struct FakeDevice {
+        reports: Reports,
+        last: Mutex<Vec<u8>>,
+    }
+
+    impl ReportDevice for FakeDevice {
+        fn send_feature_report(&self, data: &[u8]) -> hidapi::HidResult<()> {
+            let packet: RazerPacket = bincode::deserialize(data).unwrap();
+            let args = packet.args[..packet.data_size.min(80) as usize].to_vec();
+            self.reports.lock().unwrap().push((packet.command_class, packet.command_id, args));
+            *self.last.lock().unwrap() = data.to_vec();
+            Ok(())
+        }
+
+        fn get_feature_report(&self, buf: &mut [u8]) -> hidapi::HidResult<usize> {
+            let mut packet: RazerPacket = bincode::deserialize(&self.last.lock().unwrap()).unwrap();
+            packet.status = RazerPacket::RAZER_CMD_SUCCESSFUL;
+            let data = bincode::serialize(&packet).unwrap();
+            buf[..data.len()].copy_from_slice(&data);
+            Ok(buf.len())
+        }
+    }
+
+    pub fn capabilities() -> Capabilities {
+        Capabilities {
+            logo: true,
+            logo_breathing: true,
+            cpu_boost: true,
+            gpu_boost: true,
+            creator_mode: false,
+            bho: true,
+            fan_limits: Some((3500, 5000)),
+        }
+    }
+
+    /// A laptop that records the reports sent to it
+    pub fn fake_laptop() -> (RazerLaptop, Reports) {
+        fake_laptop_with(capabilities())
+    }
+
+    pub fn fake_laptop_with(capabilities: Capabilities) -> (RazerLaptop, Reports) {
+        let reports = Reports::default();
+        let device = FakeDevice { reports: reports.clone(), last: Mutex::new(vec![]) };
+        (RazerLaptop::new(String::from("Test Blade"), capabilities, Box::new(device)), reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fan_rpm_is_clamped_to_the_limits() {
+        assert_eq!(clamp_fan_rpm(1000, Some((3500, 5000))), Ok(3500));
+        assert_eq!(clamp_fan_rpm(3499, Some((3500, 5000))), Ok(3500));
+        assert_eq!(clamp_fan_rpm(4200, Some((3500, 5000))), Ok(4200));
+        assert_eq!(clamp_fan_rpm(5001, Some((3500, 5000))), Ok(5000));
+        assert_eq!(clamp_fan_rpm(9000, Some((3500, 5000))), Ok(5000));
+        // Without a device there is nothing to clamp to
+        assert_eq!(clamp_fan_rpm(9000, None), Ok(9000));
+    }
+
+    #[test]
+    fn fan_rpm_zero_is_auto() {
+        assert_eq!(clamp_fan_rpm(0, Some((3500, 5000))), Ok(0));
+        assert_eq!(clamp_fan_rpm(0, None), Ok(0));
+    }
+
+    #[test]
+    fn negative_fan_rpm_is_rejected() {
+        for rpm in [-1, -4000, i32::MIN] {
+            assert!(matches!(clamp_fan_rpm(rpm, Some((3500, 5000))), Err(DeviceError::InvalidArgument(_))));
+        }
+    }
+
+    /// A device manager holding a fake laptop, with the keyboard at 128 in both AC states
+    fn fake_manager() -> (DeviceManager, testing::Reports) {
+        let (laptop, reports) = testing::fake_laptop();
+        let mut d = DeviceManager::new();
+        d.devices.push(laptop);
+        let mut config = config::Configuration::new();
+        config.power.iter_mut().for_each(|p| p.brightness = 128);
+        d.config = Some(config);
+        (d, reports)
+    }
+
+    /// Takes the recorded reports, returning the keyboard brightness writes
+    fn backlight_writes(reports: &testing::Reports) -> Vec<u8> {
+        reports.lock().unwrap()
+            .drain(..)
+            .filter(|(class, id, args)| (*class, *id) == (0x03, 0x03) && args[1] == RazerLaptop::BACKLIGHT_LED)
+            .map(|(_, _, args)| args[2])
+            .collect()
+    }
+
+    #[test]
+    fn light_off_writes_once() {
+        let (mut d, reports) = fake_manager();
+        d.light_off();
+        assert_eq!(backlight_writes(&reports), vec![0]);
+        // Another source asking for it changes nothing
+        d.light_off();
+        d.dim_light(0);
+        assert!(reports.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn restore_light_writes_once() {
+        let (mut d, reports) = fake_manager();
+        d.restore_light();
+        assert!(reports.lock().unwrap().is_empty(), "the light wasn't off");
+        d.light_off();
+        backlight_writes(&reports);
+        d.restore_light();
+        assert_eq!(backlight_writes(&reports), vec![128]);
+        d.restore_light();
+        assert!(reports.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn manual_light_off_survives_restore_light() {
+        let (mut d, reports) = fake_manager();
+        assert_eq!(d.set_keyboard_power(false), Ok(()));
+        assert_eq!(backlight_writes(&reports), vec![0]);
+        // Idle and screensaver events end, the light stays off
+        d.restore_light();
+        d.light_off();
+        d.restore_light();
+        assert!(reports.lock().unwrap().is_empty());
+        assert_eq!(d.set_keyboard_power(true), Ok(()));
+        assert_eq!(backlight_writes(&reports), vec![128]);
+    }
+
+    #[test]
+    fn keyboard_power_needs_a_device() {
+        let mut d = DeviceManager::new();
+        assert_eq!(d.set_keyboard_power(false), Err(DeviceError::NoDevice));
+        assert!(!d.manual_light_off);
+    }
+
+    /// Idle monitor that records the removed watches, removing `failing` fails
+    #[derive(Default)]
+    struct FakeIdleMonitor {
+        removed: std::cell::RefCell<Vec<u32>>,
+        failing: Option<u32>,
+    }
+
+    impl dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor for FakeIdleMonitor {
+        fn get_idletime(&self) -> Result<u64, dbus::Error> {
+            Ok(0)
+        }
+
+        fn add_idle_watch(&self, _interval: u64) -> Result<u32, dbus::Error> {
+            Ok(1)
+        }
+
+        fn add_user_active_watch(&self) -> Result<u32, dbus::Error> {
+            Ok(2)
+        }
+
+        fn remove_watch(&self, id: u32) -> Result<(), dbus::Error> {
+            self.removed.borrow_mut().push(id);
+            match self.failing {
+                Some(failing) if failing == id => Err(dbus::Error::new_failed("no such watch")),
+                _ => Ok(()),
+            }
+        }
+
+        fn reset_idletime(&self) -> Result<(), dbus::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn remove_watches_removes_both() {
+        let mut d = DeviceManager::new();
+        d.idle_id = 7;
+        d.active_id = 8;
+        d.add_active = true;
+        let monitor = FakeIdleMonitor::default();
+        d.remove_watches(&monitor);
+        assert_eq!(*monitor.removed.borrow(), vec![7, 8]);
+        assert_eq!((d.idle_id, d.active_id, d.add_active), (0, 0, false));
+        // Nothing is left to remove the second time
+        d.remove_watches(&monitor);
+        assert_eq!(monitor.removed.borrow().len(), 2);
+    }
+
+    #[test]
+    fn remove_watches_skips_unset_ids() {
+        let mut d = DeviceManager::new();
+        d.active_id = 8;
+        let monitor = FakeIdleMonitor::default();
+        d.remove_watches(&monitor);
+        assert_eq!(*monitor.removed.borrow(), vec![8]);
+        assert_eq!((d.idle_id, d.active_id), (0, 0));
+    }
+
+    #[test]
+    fn remove_watches_goes_on_after_an_error() {
+        let mut d = DeviceManager::new();
+        d.idle_id = 7;
+        d.active_id = 8;
+        let monitor = FakeIdleMonitor { failing: Some(7), ..Default::default() };
+        d.remove_watches(&monitor);
+        assert_eq!(*monitor.removed.borrow(), vec![7, 8]);
+        assert_eq!((d.idle_id, d.active_id), (0, 0));
+    }
+
+    #[test]
+    fn prepare_exit_turns_the_light_off() {
+        let (mut d, reports) = fake_manager();
+        d.config.as_mut().unwrap().exit_light_off = true;
+        d.prepare_exit();
+        assert_eq!(backlight_writes(&reports), vec![0]);
+    }
+
+    #[test]
+    fn prepare_exit_restores_the_light() {
+        let (mut d, reports) = fake_manager();
+        d.light_off();
+        backlight_writes(&reports);
+        d.prepare_exit();
+        assert_eq!(backlight_writes(&reports), vec![128]);
+    }
+
+    /// Takes the recorded reports, returning the bytes written to the battery health optimizer
+    fn bho_writes(reports: &testing::Reports) -> Vec<u8> {
+        reports.lock().unwrap()
+            .drain(..)
+            .filter(|(class, id, _)| (*class, *id) == (0x07, 0x12))
+            .map(|(_, _, args)| args[0])
+            .collect()
+    }
+
+    #[test]
+    fn bho_thresholds() {
+        for (threshold, valid) in [(49, false), (50, true), (55, true), (80, true), (81, false), (100, false)] {
+            assert_eq!(is_valid_bho_threshold(threshold), valid, "threshold {}", threshold);
+        }
+        assert!(!is_valid_bho_threshold(52));
+    }
+
+    #[test]
+    fn set_bho_handler_checks_the_threshold() {
+        let (mut d, reports) = fake_manager();
+        // Without a config nothing is written to the settings file
+        d.config = None;
+        for threshold in [49, 81, 100] {
+            assert!(!d.set_bho_handler(true, threshold));
+        }
+        assert!(bho_writes(&reports).is_empty());
+        assert!(d.set_bho_handler(true, 50));
+        assert!(d.set_bho_handler(true, 80));
+        // Turning it off keeps the threshold, whatever it is
+        assert!(d.set_bho_handler(false, 100));
+        assert_eq!(bho_writes(&reports), vec![0x80 | 50, 0x80 | 80, 100]);
+    }
+
+    #[test]
+    fn restore_bho_applies_the_stored_state() {
+        let (mut d, reports) = fake_manager();
+        assert!(d.restore_bho(), "nothing stored is nothing to restore");
+        assert!(bho_writes(&reports).is_empty());
+        d.config.as_mut().unwrap().bho = Some((true, 70));
+        assert!(d.restore_bho());
+        assert_eq!(bho_writes(&reports), vec![0x80 | 70]);
+        // A hand edited value outside of the valid ones isn't written
+        d.config.as_mut().unwrap().bho = Some((true, 90));
+        assert!(!d.restore_bho());
+        assert!(bho_writes(&reports).is_empty());
+    }
+
+    #[test]
+    fn bho_config_round_trip() {
+        let mut config = config::Configuration::new();
+        config.bho = Some((true, 65));
+        let json = serde_json::to_string(&config).unwrap();
+        let read: config::Configuration = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.bho, Some((true, 65)));
+        // Files from before the setting leave it untouched
+        let read: config::Configuration = serde_json::from_str("{}").unwrap();
+        assert_eq!(read.bho, None);
+    }
+
+    #[test]
+    fn bho_byte() {
+        assert_eq!(bho_to_byte(true, 80), 0xd0);
+        assert_eq!(bho_to_byte(false, 80), 80);
+        assert_eq!(byte_to_bho(0xd0), (true, 80));
+        assert_eq!(byte_to_bho(80), (false, 80));
+    }
+
+    #[test]
+    fn logo_is_restored_after_the_standard_effect() {
+        let (mut d, reports) = fake_manager();
+        let config = d.config.as_mut().unwrap();
+        config.standard_effect = RazerLaptop::WAVE;
+        config.power.iter_mut().for_each(|p| p.logo_state = 1);
+        assert!(d.restore_lighting());
+
+        let reports = reports.lock().unwrap();
+        let position = |class: u8, id: u8, args: &[u8]| reports.iter()
+            .position(|(c, i, a)| (*c, *i) == (class, id) && a.starts_with(args))
+            .unwrap_or_else(|| panic!("no {:#04x}/{:#04x} report in {:?}", class, id, reports));
+        let effect = position(0x03, 0x0a, &[RazerLaptop::WAVE]);
+        let logo = position(0x03, 0x00, &[RazerLaptop::VARSTORE, RazerLaptop::LOGO_LED, 0x01]);
+        assert!(effect < logo, "the logo was written before the standard effect: {:?}", reports);
+    }
+
+    #[test]
+    fn logo_restore_needs_a_device() {
+        let mut d = DeviceManager::new();
+        assert!(!d.restore_lighting());
+    }
+
+    fn model(features: &[&str], fan: &[u16]) -> SupportedDevice {
+        SupportedDevice {
+            name: String::from("Blade"),
+            vid: String::from("1532"),
+            pid: String::from("0270"),
+            features: features.iter().map(|f| f.to_string()).collect(),
+            fan: fan.to_vec(),
+        }
+    }
+
+    #[test]
+    fn logo_features() {
+        let capabilities = model(&["logo"], &[]).capabilities();
+        assert!(capabilities.logo && capabilities.logo_breathing);
+        let capabilities = model(&["logo", "logo_static"], &[]).capabilities();
+        assert!(capabilities.logo && !capabilities.logo_breathing);
+        let capabilities = model(&["logo_static"], &[]).capabilities();
+        assert!(!capabilities.logo && !capabilities.logo_breathing);
+    }
+
+    #[test]
+    fn other_features() {
+        let capabilities = model(&["boost", "creator_mode", "bho"], &[3500, 5000]).capabilities();
+        assert_eq!(capabilities, Capabilities {
+            logo: false,
+            logo_breathing: false,
+            cpu_boost: true,
+            gpu_boost: true,
+            creator_mode: true,
+            bho: true,
+            fan_limits: Some((3500, 5000)),
+        });
+        assert!(!model(&["no_gpu_boost"], &[]).capabilities().gpu_boost);
+    }
+
+    #[test]
+    fn missing_fan_range() {
+        let device = model(&["logo"], &[]);
+        assert_eq!(device.validate(), Ok(()));
+        assert_eq!(device.capabilities().fan_limits, None);
+    }
+
+    #[test]
+    fn ids_must_be_hexadecimal() {
+        assert_eq!(model(&[], &[]).validate(), Ok(()));
+        let device = SupportedDevice { vid: String::from("15G2"), ..model(&[], &[]) };
+        assert_eq!(device.validate(), Err(String::from("vid \"15G2\" is not a hexadecimal USB id")));
+        let device = SupportedDevice { pid: String::new(), ..model(&[], &[]) };
+        assert!(device.validate().unwrap_err().starts_with("pid"));
+    }
+
+    #[test]
+    fn fan_range_must_be_min_max() {
+        assert_eq!(model(&[], &[3500, 3500]).validate(), Ok(()));
+        assert_eq!(model(&[], &[5000, 3500]).validate(), Err(String::from("fan must be [min, max], got [5000, 3500]")));
+        assert!(model(&[], &[3500]).validate().is_err());
+        assert!(model(&[], &[3500, 4000, 5000]).validate().is_err());
+    }
+
+    #[test]
+    fn malformed_entries_are_skipped() {
+        let data = br#"[
+            {"name": "Good", "vid": "1532", "pid": "0270", "features": [], "fan": []},
+            {"name": "Bad vid", "vid": "xyz", "pid": "0270", "features": [], "fan": []},
+            {"name": "Bad fan", "vid": "1532", "pid": "0271", "features": [], "fan": [5000, 3500]},
+            {"name": "No pid", "vid": "1532", "features": [], "fan": []}
+        ]"#;
+        let devices = parse_device_file(data, "test").unwrap();
+        assert_eq!(devices.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["Good"]);
+    }
+
+    #[test]
+    fn laptops_file_is_valid() {
+        let data = include_bytes!("../../data/devices/laptops.json");
+        let count = serde_json::from_slice::<Vec<serde_json::Value>>(data).unwrap().len();
+        assert_eq!(parse_device_file(data, "laptops.json").unwrap().len(), count);
+    }
+}
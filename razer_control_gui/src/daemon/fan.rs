@@ -0,0 +1,88 @@
+/// Fan speed as a function of temperature, linearly interpolated between
+/// (temperature, rpm) points
+pub struct FanCurve {
+    /// Sorted by temperature
+    points: Vec<(f32, i32)>,
+}
+
+impl FanCurve {
+    /// Builds a curve from unsorted points. Returns None if there are no usable points
+    pub fn new(points: &[(f32, i32)]) -> Option<FanCurve> {
+        let mut points: Vec<(f32, i32)> = points.iter()
+            .copied()
+            .filter(|(temp, _)| temp.is_finite())
+            .collect();
+        if points.is_empty() {
+            return None;
+        }
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Some(FanCurve { points })
+    }
+
+    /// Returns the fan speed for a temperature. Below the first point and above
+    /// the last one the speed of that point is used
+    pub fn rpm_for_temp(&self, temp: f32) -> i32 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if temp <= first.0 {
+            return first.1;
+        }
+        if temp >= last.0 {
+            return last.1;
+        }
+
+        // temp is strictly between the first and last point, so there is a next point
+        let next = self.points.iter().position(|(t, _)| *t > temp).unwrap();
+        let (t0, rpm0) = self.points[next - 1];
+        let (t1, rpm1) = self.points[next];
+        let ratio = (temp - t0) / (t1 - t0);
+        (rpm0 as f32 + (rpm1 - rpm0) as f32 * ratio).round() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsorted_points() {
+        let curve = FanCurve::new(&[(80.0, 5000), (40.0, 0), (60.0, 3000)]).unwrap();
+        assert_eq!(curve.points, vec![(40.0, 0), (60.0, 3000), (80.0, 5000)]);
+        assert_eq!(curve.rpm_for_temp(70.0), 4000);
+    }
+
+    #[test]
+    fn single_point() {
+        let curve = FanCurve::new(&[(60.0, 3000)]).unwrap();
+        assert_eq!(curve.rpm_for_temp(20.0), 3000);
+        assert_eq!(curve.rpm_for_temp(60.0), 3000);
+        assert_eq!(curve.rpm_for_temp(100.0), 3000);
+    }
+
+    #[test]
+    fn clamps_outside_the_curve() {
+        let curve = FanCurve::new(&[(40.0, 2000), (80.0, 5000)]).unwrap();
+        assert_eq!(curve.rpm_for_temp(-10.0), 2000);
+        assert_eq!(curve.rpm_for_temp(40.0), 2000);
+        assert_eq!(curve.rpm_for_temp(80.0), 5000);
+        assert_eq!(curve.rpm_for_temp(120.0), 5000);
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let curve = FanCurve::new(&[(40.0, 2000), (60.0, 3000), (80.0, 5000)]).unwrap();
+        assert_eq!(curve.rpm_for_temp(50.0), 2500);
+        assert_eq!(curve.rpm_for_temp(60.0), 3000);
+        assert_eq!(curve.rpm_for_temp(65.0), 3500);
+        // Rounded to the nearest RPM
+        assert_eq!(curve.rpm_for_temp(40.1), 2005);
+    }
+
+    #[test]
+    fn filters_points_that_are_not_finite() {
+        let curve = FanCurve::new(&[(f32::NAN, 4000), (40.0, 2000), (f32::INFINITY, 5000), (80.0, 3000)]).unwrap();
+        assert_eq!(curve.points, vec![(40.0, 2000), (80.0, 3000)]);
+        assert!(FanCurve::new(&[(f32::NAN, 4000)]).is_none());
+        assert!(FanCurve::new(&[]).is_none());
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs;
+use std::io::Read;
+
+use log::*;
+
+const INPUT_DEVICES_FILE: &str = "/proc/bus/input/devices";
+
+const EV_KEY: u16 = 0x01;
+/// Value of a key event when the key goes down (1), as opposed to up (0) or repeat (2)
+const KEY_DOWN: i32 = 1;
+
+/// Linux key codes laid out on the 6x15 keyboard matrix, one slice per row.
+/// The position in the slice is the column. Wide keys only take one column,
+/// so the mapping is approximate on the bottom rows
+const KEY_ROWS: [&[u16]; 6] = [
+    // Esc, F1-F12, Insert, Delete
+    &[1, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 87, 88, 110, 111],
+    // `, 1-0, -, =, Backspace
+    &[41, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14],
+    // Tab, Q-P, [, ], \
+    &[15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 43],
+    // Caps Lock, A-L, ;, ', Enter
+    &[58, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 28],
+    // Left Shift, Z-M, ",", ., /, Right Shift, Up
+    &[42, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 103],
+    // Left Ctrl, Left Meta, Left Alt, Space, Right Alt, Right Ctrl, Left, Down, Right
+    &[29, 125, 56, 57, 100, 97, 105, 108, 106],
+];
+
+/// Returns the matrix index (row * 15 + column) of a key code
+pub fn get_key_index(code: u16) -> Option<usize> {
+    KEY_ROWS.iter().enumerate().find_map(|(row, keys)| {
+        keys.iter().position(|k| *k == code).map(|col| row * 15 + col)
+    })
+}
+
+/// Finds the event devices of the keyboards, e.g. "/dev/input/event3"
+pub fn find_keyboards() -> Vec<String> {
+    let devices = match fs::read_to_string(INPUT_DEVICES_FILE) {
+        Ok(devices) => devices,
+        Err(e) => {
+            warn!("Could not read {}: {}", INPUT_DEVICES_FILE, e);
+            return vec![];
+        }
+    };
+    // Each device is a block of lines separated by an empty line. Keyboards
+    // have the "kbd" handler and report key, LED and repeat events (EV=120013)
+    devices.split("\n\n")
+        .filter(|block| block.lines().any(|l| l.starts_with("B: EV=120013")))
+        .filter_map(|block| {
+            let handlers = block.lines().find_map(|l| l.strip_prefix("H: Handlers="))?;
+            let mut handlers = handlers.split_whitespace();
+            if !handlers.clone().any(|h| h == "kbd") {
+                return None;
+            }
+            handlers.find(|h| h.starts_with("event")).map(|h| format!("/dev/input/{}", h))
+        })
+        .collect()
+}
+
+/// Reads key presses from an event device until it can't be read anymore,
+/// calling the handler with the key code of every key that goes down
+pub fn read_key_presses<F: FnMut(u16)>(path: &str, mut handler: F) {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Could not open {}: {}", path, e);
+            return;
+        }
+    };
+    // struct input_event ends with type (u16), code (u16) and value (i32),
+    // after a timeval whose size depends on the platform
+    let size = std::mem::size_of::<libc::input_event>();
+    let mut buffer = vec![0u8; size];
+    loop {
+        if let Err(e) = file.read_exact(&mut buffer) {
+            warn!("Stopped reading {}: {}", path, e);
+            return;
+        }
+        let tail = &buffer[size - 8..];
+        let kind = u16::from_ne_bytes([tail[0], tail[1]]);
+        let code = u16::from_ne_bytes([tail[2], tail[3]]);
+        let value = i32::from_ne_bytes([tail[4], tail[5], tail[6], tail[7]]);
+        if kind == EV_KEY && value == KEY_DOWN {
+            handler(code);
+        }
+    }
+}
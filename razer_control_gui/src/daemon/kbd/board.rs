@@ -21,6 +21,27 @@ pub struct KeyColour {
     pub blue: u8,
 }
 
+impl KeyColour {
+    /// Returns the colour scaled by `factor` (0.0 - 1.0)
+    pub fn scaled(self, factor: f32) -> KeyColour {
+        KeyColour {
+            red: (self.red as f32 * factor) as u8,
+            green: (self.green as f32 * factor) as u8,
+            blue: (self.blue as f32 * factor) as u8,
+        }
+    }
+
+    /// Returns this colour drawn over `below` with the given opacity (0.0 - 1.0)
+    pub fn blended_over(self, below: KeyColour, opacity: f32) -> KeyColour {
+        let mix = |top: u8, bottom: u8| (bottom as f32 + (top as f32 - bottom as f32) * opacity).round() as u8;
+        KeyColour {
+            red: mix(self.red, below.red),
+            green: mix(self.green, below.green),
+            blue: mix(self.blue, below.blue),
+        }
+    }
+}
+
 /// Same as `KeyColour`, but uses f32 values, for more accurate frame by frame
 /// colour blending in animations
 #[derive(Copy, Clone, Debug)]
@@ -214,10 +235,11 @@ impl KeyboardData {
 
     pub fn update_kbd(&mut self, laptop: &mut device::RazerLaptop) -> bool {
         // driver_sysfs::write_rgb_map(self.get_curr_state())
+        let mut ok = true;
         for idx in 0..ROWS {
-            laptop.set_custom_frame_data(idx as u8, self.rows[idx].get_row_data());
+            ok &= laptop.set_custom_frame_data(idx as u8, self.rows[idx].get_row_data());
         }
-        return true;
+        return ok;
     }
 
     pub fn update_custom_mode(&mut self, laptop: &mut device::RazerLaptop) -> bool {
@@ -282,3 +304,23 @@ impl KeyboardData {
         return all_vals;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: KeyColour = KeyColour { red: 255, green: 255, blue: 255 };
+    const BLUE: KeyColour = KeyColour { red: 0, green: 0, blue: 100 };
+
+    fn rgb(c: KeyColour) -> [u8; 3] {
+        [c.red, c.green, c.blue]
+    }
+
+    #[test]
+    fn blend_opacity() {
+        assert_eq!(rgb(WHITE.blended_over(BLUE, 0.0)), [0, 0, 100]);
+        assert_eq!(rgb(WHITE.blended_over(BLUE, 0.5)), [128, 128, 178]);
+        assert_eq!(rgb(WHITE.blended_over(BLUE, 1.0)), [255, 255, 255]);
+        assert_eq!(rgb(BLUE.blended_over(WHITE, 0.5)), [128, 128, 178]);
+    }
+}
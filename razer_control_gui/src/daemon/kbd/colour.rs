@@ -0,0 +1,67 @@
+/// Converts a HSV colour to RGB.
+///
+/// Hue is in degrees and wraps around, saturation and value go from 0.0 to 1.0
+/// and are clamped to that range
+pub fn hsv_to_rgb(h: f32, s: f32, v: f32) -> [u8; 3] {
+    let s = s.clamp(0.0, 1.0);
+    let v = v.clamp(0.0, 1.0);
+    let h = h.rem_euclid(360.0) / 60.0;
+
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = v - c;
+    let (r, g, b) = match h as u8 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    [
+        ((r + m) * 255.0).round() as u8,
+        ((g + m) * 255.0).round() as u8,
+        ((b + m) * 255.0).round() as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primaries() {
+        assert_eq!(hsv_to_rgb(0.0, 1.0, 1.0), [255, 0, 0]);
+        assert_eq!(hsv_to_rgb(120.0, 1.0, 1.0), [0, 255, 0]);
+        assert_eq!(hsv_to_rgb(240.0, 1.0, 1.0), [0, 0, 255]);
+        assert_eq!(hsv_to_rgb(60.0, 1.0, 1.0), [255, 255, 0]);
+        assert_eq!(hsv_to_rgb(30.0, 1.0, 0.5), [128, 64, 0]);
+    }
+
+    #[test]
+    fn hue_wraps_around() {
+        assert_eq!(hsv_to_rgb(-60.0, 1.0, 1.0), hsv_to_rgb(300.0, 1.0, 1.0));
+        assert_eq!(hsv_to_rgb(-60.0, 1.0, 1.0), [255, 0, 255]);
+        assert_eq!(hsv_to_rgb(360.0, 1.0, 1.0), [255, 0, 0]);
+        assert_eq!(hsv_to_rgb(720.0, 1.0, 1.0), [255, 0, 0]);
+        assert_eq!(hsv_to_rgb(480.0, 1.0, 1.0), [0, 255, 0]);
+    }
+
+    #[test]
+    fn no_saturation_is_grey() {
+        for h in [0.0, 90.0, 200.0, 330.0] {
+            assert_eq!(hsv_to_rgb(h, 0.0, 0.5), [128, 128, 128]);
+            assert_eq!(hsv_to_rgb(h, 0.0, 1.0), [255, 255, 255]);
+            assert_eq!(hsv_to_rgb(h, 0.0, 0.0), [0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn saturation_and_value_are_clamped() {
+        assert_eq!(hsv_to_rgb(120.0, 2.0, 1.0), hsv_to_rgb(120.0, 1.0, 1.0));
+        assert_eq!(hsv_to_rgb(120.0, -1.0, 1.0), [255, 255, 255]);
+        assert_eq!(hsv_to_rgb(240.0, 1.0, 5.0), [0, 0, 255]);
+        assert_eq!(hsv_to_rgb(240.0, 1.0, -0.5), [0, 0, 0]);
+    }
+}
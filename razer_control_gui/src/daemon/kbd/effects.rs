@@ -29,6 +29,10 @@ impl Effect for Static {
         return self.kbd;
     }
 
+    fn is_animated(&self) -> bool {
+        false
+    }
+
     fn get_name() -> &'static str
     where
         Self: Sized,
@@ -36,6 +40,17 @@ impl Effect for Static {
         return "Static";
     }
 
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 3,
+            max_len: Some(3),
+            fields: &["red", "green", "blue"],
+        }
+    }
+
     fn get_varargs(&mut self) -> &[u8] {
         &self.args
     }
@@ -92,6 +107,10 @@ impl Effect for StaticGradient {
         self.kbd // Nothing to update
     }
 
+    fn is_animated(&self) -> bool {
+        false
+    }
+
     fn get_name() -> &'static str
     where
         Self: Sized,
@@ -99,6 +118,17 @@ impl Effect for StaticGradient {
         "Static Gradient"
     }
 
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 6,
+            max_len: Some(6),
+            fields: &["red_1", "green_1", "blue_1", "red_2", "green_2", "blue_2"],
+        }
+    }
+
     fn get_varargs(&mut self) -> &[u8] {
         return &self.args;
     }
@@ -124,10 +154,65 @@ impl Effect for StaticGradient {
 /// 2 colours forming a gradient, animated across the keyboard
 ///
 
+/// Way the wave moves across the keyboard
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WaveDirection {
+    Right = 0,
+    Left = 1,
+}
+
+/// Parameters of the wave gradient. They are sent as
+/// `[red_1, green_1, blue_1, red_2, green_2, blue_2, direction, speed]`, the
+/// last two can be left out for a right moving wave at full speed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WaveParams {
+    pub stops: [[u8; 3]; 2],
+    pub direction: WaveDirection,
+    /// Columns moved per 10 frames, 1 - 10
+    pub speed: u8,
+}
+
+impl WaveParams {
+    pub const MAX_SPEED: u8 = 10;
+
+    /// Parses and validates the params of the effect
+    pub fn parse(args: &[u8]) -> Result<WaveParams, String> {
+        let (direction, speed) = match args.len() {
+            6 => (WaveDirection::Right, WaveParams::MAX_SPEED),
+            8 => {
+                let direction = match args[6] {
+                    0 => WaveDirection::Right,
+                    1 => WaveDirection::Left,
+                    d => return Err(format!("Invalid wave direction {}, expected 0 (right) or 1 (left)", d)),
+                };
+                if !(1..=WaveParams::MAX_SPEED).contains(&args[7]) {
+                    return Err(format!("Invalid wave speed {}, expected 1 to {}", args[7], WaveParams::MAX_SPEED));
+                }
+                (direction, args[7])
+            },
+            n => return Err(format!("Wave gradient expects 6 or 8 params, got {}", n)),
+        };
+        Ok(WaveParams {
+            stops: [[args[0], args[1], args[2]], [args[3], args[4], args[5]]],
+            direction,
+            speed,
+        })
+    }
+
+    fn to_args(self) -> [u8; 8] {
+        let [a, b] = self.stops;
+        [a[0], a[1], a[2], b[0], b[1], b[2], self.direction as u8, self.speed]
+    }
+}
+
+#[derive(Clone)]
 pub struct WaveGradient {
     kbd: board::KeyboardData,
-    args: [u8; 6],
+    args: [u8; 8],
+    params: WaveParams,
     colour_band: Vec<board::AnimatorKeyColour>,
+    /// Speed accumulated since the band last moved, it moves every MAX_SPEED
+    progress: u8,
 }
 
 impl Effect for WaveGradient {
@@ -135,16 +220,23 @@ impl Effect for WaveGradient {
     where
         Self: Sized,
     {
-        let args: [u8; 6] = [
-            args[0], args[1], args[2], args[3], args[4], args[5],
-        ];
+        // Params are validated before the effect is made, but saved
+        // effects may not be, so fall back on the colours alone
+        let params = WaveParams::parse(&args).unwrap_or_else(|_| WaveParams {
+            stops: [[args[0], args[1], args[2]], [args[3], args[4], args[5]]],
+            direction: WaveDirection::Right,
+            speed: WaveParams::MAX_SPEED,
+        });
+        let [s1, s2] = params.stops;
         let mut wave = WaveGradient {
             kbd: board::KeyboardData::new(),
-            args,
+            args: params.to_args(),
+            params,
             colour_band: vec![],
+            progress: 0,
         };
-        let mut c1 = board::AnimatorKeyColour::new_u(args[0], args[1], args[2]);
-        let mut c2 = board::AnimatorKeyColour::new_u(args[3], args[4], args[5]);
+        let mut c1 = board::AnimatorKeyColour::new_u(s1[0], s1[1], s1[2]);
+        let mut c2 = board::AnimatorKeyColour::new_u(s2[0], s2[1], s2[2]);
         let c_delta = (c2 - c1).divide(15.0);
         for _ in 0..15 {
             wave.colour_band.push(c1);
@@ -162,7 +254,14 @@ impl Effect for WaveGradient {
             let c = self.colour_band[i].get_clamped_colour();
             self.kbd.set_col_colour(i, c.red, c.green, c.blue);
         }
-        self.colour_band.rotate_right(1);
+        self.progress += self.params.speed;
+        while self.progress >= WaveParams::MAX_SPEED {
+            self.progress -= WaveParams::MAX_SPEED;
+            match self.params.direction {
+                WaveDirection::Right => self.colour_band.rotate_right(1),
+                WaveDirection::Left => self.colour_band.rotate_left(1),
+            }
+        }
         self.kbd
     }
 
@@ -173,6 +272,17 @@ impl Effect for WaveGradient {
         "Wave Gradient"
     }
 
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 6,
+            max_len: Some(8),
+            fields: &["red_1", "green_1", "blue_1", "red_2", "green_2", "blue_2", "direction", "speed"],
+        }
+    }
+
     fn get_varargs(&mut self) -> &[u8] {
         return &self.args;
     }
@@ -193,16 +303,6 @@ impl Effect for WaveGradient {
     }
 }
 
-impl Clone for WaveGradient {
-    fn clone(&self) -> Self {
-        WaveGradient {
-            kbd: self.kbd,
-            args: self.args,
-            colour_band: self.colour_band.to_vec(),
-        }
-    }
-}
-
 ///
 /// BREATHING (1 Colour) KEYBOARD EFFECT
 /// 1 colour, fading in and out
@@ -285,6 +385,17 @@ impl Effect for BreathSingle {
         "Breathing Single"
     }
 
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 4,
+            max_len: Some(4),
+            fields: &["red", "green", "blue", "cycle_duration_100ms"],
+        }
+    }
+
     fn get_varargs(&mut self) -> &[u8] {
         return &self.args;
     }
@@ -304,3 +415,1228 @@ impl Effect for BreathSingle {
         self.kbd.get_curr_state()
     }
 }
+
+///
+/// SPLIT KEYBOARD EFFECT
+/// 2 colours, one for each half of the keyboard, with an optional
+/// gradient band of the given width (in columns) between them
+///
+
+#[derive(Copy, Clone)]
+pub struct Split {
+    kbd: board::KeyboardData,
+    args: [u8; 7],
+}
+
+impl Effect for Split {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let mut kbd = board::KeyboardData::new();
+        let args: [u8; 7] = [
+            args[0], args[1], args[2], args[3], args[4], args[5], args[6]
+        ];
+        let left = board::AnimatorKeyColour::new_u(args[0], args[1], args[2]);
+        let right = board::AnimatorKeyColour::new_u(args[3], args[4], args[5]);
+        let band = args[6] as f32;
+        let center = board::KEYS_PER_ROW as f32 / 2.0;
+        for i in 0..board::KEYS_PER_ROW {
+            let x = i as f32 + 0.5;
+            let t = if band > 0.0 {
+                ((x - center) / band + 0.5).clamp(0.0, 1.0)
+            } else if x < center {
+                0.0
+            } else {
+                1.0
+            };
+            let c = board::AnimatorKeyColour::new_f(
+                left.red + (right.red - left.red) * t,
+                left.green + (right.green - left.green) * t,
+                left.blue + (right.blue - left.blue) * t,
+            ).get_clamped_colour();
+            kbd.set_col_colour(i, c.red, c.green, c.blue);
+        }
+
+        Box::new(Split { kbd, args })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        self.kbd // Nothing to update
+    }
+
+    fn is_animated(&self) -> bool {
+        false
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Split"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 7,
+            max_len: Some(7),
+            fields: &[
+                "left_red", "left_green", "left_blue",
+                "right_red", "right_green", "right_blue",
+                "band_columns",
+            ],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Split"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// SCROLLING TEXT KEYBOARD EFFECT
+/// Text scrolling from right to left using a tiny 3x5 font.
+/// Args are the text colour, the speed in columns per second and then the
+/// text itself
+///
+
+const FONT_WIDTH: usize = 3;
+const FONT_HEIGHT: usize = 5;
+
+/// Returns the rows of a glyph, top to bottom. Each row holds 3 bits, with
+/// the most significant one being the leftmost pixel
+fn get_glyph(c: char) -> Option<[u8; FONT_HEIGHT]> {
+    let glyph = match c {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '?' => [0b110, 0b001, 0b010, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        _ => return None,
+    };
+    Some(glyph)
+}
+
+#[derive(Clone)]
+pub struct ScrollText {
+    kbd: board::KeyboardData,
+    args: Vec<u8>,
+    /// Pixel columns of the whole text, bit 0 being the top row
+    columns: Vec<u8>,
+    colour: board::KeyColour,
+    columns_per_sec: u128,
+    start_ms: u128,
+}
+
+impl ScrollText {
+    /// Returns whether every character of the text can be rendered
+    pub fn is_supported(text: &str) -> bool {
+        text.chars().all(|c| get_glyph(c.to_ascii_uppercase()).is_some())
+    }
+}
+
+impl Effect for ScrollText {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let text = String::from_utf8_lossy(&args[4..]).to_ascii_uppercase();
+        let mut columns: Vec<u8> = vec![];
+        for c in text.chars() {
+            let glyph = get_glyph(c).unwrap_or([0; FONT_HEIGHT]);
+            for x in 0..FONT_WIDTH {
+                let mut column = 0;
+                for (y, row) in glyph.iter().enumerate() {
+                    if row & (1 << (FONT_WIDTH - 1 - x)) != 0 {
+                        column |= 1 << y;
+                    }
+                }
+                columns.push(column);
+            }
+            columns.push(0); // Space between letters
+        }
+        // Let the text scroll out completely before it starts again
+        columns.extend([0; board::KEYS_PER_ROW]);
+
+        let mut kbd = board::KeyboardData::new();
+        kbd.set_kbd_colour(0, 0, 0);
+        Box::new(ScrollText {
+            kbd,
+            colour: board::KeyColour { red: args[0], green: args[1], blue: args[2] },
+            columns_per_sec: args[3].max(1) as u128,
+            start_ms: get_millis(),
+            args,
+            columns,
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let offset = ((get_millis() - self.start_ms) * self.columns_per_sec / 1000) as usize;
+        // The text uses the bottom 5 rows, the top one is left off
+        let top_row = board::ROWS - FONT_HEIGHT;
+        for x in 0..board::KEYS_PER_ROW {
+            let column = self.columns[(offset + x) % self.columns.len()];
+            for y in 0..FONT_HEIGHT {
+                let c = if column & (1 << y) != 0 {
+                    self.colour
+                } else {
+                    board::KeyColour { red: 0, green: 0, blue: 0 }
+                };
+                self.kbd.set_key_colour(top_row + y, x, c.red, c.green, c.blue);
+            }
+        }
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Scroll Text"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 4,
+            max_len: None,
+            fields: &["red", "green", "blue", "columns_per_sec", "text"],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Scroll Text"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// SCRIPTED KEYBOARD EFFECT
+/// Effect described by a JSON definition instead of code. Each keyframe
+/// holds colour stops that are spread from the left to the right of the
+/// keyboard, and the animation blends between keyframes over time.
+/// Args are the JSON definition
+///
+
+const MAX_KEYFRAMES: usize = 64;
+
+#[derive(Deserialize, Clone)]
+struct ScriptKeyframe {
+    /// Position in the animation, from 0.0 to 1.0
+    time: f32,
+    /// Colour stops from the left to the right of the keyboard
+    colours: Vec<[u8; 3]>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ScriptDefinition {
+    pub name: String,
+    /// Length of one loop of the animation
+    duration_ms: u32,
+    keyframes: Vec<ScriptKeyframe>,
+}
+
+impl ScriptDefinition {
+    /// Parses and validates a definition
+    pub fn parse(json: &str) -> Result<ScriptDefinition, String> {
+        let def: ScriptDefinition = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        if def.name.is_empty() || !def.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Invalid effect name: {:?}", def.name));
+        }
+        if !(100..=600_000).contains(&def.duration_ms) {
+            return Err(String::from("duration_ms must be between 100 and 600000"));
+        }
+        if def.keyframes.is_empty() || def.keyframes.len() > MAX_KEYFRAMES {
+            return Err(format!("There must be between 1 and {} keyframes", MAX_KEYFRAMES));
+        }
+        let mut last_time = 0.0;
+        for frame in def.keyframes.iter() {
+            if !(last_time..=1.0).contains(&frame.time) {
+                return Err(String::from("Keyframe times must be sorted and between 0.0 and 1.0"));
+            }
+            if frame.colours.is_empty() || frame.colours.len() > board::KEYS_PER_ROW {
+                return Err(format!("Keyframes need between 1 and {} colours", board::KEYS_PER_ROW));
+            }
+            last_time = frame.time;
+        }
+        Ok(def)
+    }
+
+    /// Colour of a keyframe at `x` (0.0 = left, 1.0 = right)
+    fn keyframe_colour(frame: &ScriptKeyframe, x: f32) -> board::AnimatorKeyColour {
+        let pos = x * (frame.colours.len() - 1) as f32;
+        let i = (pos as usize).min(frame.colours.len() - 1);
+        let j = (i + 1).min(frame.colours.len() - 1);
+        let a = frame.colours[i];
+        let b = frame.colours[j];
+        lerp(
+            board::AnimatorKeyColour::new_u(a[0], a[1], a[2]),
+            board::AnimatorKeyColour::new_u(b[0], b[1], b[2]),
+            pos - i as f32,
+        )
+    }
+
+    /// Colour at `t` in the animation and `x` across the keyboard, both 0.0 - 1.0
+    fn colour_at(&self, t: f32, x: f32) -> board::AnimatorKeyColour {
+        // Keyframes before t, the animation loops so the last one blends into the first
+        let next = self.keyframes.iter().position(|f| f.time > t);
+        let (prev, next) = match next {
+            Some(0) | None => (self.keyframes.last().unwrap(), &self.keyframes[0]),
+            Some(n) => (&self.keyframes[n - 1], &self.keyframes[n]),
+        };
+        let mut span = next.time - prev.time;
+        let mut elapsed = t - prev.time;
+        if span <= 0.0 {
+            span += 1.0;
+        }
+        if elapsed < 0.0 {
+            elapsed += 1.0;
+        }
+        lerp(
+            ScriptDefinition::keyframe_colour(prev, x),
+            ScriptDefinition::keyframe_colour(next, x),
+            (elapsed / span).clamp(0.0, 1.0),
+        )
+    }
+}
+
+fn lerp(a: board::AnimatorKeyColour, b: board::AnimatorKeyColour, t: f32) -> board::AnimatorKeyColour {
+    board::AnimatorKeyColour::new_f(
+        a.red + (b.red - a.red) * t,
+        a.green + (b.green - a.green) * t,
+        a.blue + (b.blue - a.blue) * t,
+    )
+}
+
+#[derive(Clone)]
+pub struct Scripted {
+    kbd: board::KeyboardData,
+    args: Vec<u8>,
+    /// None if the definition is invalid, the keyboard then stays black
+    definition: Option<ScriptDefinition>,
+    start_ms: u128,
+}
+
+impl Effect for Scripted {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let definition = match ScriptDefinition::parse(&String::from_utf8_lossy(&args)) {
+            Ok(def) => Some(def),
+            Err(e) => {
+                eprintln!("Invalid scripted effect: {}", e);
+                None
+            }
+        };
+        let mut kbd = board::KeyboardData::new();
+        kbd.set_kbd_colour(0, 0, 0);
+        Box::new(Scripted {
+            kbd,
+            args,
+            definition,
+            start_ms: get_millis(),
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        if let Some(def) = self.definition.as_ref() {
+            let elapsed = (get_millis() - self.start_ms) % def.duration_ms as u128;
+            let t = elapsed as f32 / def.duration_ms as f32;
+            for col in 0..board::KEYS_PER_ROW {
+                let x = col as f32 / (board::KEYS_PER_ROW - 1) as f32;
+                let c = def.colour_at(t, x).get_clamped_colour();
+                self.kbd.set_col_colour(col, c.red, c.green, c.blue);
+            }
+        }
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Scripted"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 1,
+            max_len: None,
+            fields: &["definition_json"],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Scripted"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// SPECTRUM CYCLE KEYBOARD EFFECT
+/// Rainbow spread across the columns, with the hue moving over time
+///
+
+#[derive(Copy, Clone)]
+pub struct SpectrumCycle {
+    kbd: board::KeyboardData,
+    args: [u8; 2],
+    period_ms: u128,
+    reverse: bool,
+    start_ms: u128,
+}
+
+impl SpectrumCycle {
+    /// Hue (in degrees) of the given column at the given time since the start
+    fn get_hue(&self, col: usize, elapsed_ms: u128) -> f32 {
+        let phase = (elapsed_ms % self.period_ms) as f32 / self.period_ms as f32 * 360.0;
+        let spread = col as f32 * 360.0 / board::KEYS_PER_ROW as f32;
+        if self.reverse {
+            spread + phase
+        } else {
+            spread - phase
+        }
+    }
+}
+
+impl Effect for SpectrumCycle {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(SpectrumCycle {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1]],
+            // args[0] is the duration of a full cycle in seconds
+            period_ms: args[0].max(1) as u128 * 1000,
+            reverse: args[1] != 0,
+            start_ms: get_millis(),
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let elapsed_ms = get_millis() - self.start_ms;
+        for col in 0..board::KEYS_PER_ROW {
+            let [r, g, b] = colour::hsv_to_rgb(self.get_hue(col, elapsed_ms), 1.0, 1.0);
+            self.kbd.set_col_colour(col, r, g, b);
+        }
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Spectrum Cycle"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 2,
+            max_len: Some(2),
+            fields: &["period_secs", "reverse"],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(*self)
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Spectrum Cycle"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// RIPPLE KEYBOARD EFFECT
+/// Pressed keys send a fading ring of colour across the keyboard,
+/// over an optional background colour
+///
+
+/// Speed at which a ripple ring grows, in keys per second
+const RIPPLE_KEYS_PER_SEC: f32 = 12.0;
+
+#[derive(Clone)]
+pub struct Ripple {
+    kbd: board::KeyboardData,
+    args: Vec<u8>,
+    colour: board::AnimatorKeyColour,
+    background: board::AnimatorKeyColour,
+    decay_ms: u128,
+    /// Matrix index and time of the recent key presses
+    presses: Vec<(usize, u128)>,
+}
+
+impl Effect for Ripple {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let background = match args.get(4..7) {
+            Some(bg) => board::AnimatorKeyColour::new_u(bg[0], bg[1], bg[2]),
+            None => board::AnimatorKeyColour::new_u(0, 0, 0),
+        };
+        Box::new(Ripple {
+            kbd: board::KeyboardData::new(),
+            colour: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
+            background,
+            // args[3] is the time a ripple takes to fade, in 100ms steps
+            decay_ms: args[3].max(1) as u128 * 100,
+            presses: vec![],
+            args,
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let now = get_millis();
+        let decay_ms = self.decay_ms;
+        self.presses.retain(|(_, at)| now - at < decay_ms);
+        for row in 0..board::ROWS {
+            for col in 0..board::KEYS_PER_ROW {
+                let mut intensity: f32 = 0.0;
+                for (index, at) in &self.presses {
+                    let age = (now - at) as f32 / 1000.0;
+                    let radius = age * RIPPLE_KEYS_PER_SEC;
+                    let (r, c) = ((index / board::KEYS_PER_ROW) as f32, (index % board::KEYS_PER_ROW) as f32);
+                    let distance = ((row as f32 - r).powi(2) + (col as f32 - c).powi(2)).sqrt();
+                    let ring = (1.0 - (distance - radius).abs()).max(0.0);
+                    let fade = 1.0 - (now - at) as f32 / decay_ms as f32;
+                    intensity = intensity.max(ring * fade);
+                }
+                let (bg, fg) = (self.background, self.colour);
+                let c = board::AnimatorKeyColour::new_f(
+                    bg.red + (fg.red - bg.red) * intensity,
+                    bg.green + (fg.green - bg.green) * intensity,
+                    bg.blue + (fg.blue - bg.blue) * intensity,
+                ).get_clamped_colour();
+                self.kbd.set_key_colour(row, col, c.red, c.green, c.blue);
+            }
+        }
+        self.kbd
+    }
+
+    fn key_pressed(&mut self, index: usize) {
+        self.presses.push((index, get_millis()));
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Ripple"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 4,
+            max_len: Some(7),
+            fields: &["red", "green", "blue", "decay_100ms", "background_red", "background_green", "background_blue"],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Ripple"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// CUSTOM MATRIX KEYBOARD EFFECT
+/// A fixed colour for every key, given by the client
+///
+
+#[derive(Clone)]
+pub struct CustomMatrix {
+    kbd: board::KeyboardData,
+    args: Vec<u8>,
+}
+
+impl Effect for CustomMatrix {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let mut kbd = board::KeyboardData::new();
+        for (index, rgb) in args.chunks_exact(3).take(90).enumerate() {
+            kbd.set_key_at(index, board::KeyColour { red: rgb[0], green: rgb[1], blue: rgb[2] });
+        }
+        Box::new(CustomMatrix { kbd, args })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        self.kbd
+    }
+
+    fn is_animated(&self) -> bool {
+        false
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Custom Matrix"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 270,
+            max_len: Some(270),
+            fields: &["key_colours"],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Custom Matrix"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// LOAD METER KEYBOARD EFFECT
+/// The whole keyboard blends from one colour to another as the CPU load rises
+///
+
+const PROC_STAT_FILE: &str = "/proc/stat";
+/// Time between two /proc/stat samples, shorter deltas are too noisy
+const LOAD_SAMPLE_MS: u128 = 500;
+/// Share of the distance to the measured load covered per frame, so the
+/// colour glides instead of jumping between samples
+const LOAD_SMOOTHING: f32 = 0.1;
+
+/// Parses the aggregate "cpu" line of /proc/stat into (idle, total) jiffies.
+/// Idle includes the time waiting for IO
+pub fn parse_cpu_times(stat: &str) -> Option<(u64, u64)> {
+    let line = stat.lines().find(|l| l.starts_with("cpu "))?;
+    let times: Vec<u64> = line.split_whitespace()
+        .skip(1)
+        .map(|t| t.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if times.len() < 4 {
+        return None;
+    }
+    let idle = times[3] + times.get(4).copied().unwrap_or(0);
+    Some((idle, times.iter().sum()))
+}
+
+/// CPU load (0.0-1.0) between two (idle, total) samples
+pub fn get_cpu_load(prev: (u64, u64), curr: (u64, u64)) -> f32 {
+    let total = curr.1.saturating_sub(prev.1);
+    if total == 0 {
+        return 0.0;
+    }
+    let idle = curr.0.saturating_sub(prev.0).min(total);
+    (total - idle) as f32 / total as f32
+}
+
+fn read_cpu_times() -> Option<(u64, u64)> {
+    parse_cpu_times(&std::fs::read_to_string(PROC_STAT_FILE).ok()?)
+}
+
+#[derive(Clone)]
+pub struct LoadMeter {
+    kbd: board::KeyboardData,
+    args: [u8; 6],
+    low: board::AnimatorKeyColour,
+    high: board::AnimatorKeyColour,
+    /// Last /proc/stat sample and when it was taken
+    sample: Option<((u64, u64), u128)>,
+    target: f32,
+    load: f32,
+}
+
+impl Effect for LoadMeter {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(LoadMeter {
+            kbd: board::KeyboardData::new(),
+            args: [args[0], args[1], args[2], args[3], args[4], args[5]],
+            low: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
+            high: board::AnimatorKeyColour::new_u(args[3], args[4], args[5]),
+            sample: None,
+            target: 0.0,
+            load: 0.0,
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let now = get_millis();
+        let due = self.sample.is_none_or(|(_, at)| now - at >= LOAD_SAMPLE_MS);
+        if due {
+            if let Some(times) = read_cpu_times() {
+                if let Some((prev, _)) = self.sample {
+                    self.target = get_cpu_load(prev, times);
+                }
+                self.sample = Some((times, now));
+            }
+        }
+        self.load += (self.target - self.load) * LOAD_SMOOTHING;
+        let (low, high, t) = (self.low, self.high, self.load);
+        let c = board::AnimatorKeyColour::new_f(
+            low.red + (high.red - low.red) * t,
+            low.green + (high.green - low.green) * t,
+            low.blue + (high.blue - low.blue) * t,
+        ).get_clamped_colour();
+        self.kbd.set_kbd_colour(c.red, c.green, c.blue);
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Load Meter"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 6,
+            max_len: Some(6),
+            fields: &["low_red", "low_green", "low_blue", "high_red", "high_green", "high_blue"],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Load Meter"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// TEMPERATURE GAUGE KEYBOARD EFFECT
+/// A row of keys filling up from left to right with the CPU temperature,
+/// going from blue to red
+///
+
+/// Time between two temperature readings
+const TEMP_SAMPLE_MS: u128 = 5000;
+
+#[derive(Clone)]
+pub struct TempGauge {
+    kbd: board::KeyboardData,
+    args: Vec<u8>,
+    min: f32,
+    max: f32,
+    row: usize,
+    /// Last reading and when it was taken
+    sample: Option<(Option<f32>, u128)>,
+}
+
+impl TempGauge {
+    /// Share of the row to light (0.0-1.0) for a temperature
+    fn get_level(&self, temp: f32) -> f32 {
+        if self.max <= self.min {
+            return if temp >= self.max { 1.0 } else { 0.0 };
+        }
+        ((temp - self.min) / (self.max - self.min)).clamp(0.0, 1.0)
+    }
+}
+
+impl Effect for TempGauge {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(TempGauge {
+            kbd: board::KeyboardData::new(),
+            // args[0] and args[1] are the temperatures (Celsius) of an empty
+            // and a full gauge, args[2] the row to use (0 = function keys)
+            min: args[0] as f32,
+            max: args[1] as f32,
+            row: args.get(2).map_or(0, |r| (*r as usize).min(board::ROWS - 1)),
+            sample: None,
+            args,
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let now = get_millis();
+        if self.sample.is_none_or(|(_, at)| now - at >= TEMP_SAMPLE_MS) {
+            self.sample = Some((crate::get_cpu_temperature(crate::config::CpuTempMode::Max), now));
+        }
+        let level = match self.sample {
+            Some((Some(temp), _)) => self.get_level(temp),
+            _ => 0.0,
+        };
+        let lit = (level * board::KEYS_PER_ROW as f32).round() as usize;
+        for col in 0..board::KEYS_PER_ROW {
+            if col < lit {
+                let t = col as f32 / (board::KEYS_PER_ROW - 1) as f32;
+                let red = (255.0 * t) as u8;
+                self.kbd.set_key_colour(self.row, col, red, 0, 255 - red);
+            } else {
+                self.kbd.set_key_colour(self.row, col, 0, 0, 0);
+            }
+        }
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Temp Gauge"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 2,
+            max_len: Some(3),
+            fields: &["min_celsius", "max_celsius", "row"],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Temp Gauge"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+///
+/// BATTERY GAUGE KEYBOARD EFFECT
+/// The number row filling up with the battery percentage, green while
+/// charging and amber when running low
+///
+
+const BATTERY_GAUGE_ROW: usize = 1;
+const BATTERY_CHARGING_COLOUR: [u8; 3] = [0, 255, 0];
+const BATTERY_LOW_COLOUR: [u8; 3] = [255, 128, 0];
+const BATTERY_LOW_DEFAULT: u8 = 20;
+
+#[derive(Clone)]
+pub struct BatteryGauge {
+    kbd: board::KeyboardData,
+    args: Vec<u8>,
+    full: board::AnimatorKeyColour,
+    empty: board::AnimatorKeyColour,
+    low: f64,
+}
+
+impl Effect for BatteryGauge {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        Box::new(BatteryGauge {
+            kbd: board::KeyboardData::new(),
+            full: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
+            empty: board::AnimatorKeyColour::new_u(args[3], args[4], args[5]),
+            // args[6] is the percentage below which the gauge turns amber
+            low: args.get(6).copied().unwrap_or(BATTERY_LOW_DEFAULT) as f64,
+            args,
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let (percentage, charging) = crate::get_battery_level();
+        let level = (percentage / 100.0).clamp(0.0, 1.0) as f32;
+        let [r, g, b] = if charging {
+            BATTERY_CHARGING_COLOUR
+        } else if percentage < self.low {
+            BATTERY_LOW_COLOUR
+        } else {
+            let (empty, full) = (self.empty, self.full);
+            let c = board::AnimatorKeyColour::new_f(
+                empty.red + (full.red - empty.red) * level,
+                empty.green + (full.green - empty.green) * level,
+                empty.blue + (full.blue - empty.blue) * level,
+            ).get_clamped_colour();
+            [c.red, c.green, c.blue]
+        };
+        let lit = (level * board::KEYS_PER_ROW as f32).ceil() as usize;
+        for col in 0..board::KEYS_PER_ROW {
+            if col < lit {
+                self.kbd.set_key_colour(BATTERY_GAUGE_ROW, col, r, g, b);
+            } else {
+                self.kbd.set_key_colour(BATTERY_GAUGE_ROW, col, 0, 0, 0);
+            }
+        }
+        self.kbd
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Battery Gauge"
+    }
+
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized,
+    {
+        ParamSpec {
+            min_len: 6,
+            max_len: Some(7),
+            fields: &["full_red", "full_green", "full_blue", "empty_red", "empty_green", "empty_blue", "low_percent"],
+        }
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        &self.args
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        Box::new(self.clone())
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Battery Gauge"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED_TO_BLUE: [u8; 6] = [255, 0, 0, 0, 0, 255];
+
+    fn wave_args(direction: u8, speed: u8) -> Vec<u8> {
+        let mut args = RED_TO_BLUE.to_vec();
+        args.extend([direction, speed]);
+        args
+    }
+
+    /// The colours of the first row of a frame
+    fn first_row(kbd: board::KeyboardData) -> Vec<(u8, u8, u8)> {
+        (0..board::KEYS_PER_ROW)
+            .map(|col| kbd.get_key_at(col))
+            .map(|c| (c.red, c.green, c.blue))
+            .collect()
+    }
+
+    #[test]
+    fn wave_params_with_six_args() {
+        let params = WaveParams::parse(&RED_TO_BLUE).unwrap();
+        assert_eq!(params.stops, [[255, 0, 0], [0, 0, 255]]);
+        assert_eq!(params.direction, WaveDirection::Right);
+        assert_eq!(params.speed, WaveParams::MAX_SPEED);
+        // The defaults are stored with the effect
+        assert_eq!(WaveGradient::new(RED_TO_BLUE.to_vec()).get_varargs(), &[255, 0, 0, 0, 0, 255, 0, 10]);
+    }
+
+    #[test]
+    fn wave_params_with_eight_args() {
+        let params = WaveParams::parse(&wave_args(1, 3)).unwrap();
+        assert_eq!(params.direction, WaveDirection::Left);
+        assert_eq!(params.speed, 3);
+        assert_eq!(WaveParams::parse(&wave_args(0, 1)).unwrap().direction, WaveDirection::Right);
+    }
+
+    #[test]
+    fn wave_params_are_validated() {
+        let err = WaveParams::parse(&wave_args(2, 5)).unwrap_err();
+        assert!(err.contains("direction 2"), "{}", err);
+        for speed in [0, 11] {
+            let err = WaveParams::parse(&wave_args(0, speed)).unwrap_err();
+            assert!(err.contains(&format!("speed {}", speed)), "{}", err);
+        }
+        assert!(WaveParams::parse(&wave_args(0, 10)).is_ok());
+        assert!(WaveParams::parse(&RED_TO_BLUE[..5]).is_err());
+        assert!(WaveParams::parse(&wave_args(0, 5)[..7]).is_err());
+    }
+
+    #[test]
+    fn wave_moves_in_its_direction() {
+        for (direction, shift) in [(0, 1), (1, -1)] {
+            let mut wave = WaveGradient::new(wave_args(direction, 10));
+            let first = first_row(wave.update());
+            // Full speed moves the band a column every frame
+            wave.update();
+            let third = first_row(wave.update());
+            for (col, colour) in first.iter().enumerate().take(board::KEYS_PER_ROW - 2).skip(2) {
+                let moved = (col as i32 + 2 * shift) as usize;
+                assert_eq!(third[moved], *colour, "direction {} column {}", direction, col);
+            }
+        }
+    }
+
+    #[test]
+    fn slow_wave_moves_less_often() {
+        let mut wave = WaveGradient::new(wave_args(0, 5));
+        let frames: Vec<_> = (0..5).map(|_| first_row(wave.update())).collect();
+        // Speed 5 moves a column every second frame
+        assert_eq!(frames[1], frames[0]);
+        assert_ne!(frames[2], frames[0]);
+        assert_eq!(frames[3], frames[2]);
+        assert_eq!(frames[2][1], frames[0][0]);
+        assert_eq!(frames[4][2], frames[0][0]);
+    }
+
+    const PROC_STAT: &str = "\
+cpu  10132153 290696 3084719 46828483 16683 0 25195 0 175628 0
+cpu0 1393280 32966 572056 13343292 6130 0 17875 0 23933 0
+cpu1 1335860 36488 409876 10553064 2104 0 3066 0 23002 0
+intr 199292172 9 0 0 0 0 0 0 0 1 0 0 0
+ctxt 1990473
+btime 1062191376
+processes 2915
+";
+
+    #[test]
+    fn parses_proc_stat() {
+        // idle + iowait, and the sum of every column
+        assert_eq!(parse_cpu_times(PROC_STAT), Some((46828483 + 16683, 60553557)));
+        // Kernels before 2.6 only have four columns
+        assert_eq!(parse_cpu_times("cpu  10 20 30 40\n"), Some((40, 100)));
+    }
+
+    #[test]
+    fn rejects_short_or_broken_cpu_lines() {
+        assert_eq!(parse_cpu_times("cpu  10 20 30\n"), None);
+        assert_eq!(parse_cpu_times("cpu  10 20 x 40\n"), None);
+        assert_eq!(parse_cpu_times("cpu0 10 20 30 40\n"), None);
+        assert_eq!(parse_cpu_times(""), None);
+    }
+
+    #[test]
+    fn cpu_load_between_samples() {
+        assert_eq!(get_cpu_load((100, 200), (150, 300)), 0.5);
+        assert_eq!(get_cpu_load((100, 200), (100, 300)), 1.0);
+        assert_eq!(get_cpu_load((100, 200), (200, 300)), 0.0);
+    }
+
+    #[test]
+    fn cpu_load_without_progress() {
+        // No time passed, or the counters went back (wrapped or reset)
+        assert_eq!(get_cpu_load((100, 200), (100, 200)), 0.0);
+        assert_eq!(get_cpu_load((100, 200), (10, 20)), 0.0);
+        assert_eq!(get_cpu_load((u64::MAX - 5, u64::MAX), (3, 10)), 0.0);
+        // Idle going back alone counts as fully busy, idle ahead of total as idle
+        assert_eq!(get_cpu_load((100, 200), (50, 300)), 1.0);
+        assert_eq!(get_cpu_load((100, 200), (400, 300)), 0.0);
+    }
+
+    /// A spectrum cycle taking `period_s` seconds, 15 s moves the colours a column per second
+    fn spectrum(period_s: u8, reverse: bool) -> SpectrumCycle {
+        SpectrumCycle {
+            kbd: board::KeyboardData::new(),
+            args: [period_s, reverse as u8],
+            period_ms: period_s as u128 * 1000,
+            reverse,
+            start_ms: 0,
+        }
+    }
+
+    fn assert_hue(actual: f32, expected: f32) {
+        let diff = (actual - expected + 180.0).rem_euclid(360.0) - 180.0;
+        assert!(diff.abs() < 0.01, "hue {} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn spectrum_spreads_over_the_row() {
+        for reverse in [false, true] {
+            let cycle = spectrum(15, reverse);
+            assert_hue(cycle.get_hue(0, 0), 0.0);
+            assert_hue(cycle.get_hue(1, 0), 24.0);
+            assert_hue(cycle.get_hue(5, 0), 120.0);
+            assert_hue(cycle.get_hue(14, 0), 336.0);
+        }
+    }
+
+    #[test]
+    fn spectrum_phase() {
+        let forward = spectrum(15, false);
+        assert_hue(forward.get_hue(0, 5000), -120.0);
+        assert_hue(forward.get_hue(5, 2500), 60.0);
+        let reverse = spectrum(15, true);
+        assert_hue(reverse.get_hue(0, 5000), 120.0);
+        assert_hue(reverse.get_hue(5, 2500), 180.0);
+        for cycle in [forward, reverse] {
+            // A full period comes back to the start
+            assert_hue(cycle.get_hue(3, 15000), cycle.get_hue(3, 0));
+            assert_hue(cycle.get_hue(3, 37500), cycle.get_hue(3, 7500));
+        }
+    }
+
+    #[test]
+    fn spectrum_direction() {
+        // Forward the colours move right, reversed they move left, a column per second
+        let forward = spectrum(15, false);
+        let reverse = spectrum(15, true);
+        for col in 1..board::KEYS_PER_ROW - 1 {
+            assert_hue(forward.get_hue(col + 1, 4000), forward.get_hue(col, 3000));
+            assert_hue(reverse.get_hue(col - 1, 4000), reverse.get_hue(col, 3000));
+        }
+    }
+}
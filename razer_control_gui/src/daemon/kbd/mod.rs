@@ -1,14 +1,22 @@
 pub mod board;
+pub mod colour;
 pub mod effects;
 use crate::device;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::VecDeque;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-const ANIMATION_FPS: u64 = 10; // 33 ms ~= 30fps
+pub const ANIMATION_FPS: u32 = 10; // 33 ms ~= 30fps
+
+/// Fastest rate the animator can be set to, keeps it from busy-looping
+pub const MAX_ANIMATION_FPS: u32 = 60;
 
 pub const ANIMATION_SLEEP_MS: u64 = (1000.0 / ANIMATION_FPS as f32) as u64;
 
+/// Number of past frames kept to answer frame diff requests
+const FRAME_HISTORY: usize = 32;
+
 pub fn get_millis() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -22,6 +30,22 @@ pub struct EffectSave {
     name: String,
 }
 
+/// Describes the parameters an effect expects in its args vector
+pub struct ParamSpec {
+    pub min_len: usize,
+    /// None if trailing parameters (e.g. text) are unbounded
+    pub max_len: Option<usize>,
+    /// Name of each parameter, in order
+    pub fields: &'static [&'static str],
+}
+
+impl ParamSpec {
+    /// Returns whether an args vector of the given length is accepted
+    pub fn accepts(&self, len: usize) -> bool {
+        len >= self.min_len && self.max_len.map(|max| len <= max).unwrap_or(true)
+    }
+}
+
 /// Base effect trait.
 /// An effect is a lighting function that is updated 30 times per second
 /// in order to create an animation of some description on the laptop's
@@ -32,14 +56,25 @@ pub trait Effect: Send + Sync {
     where
         Self: Sized;
     /// Updates the keyboard, returning the current state of the keyboard
-    /// Called 30 times per second by the Effect Manager
+    /// Called every animation frame by the Effect Manager
     fn update(&mut self) -> board::KeyboardData;
+    /// Whether the effect changes over time. While only static effects are
+    /// active the animator waits for a change instead of rendering frames
+    fn is_animated(&self) -> bool {
+        true
+    }
     /// Returns the arguments used to spawn the effect
     fn get_varargs(&mut self) -> &[u8];
     /// Returns the name of the effect (Unique identifier)
     fn get_name() -> &'static str
     where
         Self: Sized;
+    /// Returns the layout of the arguments expected by `new`
+    fn param_spec() -> ParamSpec
+    where
+        Self: Sized;
+    /// Called with the matrix index of every key pressed while the effect runs
+    fn key_pressed(&mut self, _index: usize) {}
     fn clone_box(&self) -> Box<dyn Effect>;
     fn save(&mut self) -> EffectSave;
     fn get_state(&mut self) -> Vec<u8>;
@@ -52,16 +87,22 @@ struct EffectLayer {
     /// Mask for keys
     key_mask: Vec<bool>,
     effect: Box<dyn Effect>,
+    /// Keep full brightness when the global brightness is lowered
+    ignore_brightness: bool,
+    /// How much of the layers below shows through, 1.0 covers them fully
+    opacity: f32,
 }
 
 unsafe impl Send for EffectLayer {}
 unsafe impl Sync for EffectLayer {}
 
 impl EffectLayer {
-    fn new(effect: Box<dyn Effect>, mask: [bool; 90]) -> EffectLayer {
+    fn new(effect: Box<dyn Effect>, mask: [bool; 90], opacity: f32) -> EffectLayer {
         return EffectLayer {
             key_mask: mask.to_vec(),
             effect,
+            ignore_brightness: false,
+            opacity: opacity.clamp(0.0, 1.0),
         };
     }
 
@@ -73,9 +114,10 @@ impl EffectLayer {
         match serde_json::to_value(self.effect.save()) {
             Ok(mut x) => {
                 let keys = serde_json::to_value(&self.key_mask).unwrap();
-                x.as_object_mut()
-                    .unwrap()
-                    .insert(String::from("key_mask"), keys);
+                let obj = x.as_object_mut().unwrap();
+                obj.insert(String::from("key_mask"), keys);
+                obj.insert(String::from("ignore_brightness"), json!(self.ignore_brightness));
+                obj.insert(String::from("opacity"), json!(self.opacity));
                 Some(x)
             }
             Err(_) => None,
@@ -87,7 +129,13 @@ impl EffectLayer {
             eprintln!("Missing data for effect!");
             return None;
         }
-        let key_mask: Vec<bool> = serde_json::from_value(json["key_mask"].clone()).unwrap();
+        let key_mask: Vec<bool> = match serde_json::from_value(json["key_mask"].clone()) {
+            Ok(mask) => mask,
+            Err(e) => {
+                eprintln!("Invalid key mask for effect: {}", e);
+                return None;
+            }
+        };
         if key_mask.len() != 90 {
             eprintln!(
                 "Invalid key count effect. Expected 90, found {}",
@@ -95,26 +143,52 @@ impl EffectLayer {
             );
             return None;
         }
-        let name: String = serde_json::from_value(json["name"].clone()).unwrap();
-        let args: Vec<u8> = serde_json::from_value(json["args"].clone()).unwrap();
-
-        let effect: Option<Box<dyn Effect>> = match name.as_str() {
-            "Static" => Some(effects::Static::new(args)),
-            "Wave Gradient" => Some(effects::WaveGradient::new(args)),
-            "Breathing Single" => Some(effects::BreathSingle::new(args)),
-            "Static Gradient" => Some(effects::StaticGradient::new(args)),
-            _ => None,
+        let name: String = serde_json::from_value(json["name"].clone()).unwrap_or_default();
+        let args: Vec<u8> = match serde_json::from_value(json["args"].clone()) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("Invalid args for effect {}: {}", name, e);
+                return None;
+            }
         };
-        if effect.is_none() {
-            eprintln!("Effect failed to load. Invalid name: {}", name);
-            return None;
-        }
+
+        let effect = match name.as_str() {
+            "Static" => Self::load_effect::<effects::Static>(args),
+            "Wave Gradient" => Self::load_effect::<effects::WaveGradient>(args),
+            "Breathing Single" => Self::load_effect::<effects::BreathSingle>(args),
+            "Static Gradient" => Self::load_effect::<effects::StaticGradient>(args),
+            "Split" => Self::load_effect::<effects::Split>(args),
+            "Scroll Text" => Self::load_effect::<effects::ScrollText>(args),
+            "Scripted" => Self::load_effect::<effects::Scripted>(args),
+            "Spectrum Cycle" => Self::load_effect::<effects::SpectrumCycle>(args),
+            "Ripple" => Self::load_effect::<effects::Ripple>(args),
+            "Custom Matrix" => Self::load_effect::<effects::CustomMatrix>(args),
+            "Load Meter" => Self::load_effect::<effects::LoadMeter>(args),
+            "Temp Gauge" => Self::load_effect::<effects::TempGauge>(args),
+            "Battery Gauge" => Self::load_effect::<effects::BatteryGauge>(args),
+            _ => {
+                eprintln!("Effect failed to load. Invalid name: {}", name);
+                None
+            }
+        }?;
         return Some(EffectLayer {
             key_mask,
-            effect: effect.unwrap(),
+            effect,
+            ignore_brightness: json["ignore_brightness"].as_bool().unwrap_or(false),
+            opacity: json["opacity"].as_f64().map_or(1.0, |o| (o as f32).clamp(0.0, 1.0)),
         });
     }
 
+    /// Creates a saved effect. A stale or hand-edited save can have args
+    /// that don't fit the effect, which `new` would panic on
+    fn load_effect<E: Effect>(args: Vec<u8>) -> Option<Box<dyn Effect>> {
+        if !E::param_spec().accepts(args.len()) {
+            eprintln!("Effect {} failed to load. Invalid number of args: {}", E::get_name(), args.len());
+            return None;
+        }
+        Some(E::new(args))
+    }
+
     pub fn get_state(&mut self) -> Vec<u8> {
         self.effect.get_state()
     }
@@ -127,6 +201,37 @@ pub struct EffectManager {
     layers: Vec<EffectLayer>,
     last_update_ms: u128,
     render_board: board::KeyboardData,
+    /// Name of the last effect profile that was loaded
+    active_profile: Option<String>,
+    /// Layer drawn above all others, kept out of saves (e.g. power mode colour)
+    indicator: Option<EffectLayer>,
+    /// Increased every time the rendered frame changes
+    frame_seq: u64,
+    /// Recently rendered frames with their sequence number, oldest first
+    frame_history: VecDeque<(u64, Vec<u8>)>,
+    /// Crossfade from a replaced effect, see `replace_effect`
+    transition: Option<Transition>,
+    /// Frames per second the animator renders at
+    fps: u32,
+    /// Last frame written to the keyboard, None forces the next one to be written
+    written_frame: Option<Vec<u8>>,
+    /// A standard (firmware) effect is showing, no frames are written until
+    /// a layer is added
+    standard_effect: bool,
+}
+
+/// Last frame of a replaced effect, faded out over the new one
+struct Transition {
+    from: board::KeyboardData,
+    start_ms: u128,
+    duration_ms: u128,
+}
+
+impl Transition {
+    /// How far the transition is at `now_ms`, from 0.0 to 1.0
+    fn progress(&self, now_ms: u128) -> f32 {
+        (now_ms.saturating_sub(self.start_ms) as f32 / self.duration_ms as f32).min(1.0)
+    }
 }
 
 unsafe impl Send for EffectManager {}
@@ -138,40 +243,234 @@ impl EffectManager {
             layers: vec![],
             last_update_ms: get_millis(),
             render_board: board::KeyboardData::new(),
+            active_profile: None,
+            indicator: None,
+            frame_seq: 0,
+            frame_history: VecDeque::with_capacity(FRAME_HISTORY),
+            transition: None,
+            fps: ANIMATION_FPS,
+            written_frame: None,
+            standard_effect: false,
+        }
+    }
+
+    /// Whether any layer changes over time, or a crossfade is running
+    pub fn is_animated(&self) -> bool {
+        !self.standard_effect
+            && (self.transition.is_some()
+                || self.layers.iter().chain(self.indicator.iter()).any(|l| l.effect.is_animated()))
+    }
+
+    /// Makes the next update write its frame even if it looks the same as
+    /// the last one, e.g. after the keyboard lost its state
+    pub fn invalidate(&mut self) {
+        self.written_frame = None;
+    }
+
+    pub fn get_fps(&self) -> u32 {
+        self.fps
+    }
+
+    /// Sets the animation rate, clamped to 1 - MAX_ANIMATION_FPS. Returns the
+    /// rate that was set
+    pub fn set_fps(&mut self, fps: u32) -> u32 {
+        self.fps = fps.clamp(1, MAX_ANIMATION_FPS);
+        self.fps
+    }
+
+    /// Time between two frames at the current rate
+    pub fn get_sleep_ms(&self) -> u64 {
+        1000 / self.fps as u64
+    }
+
+    /// Replaces the top layer with a full keyboard effect. With a fade
+    /// duration the old frame fades into the new effect over that many ms,
+    /// otherwise the switch is instant
+    pub fn replace_effect(&mut self, laptop: &mut device::RazerLaptop, effect: Box<dyn Effect>, fade_ms: u32) {
+        self.transition = match (fade_ms, self.layers.is_empty()) {
+            (0, _) | (_, true) => None,
+            _ => Some(Transition {
+                from: self.render_board,
+                start_ms: get_millis(),
+                duration_ms: fade_ms as u128,
+            }),
+        };
+        self.layers.pop();
+        if self.transition.is_none() {
+            self.blank_if_empty(laptop);
         }
+        self.push_effect(effect, [true; 90], 1.0);
     }
 
-    pub fn push_effect(&mut self, effect: Box<dyn Effect>, mask: [bool; 90]) {
-        self.layers.push(EffectLayer::new(effect, mask))
+    pub fn push_effect(&mut self, effect: Box<dyn Effect>, mask: [bool; 90], opacity: f32) {
+        self.invalidate();
+        self.standard_effect = false;
+        self.layers.push(EffectLayer::new(effect, mask, opacity))
     }
 
     pub fn pop_effect(&mut self, laptop: &mut device::RazerLaptop) {
+        self.invalidate();
         self.layers.pop();
-        // If no more layers, erase keyboard rendering and set it to black
-        if self.layers.is_empty() {
+        self.blank_if_empty(laptop);
+    }
+
+    /// Removes the layer at the given index, false if there is no such layer
+    pub fn remove_effect(&mut self, laptop: &mut device::RazerLaptop, index: usize) -> bool {
+        if index >= self.layers.len() {
+            return false;
+        }
+        self.invalidate();
+        self.layers.remove(index);
+        self.blank_if_empty(laptop);
+        true
+    }
+
+    /// Removes every layer and blanks the keyboard before a standard effect
+    /// takes it over, see `show_standard_effect`
+    pub fn clear_effects(&mut self, laptop: &mut device::RazerLaptop) {
+        self.invalidate();
+        self.layers.clear();
+        self.transition = None;
+        self.active_profile = None;
+        self.blank_if_empty(laptop);
+        self.show_standard_effect();
+    }
+
+    /// Stops writing frames, including the indicator, so they don't cover the
+    /// standard effect. Adding a layer writes frames again
+    pub fn show_standard_effect(&mut self) {
+        self.standard_effect = true;
+    }
+
+    /// Returns the name of the effect and the number of keys it covers for
+    /// every layer, from the bottom of the stack to the top
+    pub fn get_stack(&mut self) -> Vec<(String, usize)> {
+        self.layers.iter_mut()
+            .map(|l| (l.effect.save().name, l.key_mask.iter().filter(|k| **k).count()))
+            .collect()
+    }
+
+    fn blank_if_empty(&mut self, laptop: &mut device::RazerLaptop) {
+        // If no more layers, erase keyboard rendering and set it to black,
+        // unless that would cover the standard effect
+        if self.layers.is_empty() && !self.standard_effect {
             self.render_board.set_kbd_colour(0, 0, 0); 
             self.render_board.update_kbd(laptop);
             self.render_board.update_custom_mode(laptop);
+            self.record_frame();
         }
     }
 
-    pub fn update(&mut self, laptop: &mut device::RazerLaptop) {
-        // Do nothing if we have no effects!
-        if self.layers.is_empty() {
-            return;
+    /// Sets or removes the indicator layer. The effect stack below it is left
+    /// untouched, so removing the indicator restores the previous effect
+    pub fn set_indicator(&mut self, laptop: &mut device::RazerLaptop, indicator: Option<(Box<dyn Effect>, [bool; 90])>) {
+        self.invalidate();
+        self.indicator = indicator.map(|(effect, mask)| EffectLayer::new(effect, mask, 1.0));
+        if self.indicator.is_none() && self.layers.is_empty() && !self.standard_effect {
+            self.render_board.set_kbd_colour(0, 0, 0);
+            self.render_board.update_kbd(laptop);
+            self.render_board.update_custom_mode(laptop);
+            self.record_frame();
         }
+    }
+
+    /// Forwards a key press to the effects reacting to the keyboard
+    pub fn key_pressed(&mut self, index: usize) {
         for layer in self.layers.iter_mut() {
+            layer.effect.key_pressed(index);
+        }
+    }
+
+    /// Renders the next frame. Returns false if it could not be written to the keyboard
+    pub fn update(&mut self, laptop: &mut device::RazerLaptop) -> bool {
+        // Do nothing if we have no effects, or the standard effect shows
+        if self.standard_effect || (self.layers.is_empty() && self.indicator.is_none()) {
+            return true;
+        }
+        // While a layer ignores the global brightness, the hardware stays at
+        // full brightness and the other layers are dimmed here instead
+        let exempt = self.layers.iter().chain(self.indicator.iter()).any(|l| l.ignore_brightness);
+        laptop.set_software_dimming(exempt);
+        let dim = laptop.get_software_dimming().map(|b| b as f32 / 255.0);
+        self.compose(dim);
+        // Don't forget to actually render the board, unless the keyboard
+        // already shows this frame
+        self.last_update_ms = get_millis();
+        let frame = self.render_board.get_curr_state();
+        if self.written_frame.as_ref() == Some(&frame) {
+            return true;
+        }
+        let written = self.render_board.update_kbd(laptop) && self.render_board.update_custom_mode(laptop);
+        self.written_frame = if written { Some(frame) } else { None };
+        self.record_frame();
+        written
+    }
+
+    /// Composites the layers into the render board, dimmed by `dim` (0.0 - 1.0)
+    /// if set
+    ///
+    /// Layers are composited from the bottom of the stack to the top, with the
+    /// indicator last. Each layer only writes the keys set in its mask, so the
+    /// layers below show through the rest, and is blended over them by its
+    /// opacity. Keys no layer covers are black
+    fn compose(&mut self, dim: Option<f32>) {
+        self.render_board.set_kbd_colour(0, 0, 0);
+        for layer in self.layers.iter_mut().chain(self.indicator.iter_mut()) {
             let tmp_board = layer.update();
             for (pos, state) in layer.key_mask.iter().enumerate() {
                 if *state {
-                    self.render_board.set_key_at(pos, tmp_board.get_key_at(pos))
+                    let mut colour = tmp_board.get_key_at(pos);
+                    if let (Some(dim), false) = (dim, layer.ignore_brightness) {
+                        colour = colour.scaled(dim);
+                    }
+                    let below = self.render_board.get_key_at(pos);
+                    self.render_board.set_key_at(pos, colour.blended_over(below, layer.opacity))
+                }
+            }
+        }
+        if let Some(transition) = &self.transition {
+            let progress = transition.progress(get_millis());
+            if progress >= 1.0 {
+                self.transition = None;
+            } else {
+                for pos in 0..90 {
+                    let colour = self.render_board.get_key_at(pos);
+                    self.render_board.set_key_at(pos, colour.blended_over(transition.from.get_key_at(pos), progress));
                 }
             }
         }
-        // Don't forget to actually render the board
-        self.last_update_ms = get_millis();
-        self.render_board.update_kbd(laptop);
-        self.render_board.update_custom_mode(laptop);
+    }
+
+    /// Stores the rendered frame if it differs from the last one
+    fn record_frame(&mut self) {
+        let frame = self.render_board.get_curr_state();
+        if let Some((_, last)) = self.frame_history.back() {
+            if *last == frame {
+                return;
+            }
+        }
+        self.frame_seq += 1;
+        if self.frame_history.len() == FRAME_HISTORY {
+            self.frame_history.pop_front();
+        }
+        self.frame_history.push_back((self.frame_seq, frame));
+    }
+
+    /// Returns the current frame sequence number, whether the diff is a full
+    /// frame, and the keys (index and RGB) that changed since `since_seq`.
+    /// A full frame is returned when `since_seq` is no longer in the history
+    pub fn get_frame_diff(&mut self, since_seq: u64) -> (u64, bool, Vec<(u8, [u8; 3])>) {
+        let current = self.render_board.get_curr_state();
+        let old = self.frame_history.iter()
+            .find(|(seq, _)| *seq == since_seq)
+            .map(|(_, frame)| frame);
+        let keys = current.chunks(3)
+            .enumerate()
+            .filter(|(i, rgb)| !matches!(old, Some(old) if old[i * 3..i * 3 + 3] == **rgb))
+            .map(|(i, rgb)| (i as u8, [rgb[0], rgb[1], rgb[2]]))
+            .collect();
+
+        (self.frame_seq, old.is_none(), keys)
     }
 
     pub fn save(&mut self) -> serde_json::value::Value {
@@ -190,20 +489,106 @@ impl EffectManager {
         return save_json;
     }
 
-    pub fn load_from_save(&mut self, mut json: serde_json::Value) {
-        if json["effects"].is_null() {
-            eprintln!("Invalid json. No effects field!");
-            return;
-        }
-        for e in json["effects"].as_array_mut().unwrap() {
+    /// Adds the layers of a save. Layers that can't be loaded are skipped,
+    /// returns false if there were any
+    pub fn load_from_save(&mut self, json: serde_json::Value) -> bool {
+        let saved = match json["effects"].as_array() {
+            Some(saved) => saved,
+            None => {
+                eprintln!("Invalid json. No effects field!");
+                return false;
+            }
+        };
+        self.invalidate();
+        let mut result = true;
+        for e in saved {
             if let Some(x) = EffectLayer::from_save(e.clone()) {
+                self.standard_effect = false;
                 self.layers.push(x);
             } else {
                 eprintln!("Error adding effect");
+                result = false;
+            }
+        }
+        result
+    }
+
+    /// Replaces the whole effect stack with a saved profile. Returns false
+    /// if some of its layers couldn't be loaded
+    pub fn load_profile(&mut self, laptop: &mut device::RazerLaptop, name: &str, json: serde_json::Value) -> bool {
+        self.layers.clear();
+        self.transition = None;
+        let result = self.load_from_save(json);
+        if self.layers.is_empty() {
+            self.render_board.set_kbd_colour(0, 0, 0);
+            self.render_board.update_kbd(laptop);
+            self.render_board.update_custom_mode(laptop);
+            self.record_frame();
+        }
+        self.active_profile = Some(name.to_string());
+        result
+    }
+
+    /// Replaces the effect of a layer, keeping its mask. An index one past
+    /// the top layer adds a new full keyboard layer
+    pub fn set_layer_effect(&mut self, index: usize, effect: Box<dyn Effect>) -> bool {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.effect = effect;
+            self.invalidate();
+            true
+        } else if index == self.layers.len() {
+            self.push_effect(effect, [true; 90], 1.0);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Sets how opaque a layer is, from 0.0 (invisible) to 1.0
+    pub fn set_layer_opacity(&mut self, index: usize, opacity: f32) -> bool {
+        match self.layers.get_mut(index) {
+            Some(layer) => {
+                layer.opacity = opacity.clamp(0.0, 1.0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets whether a layer keeps full brightness when the global brightness is lowered
+    pub fn set_layer_ignore_brightness(&mut self, index: usize, ignore: bool) -> bool {
+        match self.layers.get_mut(index) {
+            Some(layer) => {
+                layer.ignore_brightness = ignore;
+                true
             }
+            None => false,
         }
     }
 
+    /// Marks a profile as active, e.g. after the current stack was saved to it
+    pub fn set_active_profile(&mut self, name: &str) {
+        self.active_profile = Some(name.to_string());
+    }
+
+    pub fn get_active_profile(&self) -> Option<&str> {
+        self.active_profile.as_deref()
+    }
+
+    /// Returns the saved name and params of a layer's effect, the top layer
+    /// for a negative id
+    pub fn get_effect(&mut self, layer_id: i32) -> Option<(String, Vec<u8>)> {
+        let layer = if layer_id < 0 {
+            self.layers.last_mut()
+        } else {
+            self.layers.get_mut(layer_id as usize)
+        };
+        layer.map(|l| {
+            let save = l.effect.save();
+            (save.name, save.args)
+        })
+    }
+
     pub fn get_map(&mut self, layer_id: i32) -> Vec<u8> {
         if layer_id < 0 {
             // Requesting global layer
@@ -213,3 +598,127 @@ impl EffectManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_layer_shows_over_full_layer() {
+        let mut manager = EffectManager::new();
+        manager.push_effect(effects::Static::new(vec![255, 0, 0]), [true; 90], 1.0);
+        let mut mask = [false; 90];
+        mask[..board::KEYS_PER_ROW].iter_mut().for_each(|k| *k = true);
+        manager.push_effect(effects::Static::new(vec![0, 255, 0]), mask, 1.0);
+        manager.compose(None);
+        for (index, rgb) in manager.get_map(-1).chunks(3).enumerate() {
+            let expected: &[u8] = if index < board::KEYS_PER_ROW { &[0, 255, 0] } else { &[255, 0, 0] };
+            assert_eq!(rgb, expected, "key {}", index);
+        }
+    }
+
+    #[test]
+    fn transition_progress() {
+        let transition = Transition { from: board::KeyboardData::new(), start_ms: 1000, duration_ms: 400 };
+        assert_eq!(transition.progress(1000), 0.0);
+        assert_eq!(transition.progress(1200), 0.5);
+        assert_eq!(transition.progress(1400), 1.0);
+        assert_eq!(transition.progress(5000), 1.0);
+        // A clock behind the start doesn't go negative
+        assert_eq!(transition.progress(900), 0.0);
+    }
+
+    #[test]
+    fn replace_effect_fades_from_the_last_frame() {
+        let (mut laptop, _) = device::testing::fake_laptop();
+        let mut manager = EffectManager::new();
+        manager.push_effect(effects::Static::new(vec![255, 0, 0]), [true; 90], 1.0);
+        manager.compose(None);
+        manager.replace_effect(&mut laptop, effects::Static::new(vec![0, 0, 255]), 300);
+        let transition = manager.transition.as_ref().expect("no transition");
+        assert_eq!(transition.duration_ms, 300);
+        let red = transition.from.get_key_at(0);
+        assert_eq!([red.red, red.green, red.blue], [255, 0, 0]);
+        assert_eq!(manager.layers.len(), 1);
+    }
+
+    #[test]
+    fn replace_effect_without_fade() {
+        let (mut laptop, _) = device::testing::fake_laptop();
+        let mut manager = EffectManager::new();
+        // Nothing to fade from
+        manager.replace_effect(&mut laptop, effects::Static::new(vec![255, 0, 0]), 300);
+        assert!(manager.transition.is_none());
+        // No fade duration
+        manager.replace_effect(&mut laptop, effects::Static::new(vec![0, 0, 255]), 0);
+        assert!(manager.transition.is_none());
+        assert_eq!(manager.layers.len(), 1);
+    }
+
+    fn saved_layer(opacity: serde_json::Value) -> serde_json::Value {
+        json!({"name": "Static", "args": [255, 0, 0], "key_mask": vec![true; 90], "opacity": opacity})
+    }
+
+    #[test]
+    fn saved_opacity_is_clamped() {
+        for (saved, expected) in [(json!(0.25), 0.25), (json!(-1.0), 0.0), (json!(3.5), 1.0), (json!(null), 1.0)] {
+            let layer = EffectLayer::from_save(saved_layer(saved.clone())).unwrap();
+            assert_eq!(layer.opacity, expected, "saved opacity {}", saved);
+        }
+    }
+
+    #[test]
+    fn saved_args_must_fit_the_effect() {
+        let names = ["Static", "Wave Gradient", "Breathing Single", "Static Gradient", "Split", "Scroll Text",
+            "Scripted", "Spectrum Cycle", "Ripple", "Custom Matrix", "Load Meter", "Temp Gauge", "Battery Gauge"];
+        // Every length is either rejected or loads without panicking
+        for name in names {
+            for len in 0..12 {
+                let saved = json!({"name": name, "args": vec![1u8; len], "key_mask": vec![true; 90]});
+                EffectLayer::from_save(saved);
+            }
+        }
+        let saved = json!({"name": "Split", "args": [1, 2, 3], "key_mask": vec![true; 90]});
+        assert!(EffectLayer::from_save(saved).is_none());
+        let saved = json!({"name": "Static", "args": "red", "key_mask": vec![true; 90]});
+        assert!(EffectLayer::from_save(saved).is_none());
+    }
+
+    #[test]
+    fn bad_layers_are_skipped() {
+        let mut manager = EffectManager::new();
+        let save = json!({"effects": [
+            {"name": "Split", "args": [1, 2, 3], "key_mask": vec![true; 90]},
+            saved_layer(json!(1.0)),
+        ]});
+        assert!(!manager.load_from_save(save));
+        assert_eq!(manager.layers.len(), 1);
+        assert!(manager.load_from_save(json!({"effects": [saved_layer(json!(1.0))]})));
+        assert!(!manager.load_from_save(json!({"effects": 3})));
+    }
+
+    #[test]
+    fn static_stack_is_written_once() {
+        let (mut laptop, reports) = device::testing::fake_laptop();
+        let mut manager = EffectManager::new();
+        manager.push_effect(effects::Static::new(vec![255, 0, 0]), [true; 90], 1.0);
+        assert!(!manager.is_animated());
+        assert!(manager.update(&mut laptop));
+        let written = reports.lock().unwrap().len();
+        assert!(written > 0);
+        assert!(manager.update(&mut laptop));
+        assert!(manager.update(&mut laptop));
+        assert_eq!(reports.lock().unwrap().len(), written, "unchanged frames were written again");
+        manager.invalidate();
+        assert!(manager.update(&mut laptop));
+        assert_eq!(reports.lock().unwrap().len(), 2 * written);
+    }
+
+    #[test]
+    fn animated_layer_keeps_the_animator_running() {
+        let mut manager = EffectManager::new();
+        manager.push_effect(effects::Static::new(vec![255, 0, 0]), [true; 90], 1.0);
+        manager.push_effect(effects::SpectrumCycle::new(vec![5, 0]), [true; 90], 0.5);
+        assert!(manager.is_animated());
+    }
+}
@@ -0,0 +1,87 @@
+use std::{env, fs, process};
+use std::collections::HashMap;
+
+use log::*;
+
+const KDE_APPLETS_FILE: &str = "/.config/plasma-org.kde.plasma.desktop-appletsrc";
+
+/// Tries to find the current desktop wallpaper, first from GNOME and then
+/// from the KDE Plasma desktop settings
+pub fn get_wallpaper_path() -> Option<String> {
+    get_gnome_wallpaper().or_else(get_kde_wallpaper)
+}
+
+fn get_gnome_wallpaper() -> Option<String> {
+    let output = process::Command::new("gsettings")
+        .arg("get")
+        .arg("org.gnome.desktop.background")
+        .arg("picture-uri")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let uri = String::from_utf8_lossy(&output.stdout);
+    let path = uri_to_path(uri.trim().trim_matches('\''));
+    if path.is_empty() {
+        return None;
+    }
+    Some(path)
+}
+
+fn get_kde_wallpaper() -> Option<String> {
+    let home = env::var("HOME").ok()?;
+    let contents = fs::read_to_string(home + KDE_APPLETS_FILE).ok()?;
+    // The wallpaper plugin section contains a line like "Image=file:///path"
+    contents.lines()
+        .filter_map(|line| line.strip_prefix("Image="))
+        .map(uri_to_path)
+        .find(|path| !path.is_empty())
+}
+
+fn uri_to_path(uri: &str) -> String {
+    uri.strip_prefix("file://").unwrap_or(uri).to_string()
+}
+
+/// Computes the dominant colour of an image.
+///
+/// The image is scaled down and its pixels grouped into coarse colour
+/// buckets. The average colour of the most populated bucket is returned.
+/// Very dark and very bright pixels are skipped, as they usually belong to
+/// shadows and highlights rather than the theme of the picture
+pub fn dominant_colour(path: &str) -> Option<[u8; 3]> {
+    let img = match image::open(path) {
+        Ok(img) => img.thumbnail(64, 64).to_rgb8(),
+        Err(e) => {
+            error!("Could not load wallpaper {}: {}", path, e);
+            return None;
+        }
+    };
+
+    let mut buckets: HashMap<(u8, u8, u8), (u32, [u32; 3])> = HashMap::new();
+    for px in img.pixels() {
+        let [r, g, b] = px.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        if max < 24 || min > 232 {
+            continue;
+        }
+        let entry = buckets.entry((r >> 5, g >> 5, b >> 5)).or_insert((0, [0; 3]));
+        entry.0 += 1;
+        entry.1[0] += r as u32;
+        entry.1[1] += g as u32;
+        entry.1[2] += b as u32;
+    }
+
+    let (count, sum) = buckets.values().max_by_key(|(count, _)| *count)?;
+    Some([
+        (sum[0] / count) as u8,
+        (sum[1] / count) as u8,
+        (sum[2] / count) as u8,
+    ])
+}
+
+/// Returns the modification time of a file, used to notice wallpaper changes
+pub fn get_modified(path: &str) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
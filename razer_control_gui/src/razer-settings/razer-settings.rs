@@ -24,7 +24,13 @@ use util::*;
 
 fn send_data(opt: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
     match comms::try_bind() {
-        Ok(socket) => comms::send_to_daemon(opt, socket),
+        Ok(socket) => match comms::send_to_daemon(opt, socket) {
+            Some(comms::DaemonResponse::Error { code, message }) => {
+                println!("Daemon error ({code:?}): {message}");
+                None
+            }
+            response => response,
+        },
         Err(error) if error.kind() == ErrorKind::NotFound => {
             crash_with_msg("Can't connect to the daemon");
         }
@@ -67,6 +73,22 @@ fn get_bho() -> Option<(bool, u8)> {
     }
 }
 
+fn get_active_effect() -> Option<comms::EffectDescriptor> {
+    let response = send_data(comms::DaemonCommand::GetKeyboardRGB { layer: -1 })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetKeyboardRGB { active_effect, .. } => {
+            active_effect
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetKeyboardRGB got {response:?}");
+            None
+        }
+    }
+}
+
 fn set_bho(is_on: bool, threshold: u8) -> Option<bool> {
     let response = send_data(comms::DaemonCommand::SetBatteryHealthOptimizer {
         is_on, threshold
@@ -108,7 +130,10 @@ fn set_brightness(ac: bool, val: u8) -> Option<bool> {
 
     use comms::DaemonResponse::*;
     match response {
-        SetBrightness { result } => {
+        SetBrightness { result, error } => {
+            if let Some(error) = error {
+                println!("Could not set brightness: {error}");
+            }
             Some(result)
         }
         response => {
@@ -160,7 +185,10 @@ fn set_effect(name: &str, values: Vec<u8>) -> Option<bool> {
 
     use comms::DaemonResponse::*;
     match response {
-        SetEffect { result } => {
+        SetEffect { result, error } => {
+            if let Some(error) = error {
+                println!("Could not set effect: {error}");
+            }
             Some(result)
         }
         response => {
@@ -225,7 +253,10 @@ fn set_power(ac: bool, power: (u8, u8, u8)) -> Option<bool> {
 
     use comms::DaemonResponse::*;
     match response {
-        SetPowerMode { result } => {
+        SetPowerMode { result, error } => {
+            if let Some(error) = error {
+                println!("Could not set power mode: {error}");
+            }
             Some(result)
         }
         response => {
@@ -253,13 +284,50 @@ fn get_fan_speed(ac: bool) -> Option<i32> {
     }
 }
 
+fn get_boost_range(ac: bool) -> Option<(comms::BoostRange, comms::BoostRange)> {
+    let ac = if ac { 1 } else { 0 };
+    let response = send_data(comms::DaemonCommand::GetBoostRange { ac })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetBoostRange { cpu, gpu } => {
+            Some((cpu, gpu))
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetBoostRange got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_fan_rpm_range(ac: bool) -> Option<(i32, i32)> {
+    let ac = if ac { 1 } else { 0 };
+    let response = send_data(comms::DaemonCommand::GetFanRpmRange { ac })?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetFanRpmRange { min, max, .. } => {
+            Some((min, max))
+        }
+        response => {
+            // This should not happen
+            println!("Instead of GetFanRpmRange got {response:?}");
+            None
+        }
+    }
+}
+
 fn set_fan_speed(ac: bool, value: i32) -> Option<bool> {
     let ac = if ac { 1 } else { 0 };
     let response = send_data(comms::DaemonCommand::SetFanSpeed{ ac, rpm: value })?;
 
     use comms::DaemonResponse::*;
     match response {
-        SetFanSpeed { result } => {
+        SetFanSpeed { result, error, .. } => {
+            if let Some(error) = error {
+                println!("Could not set fan speed: {error}");
+            }
             Some(result)
         }
         response => {
@@ -274,6 +342,10 @@ fn main() {
     setup_panic_hook();
     gtk::init().or_crash("Failed to initialize GTK.");
 
+    if let Err(e) = comms::check_protocol_version() {
+        crash_with_msg(e);
+    }
+
     let device_file = std::fs::read_to_string(service::DEVICE_FILE)
         .or_crash("Failed to read the device file");
     let devices: Vec<SupportedDevice> = serde_json::from_str(&device_file)
@@ -365,10 +437,15 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
     let brightness = get_brightness(ac).or_crash("Error reading brightness");
     let power = get_power(ac);
 
-    let min_fan_speed = *device.fan.get(0)
-        .or_crash("Invalid fan values") as f64;
-    let max_fan_speed = *device.fan.get(1)
-        .or_crash("Invalid fan values") as f64;
+    // Ask the daemon for the range of the detected laptop, older daemons
+    // don't know the command so fall back to the laptops file
+    let (min_fan_speed, max_fan_speed) = match get_fan_rpm_range(ac) {
+        Some((min, max)) => (min as f64, max as f64),
+        None => (
+            *device.fan.get(0).or_crash("Invalid fan values") as f64,
+            *device.fan.get(1).or_crash("Invalid fan values") as f64,
+        ),
+    };
 
     let settings_page = SettingsPage::new();
 
@@ -406,12 +483,19 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
                 power_profile.set_width_request(100);
         let row = SettingsRow::new(&label, &power_profile);
         settings_section.add_row(&row.master_container);
+        // The levels come from the daemon, older ones don't know the command
+        let (cpu_levels, gpu_levels) = match get_boost_range(ac) {
+            Some((cpu, gpu)) => (cpu.steps, gpu.steps),
+            None => {
+                let mut cpu = vec!["Low".to_string(), "Medium".to_string(), "High".to_string()];
+                let gpu = cpu.clone();
+                if device.can_boost() { cpu.push("Boost".to_string()) };
+                (cpu, gpu)
+            }
+        };
             let label = Label::new(Some("CPU Boost"));
             let cpu_boost = ComboBoxText::new();
-                cpu_boost.append_text("Low");
-                cpu_boost.append_text("Medium");
-                cpu_boost.append_text("High");
-                if device.can_boost() { cpu_boost.append_text("Boost") };
+                cpu_levels.iter().for_each(|l| cpu_boost.append_text(l));
                 cpu_boost.set_active(Some(power.1 as u32));
                 cpu_boost.set_width_request(100);
         let row = SettingsRow::new(&label, &cpu_boost);
@@ -419,9 +503,7 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
         settings_section.add_row(cpu_boost_row);
             let label = Label::new(Some("GPU Boost"));
             let gpu_boost = ComboBoxText::new();
-                gpu_boost.append_text("Low");
-                gpu_boost.append_text("Medium");
-                gpu_boost.append_text("High");
+                gpu_levels.iter().for_each(|l| gpu_boost.append_text(l));
                 gpu_boost.set_active(Some(power.2 as u32));
                 gpu_boost.set_width_request(100);
         let row = SettingsRow::new(&label, &gpu_boost);
@@ -562,7 +644,13 @@ fn make_general_page() -> SettingsPage {
             effect_options.append_text("Static Gradient");
             effect_options.append_text("Wave Gradient");
             effect_options.append_text("Breathing");
-            effect_options.set_active(Some(0));
+            // Select the effect the daemon runs, the options are in the same order
+            let active = get_active_effect().and_then(|effect| {
+                ["Static", "Static Gradient", "Wave Gradient", "Breathing Single"]
+                    .iter()
+                    .position(|name| *name == effect.name)
+            });
+            effect_options.set_active(Some(active.unwrap_or(0) as u32));
     let row = SettingsRow::new(&label, &effect_options);
     settings_section.add_row(&row.master_container);
         let label = Label::new(Some("Color 1"));